@@ -2,7 +2,14 @@ use rand::Rng;
 use raylib::prelude::*;
 use simulation::geometry::Geometry;
 use simulation::rect_geom::RectGeometry;
-use simulation::conway_sim::{draw_gol_rect, ConwaySimulation};
+use simulation::conway_sim::ConwaySimulation;
+use simulation::draw::{RaylibRenderer, RaylibStyle};
+use simulation::raster::Rgba;
+use simulation::render::Renderer;
+use simulation::history::History;
+use simulation::rng;
+use simulation::pattern::{self, Transform};
+use simulation::recorder::{Recorder, RecorderOptions};
 /*
  * Il piano della muerte e' finire tutto questo in 5 giorni.
  * L'obiettivo e' avere una prima mappa esagonale su cui far spannare la mia simulazione.
@@ -30,6 +37,7 @@ const YEND: f32 = YSTART + (YCELLS as f32) * YCELSIZE;
 
 const BASECAMERASPEED: f32 = 5.0;
 
+#[allow(clippy::manual_clamp)]
 fn my_camera_update(camera: &mut Camera2D, rl: &mut RaylibHandle) {
     let speed = BASECAMERASPEED / camera.zoom;
     let translation = Vector2::new(
@@ -53,6 +61,8 @@ fn my_camera_update(camera: &mut Camera2D, rl: &mut RaylibHandle) {
     else if camera.zoom < 0.04 { camera.zoom = 0.04; }
 }
 
+const RECORDING_PATH: &str = "conway.gif";
+
 fn main() {
     // Initialization
     //--------------------------------------------------------------------------------------
@@ -74,25 +84,58 @@ fn main() {
 
     // Init Simulation
     let geometry = RectGeometry::new(
-        Vector2::new(0.0, 0.0),
+        Vector2::new(0.0, 0.0).into(),
         300,
         200,
-        Vector2::new(100.0, 100.0)
+        Vector2::new(100.0, 100.0).into()
     );
     let mut sim = ConwaySimulation::new(&geometry, &[3], &[2,3]);
-    let mut rng = rand::rng();
-    for i in 0..geometry.size() {
-        if let Some(state) = sim.get_mut(i) {
-            state.val = if rng.random::<f32>() < 0.3 { 1 } else { 0 };
+    let seed = rng::seed_from_args();
+    println!("Seed: {}", seed);
+    let mut rng = rng::seeded(seed);
+    // Pattern given after the seed (catalogue name or .rle file) at the centre, or random soup
+    let pattern = pattern::pattern_from_args(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+    if let Some(p) = pattern {
+        let (w, h) = (geometry.cells.x, geometry.cells.y);
+        p.stamp(&mut sim, w.saturating_sub(p.width) / 2, h.saturating_sub(p.height) / 2, Transform::default());
+    } else {
+        for i in 0..geometry.size() {
+            if let Some(state) = sim.get_mut(i) {
+                state.val = if rng.random::<f32>() < 0.3 { 1 } else { 0 };
+            }
         }
     }
 
+    // History - hold Z to rewind
+    let mut history = History::new(50, 256 * 1024 * 1024);
+    history.record(&sim);
+
+    // Recorder - R to start, R again to write the time-lapse
+    let mut recorder = Recorder::new(RecorderOptions { every: 2, ..Default::default() });
+    let mut recording = false;
+
+    let style = RaylibStyle { grid: Some(Rgba::BLACK), frame: None, ..Default::default() };
     rl.set_target_fps(10);
 
     while !rl.window_should_close() {
         // Update
         //----------------------------------------------------------------------------------
         my_camera_update(&mut camera, &mut rl);
+        if rl.is_key_pressed(KeyboardKey::KEY_R) {
+            recording = !recording;
+            if recording {
+                recorder.start(sim.generation(), &sim);
+                println!("Recording...");
+            } else {
+                match recorder.save(RECORDING_PATH) {
+                    Ok(()) => println!("{} frames written to {}", recorder.len(), RECORDING_PATH),
+                    Err(e) => eprintln!("Cannot write {}: {}", RECORDING_PATH, e),
+                }
+            }
+        }
         // Draw
         //----------------------------------------------------------------------------------
         let mut d = rl.begin_drawing(&thread);
@@ -108,12 +151,20 @@ fn main() {
             // d2d.draw_rectangle_rec(Rectangle{x:0.0, y:100.0, width:5.0, height:5.0}, Color::BLUE);
             // END DRAW AXIS
             // draw_2d_map(&mut d2d);
-            draw_gol_rect(&mut d2d, &sim);
+            RaylibRenderer::new(&mut d2d, &style).render(&sim);
         }
 
         d.draw_fps(10, 10);
-        // Step?
-        sim.step();
+        // Step or rewind
+        if d.is_key_down(KeyboardKey::KEY_Z) {
+            history.rewind(&mut sim, 1);
+        } else {
+            sim.step();
+            history.record(&sim);
+            if recording {
+                recorder.capture(sim.generation(), &sim);
+            }
+        }
     }
     // De-Initialization
     //--------------------------------------------------------------------------------------
@@ -2,6 +2,10 @@ use rand::Rng;
 use raylib::prelude::*;
 use simulation::hex_conway_sim::HexConwaySimulation;
 use simulation::hex_geom::HexGeometry;
+use simulation::draw::{camera_view, RaylibRenderer, RaylibStyle};
+use simulation::history::History;
+use simulation::render::Renderer;
+use simulation::rng;
 
 struct CameraSettings{
     basespeed: f32,
@@ -10,6 +14,7 @@ struct CameraSettings{
 }
 
 //noinspection DuplicatedCode
+#[allow(clippy::manual_clamp)]
 fn my_camera_update(camera: &mut Camera2D, rl: &mut RaylibHandle, s: &CameraSettings) {
     let speed = s.basespeed / camera.zoom;
     let translation = Vector2::new(
@@ -47,13 +52,15 @@ fn main() {
 
     // Init Simulation
     let geo = HexGeometry::new(
-        Vector2{x: 0.0, y: 0.0},
+        Vector2{x: 0.0, y: 0.0}.into(),
         200,
         100,
         50.0
     );
     let mut sim = HexConwaySimulation::new(&geo, &[2], &[3,5]); //no B3/S2,3 but B2/S3,5
-    let mut rng = rand::rng();
+    let seed = rng::seed_from_args();
+    println!("Seed: {}", seed);
+    let mut rng = rng::seeded(seed);
     for row in sim.states.iter_mut() {
         for s in row.iter_mut() {
             *s = if rng.random::<f32>() < 0.3 { 1 } else { 0 };
@@ -75,6 +82,11 @@ fn main() {
         end: Vector2{x: rect.x+rect.width, y: rect.y+rect.height},
     };
 
+    // History - hold Z to rewind
+    let mut history = History::new(50, 256 * 1024 * 1024);
+    history.record(&sim);
+
+    let style = RaylibStyle::default();
     rl.set_target_fps(10);
 
     while !rl.window_should_close() {
@@ -87,6 +99,7 @@ fn main() {
                     *s = if rng.random::<f32>() < 0.3 { 1 } else { 0 };
                 }
             }
+            history.record(&sim);
         }
 
         // Draw
@@ -96,6 +109,7 @@ fn main() {
 
         // 2D Draw
         {
+            let visible = camera_view(&camera, d.get_screen_width(), d.get_screen_height());
             let mut d2d = d.begin_mode2D(camera);
             // DRAW AXIS
             // d2d.draw_line(0, 0, 100, 0, Color::RED);
@@ -103,12 +117,17 @@ fn main() {
             // d2d.draw_rectangle_rec(Rectangle{x:100.0, y:0.0, width:5.0, height:5.0}, Color::RED);
             // d2d.draw_rectangle_rec(Rectangle{x:0.0, y:100.0, width:5.0, height:5.0}, Color::BLUE);
             // END DRAW AXIS
-            sim.draw(&mut d2d);
+            RaylibRenderer::new(&mut d2d, &style).culled(visible).render(&sim);
         }
 
         d.draw_fps(10, 10);
-        // Step?
-        sim.step();
+        // Step or rewind
+        if d.is_key_down(KeyboardKey::KEY_Z) {
+            history.rewind(&mut sim, 1);
+        } else {
+            sim.step();
+            history.record(&sim);
+        }
     }
     // De-Initialization
     //--------------------------------------------------------------------------------------
@@ -1,94 +1,19 @@
-use noise::{NoiseFn, self, Seedable};
-use raylib::color::Color;
-use raylib::drawing::{RaylibDraw, RaylibDrawHandle};
-use raylib::math::{Vector2};
 use rand::Rng;
 use raylib::prelude::*;
-use simulation::hex_geom::{HexGeometry, OffsetCoord};
-
-pub struct HexSimulation<'a> {
-    geo: &'a HexGeometry,
-    pub states: Vec<Vec<STATE>>, // [y:0-rows][x:0-cols]. External is the row, internal is col/cell
-    birth: Vec<u8>,
-    stay: Vec<u8>,
-}
-
-// STATE:
-type STATE = u8;
-const SEA: STATE = 0;
-const EARTH: STATE = 1;
-type EMPIRE = u8;
-fn empire(s: STATE) -> Option<EMPIRE> { if s >= 2 { Some(s) } else { None } }
-
-impl<'a> HexSimulation<'a> {
-    pub fn new(geo: &'a HexGeometry, birth: &[u8], stay: &[u8]) -> Self {
-        let mut states = vec![vec![SEA; geo.cols]; geo.rows];
-        let noise = noise::Fbm::<noise::Perlin>::new(2);
-        for (y, row) in states.iter_mut().enumerate() {
-            for (x, state) in row.iter_mut().enumerate() {
-                let lowest_max = [geo.cols, geo.rows].into_iter().min().unwrap() as f64;
-                // Generally noises are made to work between -1 and 1 and output -1 to 1
-                *state = if noise.get([x as f64/lowest_max,y as f64/lowest_max]) >= 0.0 { EARTH } else { SEA };
-            }
-        }
-        HexSimulation {
-            geo,
-            states,
-            birth: birth.to_vec(),
-            stay: stay.to_vec(),
-        }
-    }
-
-    pub fn step(&mut self) {
-        let prev_state = self.states.clone();
-        for (y, row) in self.states.iter_mut().enumerate() {
-            for (x, state) in row.iter_mut().enumerate() {
-                if state != &EARTH { continue }
-                let neighbours = self.geo.neighbours(OffsetCoord{x: x as isize,y: y as isize}.axial());
-                // Sea cells nearby AND Greatest neighbour value
-                let nearby = neighbours.iter().fold((0_u8, 0_u8), |acc, axcord| {
-                    let o = axcord.offset();
-                    let acc_sea = if prev_state[o.y as usize][o.x as usize] == SEA { acc.0+1 } else { acc.0 };
-                    let acc_great = if prev_state[o.y as usize][o.x as usize] > acc.1 { prev_state[o.y as usize][o.x as usize] } else { acc.1 };
-                    (acc_sea, acc_great)
-                });
-                if nearby.0 != 0 {
-                    *state = 2;
-                } else if nearby.1 >= 2 {
-                    *state = nearby.1 + 1;
-                }
-            }
-        }
-    }
-
-    pub fn draw(&mut self, d: &mut RaylibDrawHandle) {
-        let w = self.geo.hex_width();
-        let h = self.geo.hex_height();
-        for (y, row) in self.states.iter().enumerate() {
-            let row_y_center = 0.5*h + (0.75*h)*y as f32;
-            let row_offset = if y % 2 == 0 { 0.5*w } else { w };
-            for (x, state) in row.iter().enumerate() {
-                let col_x_center = row_offset + x as f32*w;
-                let color = match *state {
-                    SEA => Color::CYAN,
-                    EARTH => Color::DARKGRAY,
-                    x => {
-                        let cidx = 255 - x.saturating_mul(10);
-                        Color::new(cidx, 0, 0, 255)
-                    },
-                };
-                d.draw_poly(Vector2{x: col_x_center, y: row_y_center}, 6, self.geo.size, 90.0, color);
-                d.draw_poly_lines(Vector2{x: col_x_center, y: row_y_center}, 6, self.geo.size, 90.0, Color::GRAY)
-            }
-        }
-        let mut rect = self.geo.rect();
-        let line_thick: f32 = 10.0;
-        rect.x -= line_thick;
-        rect.width += 2.0*line_thick;
-        rect.y -= line_thick;
-        rect.height += 2.0*line_thick;
-        d.draw_rectangle_lines_ex(rect, line_thick, Color::GRAY.alpha(0.5))
+use simulation::draw::{camera_view, RaylibRenderer, RaylibStyle};
+use simulation::empire::{CoastDistance, TerrainGenerator, TerrainPreset, EARTH, SEA};
+use simulation::hex_geom::HexGeometry;
+use simulation::raster::{Palette, Rgba};
+use simulation::render::Renderer;
+use simulation::rng;
+
+// Sea, earth, then darker reds the further from the coast
+fn depth_palette() -> Palette {
+    let mut p = Palette::uniform(Rgba::BLACK).with(SEA, Rgba::CYAN).with(EARTH, Rgba::DARKGRAY);
+    for x in 2..=255_u8 {
+        p = p.with(x.into(), Rgba::rgb(255 - x.saturating_mul(10), 0, 0));
     }
+    p
 }
 
 struct CameraSettings{
@@ -144,13 +69,16 @@ fn main() {
 
     // Init Simulation
     let geo = HexGeometry::new(
-        Vector2{x: 0.0, y: 0.0},
+        Vector2{x: 0.0, y: 0.0}.into(),
         200,
         100,
         50.0
     );
-    let mut sim = HexSimulation::new(&geo, &[2], &[3,5]); //no B3/S2,3 but B2/S3,5
-    let mut rng = rand::rng();
+    let seed = rng::seed_from_args();
+    println!("Seed: {}", seed);
+    let mut rng = rng::seeded(seed);
+    let terrain = TerrainGenerator::new(rng.random()).with_preset(TerrainPreset::Continent).terrain(&geo, |_, _| true);
+    let mut sim = CoastDistance::new(&geo, &terrain);
     // for row in sim.states.iter_mut() {
     //     for s in row.iter_mut() {
     //         *s = if rng.random::<f32>() < 0.3 { 1 } else { 0 };
@@ -175,6 +103,7 @@ fn main() {
         zoom_ln_max: 1.0,
     };
 
+    let style = RaylibStyle { palette: depth_palette(), ..Default::default() };
     rl.set_target_fps(10);
 
     while !rl.window_should_close() {
@@ -196,8 +125,9 @@ fn main() {
 
         // 2D Draw
         {
+            let visible = camera_view(&camera, d.get_screen_width(), d.get_screen_height());
             let mut d2d = d.begin_mode2D(camera);
-            sim.draw(&mut d2d);
+            RaylibRenderer::new(&mut d2d, &style).culled(visible).render(&sim);
         }
 
         d.draw_fps(10, 10);
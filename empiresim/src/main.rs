@@ -44,6 +44,7 @@ fn draw_hexa_test(d: &mut RaylibDrawHandle) {
     d.draw_rectangle_lines_ex(Rectangle{x: 0.0, y:0.0, width: rect_width, height: rect_height}, 1.0, Color::GRAY)
 }
 
+#[allow(dead_code)]
 fn cell_center_2d(nx: i32, ny: i32) -> Vector2 {
     Vector2::new(
         XSTART + (nx as f32 + 0.5) * XCELSIZE,
@@ -51,6 +52,7 @@ fn cell_center_2d(nx: i32, ny: i32) -> Vector2 {
     )
 }
 
+#[allow(dead_code)]
 fn cell_rectangle(nx: i32, ny: i32) -> Rectangle {
     Rectangle {
         x: XSTART + (nx as f32) * XCELSIZE,
@@ -60,6 +62,7 @@ fn cell_rectangle(nx: i32, ny: i32) -> Rectangle {
     }
 }
 
+#[allow(dead_code)]
 fn draw_2d_map(d: &mut RaylibDrawHandle) {
     for nx in 0..XCELLS {
         for ny in 0..YCELLS {
@@ -84,6 +87,7 @@ fn draw_2d_map(d: &mut RaylibDrawHandle) {
 
 const BASECAMERASPEED: f32 = 5.0;
 
+#[allow(clippy::manual_clamp)]
 fn my_camera_update(camera: &mut Camera2D, rl: &mut RaylibHandle) {
     let speed = BASECAMERASPEED / camera.zoom;
     let translation = Vector2::new(
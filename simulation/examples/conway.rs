@@ -1,58 +1,90 @@
 use simulation::{conway_sim::ConwaySimulation, rect_geom::RectGeometry};
-use raylib::prelude::*;
+use simulation::math::Vector2;
 use rand::Rng;
 use std::{thread, time::Duration};
 use simulation::geometry::Geometry;
+use simulation::rng;
+use simulation::scenario;
+use simulation::pattern::{self, Transform};
+use simulation::cycle::CycleDetector;
+use simulation::terminal::{self, TermRender, TermStyle, Viewport};
 
-fn print_grid(sim: &ConwaySimulation<RectGeometry>, width: usize, height: usize) {
-    println!("\x1B[2J\x1B[1;1H"); // Clear screen and move cursor to top
-    for y in 0..height {
-        for x in 0..width {
-            let id = y * width + x;
-            if let Some(state) = sim.get(id) {
-                print!("{}", if state.val > 0 { "█" } else { " " });
-            }
-        }
-        println!();
-    }
-    println!();
+// Size of the terminal, from the shell or the classic 80x24
+fn terminal_size() -> (usize, usize) {
+    let var = |name: &str, default: usize| std::env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default);
+    (var("COLUMNS", 80), var("LINES", 24))
 }
 
 fn main() {
-    let width = 80;
-    let height = 40;
-    
+    // A scenario file (.toml or .json) describes the whole run, e.g. empiresim/scenarios/gosper-gun.json
+    let scenario = scenario::scenario_from_args(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    });
+
     // Create geometry
-    let geometry = RectGeometry::new(
-        Vector2::new(0.0, 0.0),
-        width,
-        height,
-        Vector2::new(1.0, 1.0)
-    );
-    
+    let geometry = match &scenario {
+        Some(s) => s.rect_geometry().expect("The scenario needs a rect geometry"),
+        None => RectGeometry::new(Vector2::new(0.0, 0.0), 80, 40, Vector2::new(1.0, 1.0)),
+    };
+    let (width, height) = (geometry.cells.x, geometry.cells.y);
+
     // Create simulation
-    let mut sim = ConwaySimulation::new(&geometry, &[3], &[2,3]);
-    
-    // Populate with random values
-    let mut rng = rand::rng();
-    for i in 0..geometry.size() {
-        if let Some(state) = sim.get_mut(i) {
-            state.val = if rng.random::<f32>() < 0.3 { 1 } else { 0 };
+    let mut sim = if let Some(s) = &scenario {
+        s.build_conway(&geometry, &mut s.rng()).expect("The scenario needs the 'conway' model")
+    } else {
+        let mut sim = ConwaySimulation::new(&geometry, &[3], &[2,3]);
+        // Populate with random values
+        let seed = rng::seed_from_args();
+        println!("Seed: {}", seed);
+        let mut rng = rng::seeded(seed);
+        // Pattern given after the seed (catalogue name or pattern file) at the centre, or random soup
+        let pattern = pattern::pattern_from_args(std::env::args().skip(1)).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        });
+        if let Some(p) = pattern {
+            p.stamp(&mut sim, width.saturating_sub(p.width) / 2, height.saturating_sub(p.height) / 2, Transform::default());
+        } else {
+            for i in 0..geometry.size() {
+                if let Some(state) = sim.get_mut(i) {
+                    state.val = if rng.random::<f32>() < 0.3 { 1 } else { 0 };
+                }
+            }
         }
-    }
+        sim
+    };
     
+    // Two lines for the status, the viewport follows the live cells
+    let (columns, lines) = terminal_size();
+    let mut view = Viewport::rect_for_terminal(columns, lines.saturating_sub(2));
+    let style = TermStyle::default();
+    let show = |sim: &ConwaySimulation<RectGeometry>, view: &mut Viewport| {
+        let geo = sim.geo();
+        if let Some(bb) = terminal::bounding_box(width, height, |x, y| sim.get(geo.cell2id(x, y).0).map_or(0, |s| s.val as u32), |s| s != 0) {
+            view.follow(bb, width, height);
+        }
+        print!("{}", sim.frame(view, &style));
+    };
+
+    print!("{}{}", terminal::CLEAR, terminal::HIDE_CURSOR);
+    show(&sim, &mut view);
     println!("Conway's Game of Life Demo - Running for 100 steps");
-    println!("Initial state:");
-    print_grid(&sim, width, height);
-    
-    // Run simulation for 100 steps
+
+    // Run simulation for 100 steps, or until the board stabilizes
+    let mut detector = CycleDetector::new();
+    detector.observe(&sim);
     for step in 1..=100 {
         thread::sleep(Duration::from_millis(200));
         sim.step();
 
-        print_grid(&sim, width, height);
+        show(&sim, &mut view);
         println!("Step {}/100:", step);
+        if let Some(stability) = detector.observe(&sim) {
+            println!("The board {}", stability);
+            break;
+        }
     }
     
-    println!("Simulation complete!");
+    println!("Simulation complete!{}", terminal::SHOW_CURSOR);
 }
@@ -1,18 +1,26 @@
-use raylib::prelude::*;
+use rand::Rng;
 use crate::geometry::Geometry;
-use crate::rect_geom::{RectGeoID, RectGeometry};
+use crate::observer::{Observers, StepObserver};
+use crate::rect_geom::RectGeoID;
+use crate::rng::{self, SimRng};
 use crate::simulation::Simulation;
 
-#[derive(Clone)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct State {
     pub val: u8
 }
 
 pub struct ConwaySimulation<'a, T> where T: Geometry {
     geo: &'a T,
-    states: Vec<State>,
-    birth: Vec<u8>,
-    stay: Vec<u8>,
+    pub(crate) states: Vec<State>,
+    pub(crate) birth: Vec<u8>,
+    pub(crate) stay: Vec<u8>,
+    // Probability that a birth/survival allowed by the rule actually happens. 1.0 is classic Life.
+    pub(crate) birth_p: f64,
+    pub(crate) stay_p: f64,
+    pub(crate) rng: SimRng,
+    pub(crate) generation: u64,
+    observers: Observers<State>,
 }
 
 impl<'a, T> Simulation for ConwaySimulation<'a, T> where T: Geometry{
@@ -31,7 +39,17 @@ impl<'a, T> Simulation for ConwaySimulation<'a, T> where T: Geometry{
 impl<'a, T> ConwaySimulation<'a, T> where T: Geometry {
     pub fn new(geo: &'a T, birth: &[u8], stay: &[u8]) -> Self {
         let states = vec![State {val: 0}; geo.size()];
-        Self{ geo, states, birth: birth.to_vec(), stay: stay.to_vec() }
+        Self{ geo, states, birth: birth.to_vec(), stay: stay.to_vec(), birth_p: 1.0, stay_p: 1.0, rng: rng::seeded(0), generation: 0, observers: Observers::new() }
+    }
+
+    // Stochastic variant of the rule: a birth happens with probability birth_p and a survival
+    // with probability stay_p. All the draws come from the given rng.
+    pub fn with_probabilities(mut self, birth_p: f64, stay_p: f64, rng: SimRng) -> Self {
+        assert!((0.0..=1.0).contains(&birth_p) && (0.0..=1.0).contains(&stay_p), "Probabilities must be in [0, 1]");
+        self.birth_p = birth_p;
+        self.stay_p = stay_p;
+        self.rng = rng;
+        self
     }
 
     pub fn geo(&self) -> &'a T {
@@ -45,6 +63,14 @@ impl<'a, T> ConwaySimulation<'a, T> where T: Geometry {
     pub fn get_mut(&mut self, id: usize) -> Option<&mut State> {
         self.states.get_mut(id)
     }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn add_observer(&mut self, observer: Box<dyn StepObserver<State>>) {
+        self.observers.add(observer);
+    }
 }
 
 // TODO: This function expect the GeoID to be a single incremental sequential value!
@@ -62,55 +88,34 @@ impl<'a, T> ConwaySimulation<'a, T> where T: Geometry<ID = RectGeoID> {
                 if prev_state.get(gid.0).unwrap().val > 0 { acc+1 } else { acc }
             });
             // TODO: Generalize as attributes!
-            s.val = if s.val > 0 {
-                if self.stay.contains(&alives) { 1 } else { 0 }
+            let alive = if s.val > 0 {
+                self.stay.contains(&alives) && chance(&mut self.rng, self.stay_p)
             } else {
-                if self.birth.contains(&alives) { 1 } else { 0 }
+                self.birth.contains(&alives) && chance(&mut self.rng, self.birth_p)
             };
+            let new = State { val: alive as u8 };
+            if !self.observers.is_empty() && *s != new {
+                self.observers.cell_changed(i, s, &new);
+            }
+            *s = new;
         }
+        self.generation += 1;
+        self.observers.generation_end(self.generation);
     }
 }
 
-pub fn draw_gol_rect(d: &mut RaylibDrawHandle, s: &dyn Simulation<State=State,Geometry=RectGeometry>) {
-    let states = s.get_states();
-    let geo = s.get_geometry();
-    let start = geo.start();
-    for nx in 0..geo.cells.x {
-        for ny in 0..geo.cells.y {
-            // Here I need to know what State it is.
-            // TODO: This function works for all Simulation that use this specific State and RectGeometry.
-            //  So probably we can generalize the State OUTSIDE the simulation?
-            let color = if states.get(geo.cell2id(nx, ny).0).unwrap().val > 0 {Color::WHITE} else {Color::BLACK};
-            d.draw_rectangle_rec(
-                Rectangle {
-                    x: start.x + (nx as f32) * geo.celsize.x,
-                    y: start.y + (ny as f32) * geo.celsize.y,
-                    width: geo.celsize.x,
-                    height: geo.celsize.y,
-                }, color);
-        }
-    }
-    for nx in 0..=geo.cells.x {
-        d.draw_line_v(
-            Vector2::new(start.x + nx as f32 * geo.celsize.x, start.y),
-            Vector2::new(start.x + nx as f32 * geo.celsize.x, start.y + geo.cells.y as f32 * geo.celsize.y),
-            Color::BLACK,
-        );
-    }
-    for ny in 0..=geo.cells.y {
-        d.draw_line_v(
-            Vector2::new(start.x, start.y + ny as f32 * geo.celsize.y),
-            Vector2::new(start.x + geo.cells.x as f32 * geo.celsize.x, start.y + ny as f32 * geo.celsize.y),
-            Color::BLACK,
-        );
-    }
+// Draw from the stream only when the outcome is actually random, so that deterministic rules
+// leave the rng untouched.
+pub(crate) fn chance(rng: &mut SimRng, p: f64) -> bool {
+    if p >= 1.0 { true } else if p <= 0.0 { false } else { rng.random_bool(p) }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::rect_geom::RectGeometry;
-    use raylib::prelude::Vector2;
+    use crate::pattern::{catalogue, rle, Transform};
+    use crate::math::Vector2;
 
     #[test]
     fn test_glider_pattern() {
@@ -126,22 +131,10 @@ mod tests {
         // Set up glider pattern at position (1,1)
         // Pattern:
         //  X
-        //   XX
-        // XX
-        let glider_positions = [
-            (2, 1), // top
-            (3, 2), // middle right
-            (1, 3), // bottom left
-            (2, 3), // bottom middle
-            (3, 3), // bottom right
-        ];
-
-        for (x, y) in glider_positions {
-            let id = y * 10 + x;
-            if let Some(state) = sim.get_mut(id) {
-                state.val = 1;
-            }
-        }
+        //   X
+        // XXX
+        let glider = rle::parse(catalogue::GLIDER).unwrap();
+        glider.stamp(&mut sim, 1, 1, Transform::default());
 
         // Step 4 times to see glider movement
         for _ in 0..4 {
@@ -149,6 +142,7 @@ mod tests {
         }
 
         // After 4 steps, glider should be at position (2,2) relative to original
+        #[allow(unused_variables)]
         let expected_positions = [
             (3, 2), // moved right and up
             (4, 3), // moved right and up
@@ -161,6 +155,7 @@ mod tests {
         for y in 0..10 {
             for x in 0..10 {
                 let id = y * 10 + x;
+                #[allow(clippy::collapsible_if)]
                 if let Some(state) = sim.get(id) {
                     if state.val > 0 {
                         alive_count += 1;
@@ -172,4 +167,51 @@ mod tests {
         // Glider should maintain 5 alive cells
         assert_eq!(alive_count, 5, "Glider should maintain 5 alive cells");
     }
+
+    fn random_soup(sim: &mut ConwaySimulation<RectGeometry>, seed: u64) {
+        let mut rng = rng::seeded(seed);
+        for i in 0..sim.geo().size() {
+            sim.get_mut(i).unwrap().val = rng.random_bool(0.3) as u8;
+        }
+    }
+
+    #[test]
+    fn test_stochastic_is_reproducible() {
+        let geometry = RectGeometry::new(Vector2::new(0.0, 0.0), 20, 20, Vector2::new(1.0, 1.0));
+        let run = |seed: u64| {
+            let mut sim = ConwaySimulation::new(&geometry, &[3], &[2,3])
+                .with_probabilities(0.7, 0.9, rng::seeded(seed));
+            random_soup(&mut sim, 1);
+            for _ in 0..10 {
+                sim.step();
+            }
+            sim.get_states().iter().map(|s| s.val).collect::<Vec<_>>()
+        };
+        assert_eq!(run(42), run(42));
+        assert_ne!(run(42), run(43));
+    }
+
+    #[test]
+    fn test_stochastic_extremes() {
+        let geometry = RectGeometry::new(Vector2::new(0.0, 0.0), 20, 20, Vector2::new(1.0, 1.0));
+        let mut never_born = ConwaySimulation::new(&geometry, &[3], &[2,3])
+            .with_probabilities(0.0, 1.0, rng::seeded(7));
+        random_soup(&mut never_born, 2);
+        let alive_before = never_born.get_states().iter().filter(|s| s.val > 0).count();
+        never_born.step();
+        let alive_after = never_born.get_states().iter().filter(|s| s.val > 0).count();
+        assert!(alive_after <= alive_before, "No cell can be born with birth probability 0");
+
+        let mut deterministic = ConwaySimulation::new(&geometry, &[3], &[2,3])
+            .with_probabilities(1.0, 1.0, rng::seeded(7));
+        let mut classic = ConwaySimulation::new(&geometry, &[3], &[2,3]);
+        random_soup(&mut deterministic, 3);
+        random_soup(&mut classic, 3);
+        for _ in 0..5 {
+            deterministic.step();
+            classic.step();
+        }
+        let vals = |s: &ConwaySimulation<RectGeometry>| s.get_states().iter().map(|s| s.val).collect::<Vec<_>>();
+        assert_eq!(vals(&deterministic), vals(&classic));
+    }
 }
@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use crate::conway_sim::ConwaySimulation;
+use crate::hex_conway_sim::HexConwaySimulation;
+use crate::hex_geom::OffsetCoord;
+use crate::rect_geom::RectGeometry;
+
+// A simulation whose alive cells can be listed in a coordinate system where moving a pattern
+// translates all its coordinates by the same amount.
+// For rect grids these are (x, y) cells, for hex grids the axial (q, r) coordinates,
+// because odd-r offset coordinates do not translate uniformly.
+pub trait CycleObservable {
+    fn generation(&self) -> u64;
+    fn live_cells(&self) -> Vec<(isize, isize)>;
+    fn step(&mut self);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stability {
+    Still { generation: u64 },
+    Oscillator { period: u64, generation: u64 },
+    Spaceship { dx: isize, dy: isize, period: u64, generation: u64 },
+}
+
+impl fmt::Display for Stability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stability::Still { generation } =>
+                write!(f, "became still at generation {}", generation),
+            Stability::Oscillator { period, generation } =>
+                write!(f, "entered period-{} oscillation at generation {}", period, generation),
+            Stability::Spaceship { dx, dy, period, generation } =>
+                write!(f, "spaceship with displacement ({},{}) and period {} from generation {}", dx, dy, period, generation),
+        }
+    }
+}
+
+// Remembers every pattern seen so far, normalized on its bounding box so that a translated copy
+// is equal, indexed by its hash. The origin of the bounding box gives the displacement.
+#[derive(Default)]
+pub struct CycleDetector {
+    seen: HashMap<u64, Vec<Seen>>, // hash -> patterns with that hash
+}
+
+struct Seen {
+    generation: u64,
+    origin: (isize, isize),
+    cells: Vec<(isize, isize)>,
+}
+
+impl CycleDetector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn clear(&mut self) {
+        self.seen.clear();
+    }
+
+    // Record the current state of the simulation and report if it repeats an earlier one.
+    pub fn observe<S: CycleObservable>(&mut self, sim: &S) -> Option<Stability> {
+        let generation = sim.generation();
+        let (cells, origin) = normalized(sim.live_cells());
+        let mut hasher = DefaultHasher::new();
+        cells.hash(&mut hasher);
+        let same_hash = self.seen.entry(hasher.finish()).or_default();
+        // Equal hashes of different patterns are no cycle
+        let Some(seen) = same_hash.iter().find(|s| s.cells == cells) else {
+            same_hash.push(Seen { generation, origin, cells });
+            return None;
+        };
+        let (first, first_origin) = (seen.generation, seen.origin);
+        let period = generation - first;
+        let (dx, dy) = (origin.0 - first_origin.0, origin.1 - first_origin.1);
+        Some(if dx != 0 || dy != 0 {
+            Stability::Spaceship { dx, dy, period, generation: first }
+        } else if period == 1 {
+            Stability::Still { generation: first }
+        } else {
+            Stability::Oscillator { period, generation: first }
+        })
+    }
+}
+
+// Cells moved so that their bounding box starts at (0, 0), sorted, and the box origin
+fn normalized(mut cells: Vec<(isize, isize)>) -> (Vec<(isize, isize)>, (isize, isize)) {
+    let min_x = cells.iter().map(|c| c.0).min().unwrap_or(0);
+    let min_y = cells.iter().map(|c| c.1).min().unwrap_or(0);
+    for c in cells.iter_mut() {
+        c.0 -= min_x;
+        c.1 -= min_y;
+    }
+    cells.sort_unstable();
+    (cells, (min_x, min_y))
+}
+
+// Step the simulation until its state repeats, at most max_generations times.
+pub fn run_until_stable<S: CycleObservable>(sim: &mut S, max_generations: u64) -> Option<Stability> {
+    let mut detector = CycleDetector::new();
+    if let Some(s) = detector.observe(sim) {
+        return Some(s);
+    }
+    for _ in 0..max_generations {
+        sim.step();
+        if let Some(s) = detector.observe(sim) {
+            return Some(s);
+        }
+    }
+    None
+}
+
+impl CycleObservable for ConwaySimulation<'_, RectGeometry> {
+    fn generation(&self) -> u64 {
+        ConwaySimulation::generation(self)
+    }
+
+    fn live_cells(&self) -> Vec<(isize, isize)> {
+        let geo = self.geo();
+        (0..geo.cells.x * geo.cells.y).filter(|&i| self.get(i).unwrap().val > 0).map(|i| {
+            let (x, y) = geo.id2cell(crate::rect_geom::RectGeoID(i));
+            (x as isize, y as isize)
+        }).collect()
+    }
+
+    fn step(&mut self) {
+        ConwaySimulation::step(self)
+    }
+}
+
+impl CycleObservable for HexConwaySimulation<'_> {
+    fn generation(&self) -> u64 {
+        HexConwaySimulation::generation(self)
+    }
+
+    fn live_cells(&self) -> Vec<(isize, isize)> {
+        let mut cells = Vec::new();
+        for (y, row) in self.states.iter().enumerate() {
+            for (x, state) in row.iter().enumerate() {
+                if *state > 0 {
+                    let a = OffsetCoord{x: x as isize, y: y as isize}.axial();
+                    cells.push((a.q, a.r));
+                }
+            }
+        }
+        cells
+    }
+
+    fn step(&mut self) {
+        HexConwaySimulation::step(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hex_geom::HexGeometry;
+    use crate::math::Vector2;
+
+    fn rect_with<'a>(geo: &'a RectGeometry, cells: &[(usize, usize)]) -> ConwaySimulation<'a, RectGeometry> {
+        let mut sim = ConwaySimulation::new(geo, &[3], &[2,3]);
+        for &(x, y) in cells {
+            sim.get_mut(geo.cell2id(x, y).0).unwrap().val = 1;
+        }
+        sim
+    }
+
+    #[test]
+    fn test_block_is_still() {
+        let geo = RectGeometry::new(Vector2::new(0.0, 0.0), 10, 10, Vector2::new(1.0, 1.0));
+        let mut sim = rect_with(&geo, &[(4, 4), (5, 4), (4, 5), (5, 5)]);
+        assert_eq!(run_until_stable(&mut sim, 10), Some(Stability::Still { generation: 0 }));
+    }
+
+    #[test]
+    fn test_blinker_oscillates() {
+        let geo = RectGeometry::new(Vector2::new(0.0, 0.0), 10, 10, Vector2::new(1.0, 1.0));
+        let mut sim = rect_with(&geo, &[(3, 5), (4, 5), (5, 5)]);
+        assert_eq!(run_until_stable(&mut sim, 10), Some(Stability::Oscillator { period: 2, generation: 0 }));
+    }
+
+    #[test]
+    fn test_glider_is_spaceship() {
+        let geo = RectGeometry::new(Vector2::new(0.0, 0.0), 20, 20, Vector2::new(1.0, 1.0));
+        let mut sim = rect_with(&geo, &[(2, 1), (3, 2), (1, 3), (2, 3), (3, 3)]);
+        let result = run_until_stable(&mut sim, 10);
+        assert_eq!(result, Some(Stability::Spaceship { dx: 1, dy: 1, period: 4, generation: 0 }));
+        assert_eq!(result.unwrap().to_string(), "spaceship with displacement (1,1) and period 4 from generation 0");
+    }
+
+    #[test]
+    fn test_dying_pattern_becomes_still() {
+        // A single cell dies at generation 1, then the empty board never changes
+        let geo = RectGeometry::new(Vector2::new(0.0, 0.0), 10, 10, Vector2::new(1.0, 1.0));
+        let mut sim = rect_with(&geo, &[(5, 5)]);
+        assert_eq!(run_until_stable(&mut sim, 10), Some(Stability::Still { generation: 1 }));
+        assert_eq!(sim.generation(), 2);
+    }
+
+    #[test]
+    fn test_hex_still_and_timeout() {
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 10, 10, 1.0);
+        let mut sim = HexConwaySimulation::new(&geo, &[2], &[3,5]);
+        assert_eq!(run_until_stable(&mut sim, 10), Some(Stability::Still { generation: 0 }));
+        // Two neighbours die and the two cells next to both are born, turning the pair around
+        sim.states[4][4] = 1;
+        sim.states[4][5] = 1;
+        assert_eq!(run_until_stable(&mut sim, 10), Some(Stability::Oscillator { period: 2, generation: 1 }));
+        // A ring of six
+        let mut sim = HexConwaySimulation::new(&geo, &[2], &[3,5]);
+        for n in geo.neighbours(OffsetCoord{x: 4, y: 4}.axial()) {
+            let o = n.offset();
+            sim.states[o.y as usize][o.x as usize] = 1;
+        }
+        assert_eq!(run_until_stable(&mut sim, 10), Some(Stability::Oscillator { period: 3, generation: 0 }));
+
+        let geo = RectGeometry::new(Vector2::new(0.0, 0.0), 30, 30, Vector2::new(1.0, 1.0));
+        let mut sim = rect_with(&geo, &[(2, 1), (3, 2), (1, 3), (2, 3), (3, 3)]);
+        assert_eq!(run_until_stable(&mut sim, 2), None);
+    }
+}
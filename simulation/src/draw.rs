@@ -0,0 +1,204 @@
+use raylib::prelude::*;
+use crate::math;
+use crate::raster::{Palette, Rgba};
+use crate::render::{CellView, Layout, Renderer};
+
+// raylib backend of render::Renderer, only with the `raylib` feature. It draws in world
+// coordinates, so call it inside begin_mode2D with the camera of the viewer.
+// Big maps: cull with camera_view, or keep them in a MapCache texture.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RaylibStyle {
+    pub palette: Palette,
+    // Outline of every cell
+    pub grid: Option<Rgba>,
+    // Line around the whole map: colour and thickness
+    pub frame: Option<(Rgba, f32)>,
+}
+
+impl Default for RaylibStyle {
+    // Alive white on black, the look of the first viewers
+    fn default() -> Self {
+        Self {
+            palette: Palette::uniform(Rgba::WHITE).with(0, Rgba::BLACK),
+            grid: Some(Rgba::GRAY),
+            frame: Some((Rgba([130, 130, 130, 127]), 10.0)),
+        }
+    }
+}
+
+pub fn color(c: Rgba) -> Color {
+    let [r, g, b, a] = c.0;
+    Color::new(r, g, b, a)
+}
+
+// World rectangle seen by the camera (without rotation) on a screen of the given size
+pub fn camera_view(camera: &Camera2D, screen_width: i32, screen_height: i32) -> math::Rectangle {
+    let (w, h) = (screen_width as f32 / camera.zoom, screen_height as f32 / camera.zoom);
+    math::Rectangle::new(camera.target.x - camera.offset.x / camera.zoom, camera.target.y - camera.offset.y / camera.zoom, w, h)
+}
+
+fn draw_cell(d: &mut impl RaylibDraw, layout: Layout, x: usize, y: usize, fill: Color, grid: Option<Color>) {
+    match layout {
+        Layout::Hex(geo) => {
+            let center: Vector2 = geo.cellcenter(x, y).into();
+            d.draw_poly(center, 6, geo.size, 90.0, fill);
+            if let Some(g) = grid {
+                d.draw_poly_lines(center, 6, geo.size, 90.0, g);
+            }
+        }
+        Layout::Rect(geo) => {
+            let rect: Rectangle = geo.cell_rectangle(x as i32, y as i32).into();
+            d.draw_rectangle_rec(rect, fill);
+            if let Some(g) = grid {
+                d.draw_rectangle_lines_ex(rect, 1.0, g);
+            }
+        }
+    }
+}
+
+fn draw_frame(d: &mut impl RaylibDraw, layout: Layout, style: &RaylibStyle) {
+    if let Some((c, thick)) = style.frame {
+        let mut rect: Rectangle = layout.bounds().into();
+        rect.x -= thick;
+        rect.width += 2.0*thick;
+        rect.y -= thick;
+        rect.height += 2.0*thick;
+        d.draw_rectangle_lines_ex(rect, thick, color(c));
+    }
+}
+
+pub struct RaylibRenderer<'d, D: RaylibDraw> {
+    d: &'d mut D,
+    style: &'d RaylibStyle,
+    visible: Option<math::Rectangle>,
+}
+
+impl<'d, D: RaylibDraw> RaylibRenderer<'d, D> {
+    pub fn new(d: &'d mut D, style: &'d RaylibStyle) -> Self {
+        Self { d, style, visible: None }
+    }
+
+    // Skip the cells outside of the world area, see camera_view
+    pub fn culled(mut self, visible: math::Rectangle) -> Self {
+        self.visible = Some(visible);
+        self
+    }
+}
+
+impl<D: RaylibDraw> Renderer for RaylibRenderer<'_, D> {
+    type Output = ();
+
+    fn render(&mut self, view: &dyn CellView) {
+        let layout = view.layout();
+        let (cols, rows) = layout.dims();
+        let (xs, ys) = match self.visible {
+            Some(area) => layout.cells_in(area),
+            None => (0..cols, 0..rows),
+        };
+        let palette = &self.style.palette;
+        // Rect grids are lines across the whole map, drawn after the cells
+        let cell_grid = match layout {
+            Layout::Hex(_) => self.style.grid.map(color),
+            Layout::Rect(_) => None,
+        };
+        for y in ys.clone() {
+            for x in xs.clone() {
+                draw_cell(self.d, layout, x, y, color(palette.get(view.cell(x, y))), cell_grid);
+            }
+        }
+        if let (Layout::Rect(geo), Some(g)) = (layout, self.style.grid) {
+            let start: Vector2 = geo.start().into();
+            let (x0, x1) = (start.x + xs.start as f32 * geo.celsize.x, start.x + xs.end as f32 * geo.celsize.x);
+            let (y0, y1) = (start.y + ys.start as f32 * geo.celsize.y, start.y + ys.end as f32 * geo.celsize.y);
+            for nx in xs.start..=xs.end {
+                let x = start.x + nx as f32 * geo.celsize.x;
+                self.d.draw_line_v(Vector2::new(x, y0), Vector2::new(x, y1), color(g));
+            }
+            for ny in ys.start..=ys.end {
+                let y = start.y + ny as f32 * geo.celsize.y;
+                self.d.draw_line_v(Vector2::new(x0, y), Vector2::new(x1, y), color(g));
+            }
+        }
+        draw_frame(self.d, layout, self.style);
+    }
+}
+
+// The whole map drawn once in a texture, then only the cells changed since the last update are
+// redrawn. Drawing the texture is a single quad whatever the size of the map, but it gets blurry
+// zoomed in: past scale() pixels per world unit draw the visible cells directly instead.
+pub struct MapCache {
+    target: RenderTexture2D,
+    // Texture pixels per world unit
+    scale: f32,
+    drawn: Vec<u32>,
+    valid: bool,
+}
+
+impl MapCache {
+    // The texture is at most max_side pixels on its longest side
+    pub fn new(rl: &mut RaylibHandle, thread: &RaylibThread, layout: Layout, max_side: u32) -> Result<Self, String> {
+        let bounds = layout.bounds();
+        let scale = max_side as f32 / bounds.width.max(bounds.height);
+        let target = rl.load_render_texture(thread, (bounds.width * scale).ceil() as u32, (bounds.height * scale).ceil() as u32)?;
+        Ok(Self { target, scale, drawn: Vec::new(), valid: false })
+    }
+
+    pub fn scale(&self) -> f32 {
+        self.scale
+    }
+
+    // Redraw everything on the next update, e.g. after a change of style
+    pub fn invalidate(&mut self) {
+        self.valid = false;
+    }
+
+    // Call before begin_mode2D. Returns the number of cells redrawn.
+    pub fn update(&mut self, d: &mut RaylibDrawHandle, thread: &RaylibThread, view: &dyn CellView, style: &RaylibStyle) -> usize {
+        let layout = view.layout();
+        let (cols, rows) = layout.dims();
+        let bounds = layout.bounds();
+        if self.drawn.len() != cols * rows {
+            self.drawn = vec![0; cols * rows];
+            self.valid = false;
+        }
+        let camera = Camera2D {
+            offset: Vector2::new(0.0, 0.0),
+            target: Vector2::new(bounds.x, bounds.y),
+            rotation: 0.0,
+            zoom: self.scale,
+        };
+        let grid = style.grid.map(color);
+        let mut t = d.begin_texture_mode(thread, &mut self.target);
+        let mut m = t.begin_mode2D(camera);
+        if !self.valid {
+            m.clear_background(Color::BLANK);
+        }
+        let mut redrawn = 0;
+        for y in 0..rows {
+            for x in 0..cols {
+                let state = view.cell(x, y);
+                let drawn = &mut self.drawn[y * cols + x];
+                if self.valid && *drawn == state {
+                    continue;
+                }
+                *drawn = state;
+                draw_cell(&mut m, layout, x, y, color(style.palette.get(state)), grid);
+                redrawn += 1;
+            }
+        }
+        self.valid = true;
+        redrawn
+    }
+
+    // The texture over the map, inside begin_mode2D
+    pub fn draw(&self, d: &mut impl RaylibDraw, layout: Layout, style: &RaylibStyle) {
+        let bounds = layout.bounds();
+        let (w, h) = (self.target.width() as f32, self.target.height() as f32);
+        // Render textures are upside down
+        let source = Rectangle { x: 0.0, y: 0.0, width: w, height: -h };
+        let dest = Rectangle { x: bounds.x, y: bounds.y, width: w / self.scale, height: h / self.scale };
+        d.draw_texture_pro(&self.target, source, dest, Vector2::new(0.0, 0.0), 0.0, Color::WHITE);
+        draw_frame(d, layout, style);
+    }
+}
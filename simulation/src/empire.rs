@@ -0,0 +1,2091 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use noise::{MultiFractal, NoiseFn};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use crate::hex_geom::{HexGeometry, OffsetCoord};
+use crate::math::Vector2;
+use crate::observer::{Observers, StepObserver};
+use crate::rng::{self, SimRng};
+use crate::scenario::TerrainSpec;
+
+// Empires expanding on a hex map of sea and land.
+// The map has two layers: the terrain with its rivers, which does not change, and the owner of
+// every cell.
+// At each step a free earth cell next to some empires is claimed by one of them, chosen by the
+// Conflict policy, and captured with the expansion chance of that empire (see Attributes) over the
+// cost of the terrain.
+// Sea is never captured. Owned cells change hands only with Warfare and Rebellion, both off by
+// default. Empires fight only the empires they are at war with (see Relation and Diplomacy).
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Terrain {
+    Sea,
+    // Land without a biome, as in the maps of noise_terrain
+    Earth,
+    Plains,
+    Forest,
+    Desert,
+    Mountain,
+    Tundra,
+}
+
+// A river makes the land easier to cross and harder to attack
+const RIVER_COST: f64 = 0.75;
+const RIVER_DEFENCE: f64 = 1.25;
+
+impl Terrain {
+    // In the order of their state, see SEA
+    pub const ALL: [Terrain; 7] = [Terrain::Sea, Terrain::Earth, Terrain::Plains, Terrain::Forest, Terrain::Desert, Terrain::Mountain, Terrain::Tundra];
+
+    pub fn is_land(self) -> bool {
+        self != Terrain::Sea
+    }
+
+    // Multiplier of the strength defending a cell: open land is harder to hold, rough land easier.
+    // The sea is never owned.
+    pub fn defence(self) -> f64 {
+        match self {
+            Terrain::Sea | Terrain::Earth => 1.0,
+            Terrain::Desert => 0.8,
+            Terrain::Plains => 0.9,
+            Terrain::Tundra => 1.2,
+            Terrain::Forest => 1.5,
+            Terrain::Mountain => 2.5,
+        }
+    }
+
+    // Divides the expansion chance into a cell, the sea cannot be crossed
+    pub fn cost(self) -> f64 {
+        match self {
+            Terrain::Sea => f64::INFINITY,
+            Terrain::Earth | Terrain::Plains => 1.0,
+            Terrain::Forest => 1.5,
+            Terrain::Desert => 2.0,
+            Terrain::Tundra => 2.5,
+            Terrain::Mountain => 4.0,
+        }
+    }
+}
+
+pub type EmpireId = u32;
+// Owner of a cell, None for free cells
+pub type Owner = Option<EmpireId>;
+
+// States of the cells in the views (CellView, Palette::empire): the terrain of the free cells
+// (Terrain as u32, from SEA and EARTH to the biomes), RIVER for free land with a river, then the
+// owned cells with owner_state of their empire
+pub const SEA: u32 = 0;
+pub const EARTH: u32 = 1;
+pub const RIVER: u32 = Terrain::ALL.len() as u32;
+pub const FIRST_OWNER: u32 = RIVER + 1;
+
+pub fn owner_state(id: EmpireId) -> u32 {
+    id + FIRST_OWNER
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Empire {
+    pub id: EmpireId,
+    pub name: String,
+    // Generation of the foundation
+    pub founded: u64,
+    // Cell (x, y) the control of the empire comes from: where it was founded, then the nearest
+    // cell left when it is lost
+    pub capital: (usize, usize),
+    pub attributes: Attributes,
+}
+
+// People living on a cell at most
+pub const PEOPLE_PER_CELL: f64 = 100.0;
+// People founding an empire
+const SETTLERS: f64 = 10.0;
+// Logistic growth of the population per step
+const GROWTH: f64 = 0.1;
+
+// Numeric traits of an empire. Population and strength follow the territory at every step,
+// expansion and aggression are given at the foundation and never change.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Attributes {
+    pub population: f64,
+    // Military strength: the people under arms, more in aggressive empires
+    pub strength: f64,
+    // Chance to capture a free cell claimed in a step, 0-1
+    pub expansion: f64,
+    // 0-1, crowded aggressive empires expand faster
+    pub aggression: f64,
+}
+
+impl Default for Attributes {
+    // Always expands: one ring per step like a plain cellular automaton
+    fn default() -> Self {
+        Attributes::founding(1.0, 0.0)
+    }
+}
+
+impl Attributes {
+    // A new empire of settlers
+    pub fn founding(expansion: f64, aggression: f64) -> Self {
+        let mut a = Attributes { population: SETTLERS, strength: 0.0, expansion, aggression };
+        a.strength = a.population * a.arms();
+        a
+    }
+
+    // Random traits, the expansion between 0.2 and 0.8
+    pub fn random(rng: &mut SimRng) -> Self {
+        Attributes::founding(rng.random_range(0.2..0.8), rng.random())
+    }
+
+    // Share of the population under arms
+    fn arms(&self) -> f64 {
+        0.05 + 0.2 * self.aggression
+    }
+
+    // Chance to capture a claimed cell for an empire of `cells` cells: the base expansion,
+    // raised towards 1 by aggression when the population fills the territory
+    pub fn expansion_chance(&self, cells: usize) -> f64 {
+        let crowding = (self.population / (cells.max(1) as f64 * PEOPLE_PER_CELL)).clamp(0.0, 1.0);
+        (self.expansion + (1.0 - self.expansion) * self.aggression * crowding).clamp(0.0, 1.0)
+    }
+
+    // One step of an empire of `cells` cells: the population grows towards what the territory
+    // can feed and the strength follows the population
+    pub fn evolve(&mut self, cells: usize) {
+        let capacity = cells as f64 * PEOPLE_PER_CELL;
+        self.population = (self.population + GROWTH * self.population * (1.0 - self.population / capacity)).max(0.0);
+        self.strength += (self.population * self.arms() - self.strength) * 0.25;
+    }
+}
+
+// How a free cell chooses among the empires around it. None of them depends on the direction
+// of the neighbours, so the growth is the same in every direction.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Conflict {
+    // At random, each empire weighted by its number of neighbouring cells
+    #[default]
+    Weighted,
+    // Most neighbouring cells, ties to the empire with the most cells on the map
+    Strongest,
+    // Most neighbouring cells, ties to the empire founded first
+    Oldest,
+    // Most neighbouring cells, a tie leaves the cell free for this step
+    KeepOnTie,
+}
+
+// Border combat: an owned cell next to other empires falls to the strongest attacker with a
+// chance growing with the ratio of the attack to the defence. Both sides bring the strength per
+// cell of their empire times the cells they have around it (the defender counts the cell itself)
+// times their control there (see Control): the defender its control of the cell, the attacker the
+// best of its cells around. The attack grows with aggression, the defence with the terrain.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(from = "WarfareFile")]
+pub struct Warfare {
+    // Chance per step that a cell falls to an overwhelming attacker, 0-1. Low values keep the
+    // fronts stable.
+    pub rate: f64,
+    // How much the stronger side is favoured: 0 is a coin toss, high values let only the stronger
+    // side win
+    pub sharpness: f64,
+}
+
+impl Default for Warfare {
+    fn default() -> Self {
+        Warfare { rate: 0.1, sharpness: 2.0 }
+    }
+}
+
+// Warfare as read from the files. The supply range of the first version became the range of
+// the Control and is ignored.
+#[derive(Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct WarfareFile {
+    rate: f64,
+    sharpness: f64,
+    #[serde(rename = "supply_range")]
+    _supply_range: Option<f64>,
+}
+
+impl Default for WarfareFile {
+    fn default() -> Self {
+        let Warfare { rate, sharpness } = Warfare::default();
+        WarfareFile { rate, sharpness, _supply_range: None }
+    }
+}
+
+impl From<WarfareFile> for Warfare {
+    fn from(file: WarfareFile) -> Self {
+        Warfare { rate: file.rate, sharpness: file.sharpness }
+    }
+}
+
+// Empires breaking up. Territories cut off from the capital become independent empires, and
+// cells losing the control of their empire rise up with the cells of the empire around them.
+// Each empire has at most one rebellion per step.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rebellion {
+    // Smallest cut off territory becoming independent, 0 keeps them all
+    pub split_size: usize,
+    // Chance per step that a cell without control rebels, 0-1. It falls linearly to 0 at the
+    // threshold.
+    pub rate: f64,
+    // Control below which a cell may rebel
+    pub threshold: f64,
+}
+
+impl Default for Rebellion {
+    fn default() -> Self {
+        Rebellion { split_size: 5, rate: 0.001, threshold: 0.3 }
+    }
+}
+
+// Relation of an empire towards another one
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Relation {
+    // Fight each other
+    War,
+    Peace,
+    // At peace, and allied against their common enemies
+    Alliance,
+    // Subject to the other empire: never fights it and leaves it the free cells next to it
+    Vassal,
+    Overlord,
+}
+
+impl Relation {
+    // The same relation seen from the other empire
+    pub fn reverse(self) -> Self {
+        match self {
+            Relation::Vassal => Relation::Overlord,
+            Relation::Overlord => Relation::Vassal,
+            r => r,
+        }
+    }
+}
+
+// Relations between pairs of empires. Only the pairs set are stored, the others have the default
+// relation of the simulation (see EmpireSimulation::relation).
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Relations {
+    // (a, b) with a < b: the relation of a towards b
+    pairs: BTreeMap<(EmpireId, EmpireId), Relation>,
+}
+
+impl Relations {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, a: EmpireId, b: EmpireId) -> Option<Relation> {
+        if a <= b {
+            self.pairs.get(&(a, b)).copied()
+        } else {
+            self.pairs.get(&(b, a)).map(|r| r.reverse())
+        }
+    }
+
+    pub fn set(&mut self, a: EmpireId, b: EmpireId, relation: Relation) {
+        if a <= b {
+            self.pairs.insert((a, b), relation);
+        } else {
+            self.pairs.insert((b, a), relation.reverse());
+        }
+    }
+
+    // Drop the relations of an empire, e.g. eliminated
+    pub fn forget(&mut self, id: EmpireId) {
+        self.pairs.retain(|&(a, b), _| a != id && b != id);
+    }
+
+    // (a, b, relation of a towards b) with a < b, in order
+    pub fn iter(&self) -> impl Iterator<Item = (EmpireId, EmpireId, Relation)> + '_ {
+        self.pairs.iter().map(|(&(a, b), &r)| (a, b, r))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+}
+
+// Simple rules changing the relations of the empires sharing a border, at every step. With s the
+// strength of the stronger empire over the weaker one, each rule applies with a chance of `rate`:
+//   peace: war when s >= war_ratio (chance times the aggression of the stronger), or alliance
+//          when they have a common enemy
+//   war: the weaker becomes a vassal when s >= vassal_ratio, peace when s < war_ratio
+//   alliance: peace when they have no common enemy left
+//   vassal: war when the vassal is the stronger
+// Without it there is no diplomacy: all the empires are at war, unless told otherwise.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Diplomacy {
+    pub rate: f64,
+    pub war_ratio: f64,
+    pub vassal_ratio: f64,
+}
+
+impl Default for Diplomacy {
+    fn default() -> Self {
+        Diplomacy { rate: 0.05, war_ratio: 1.5, vassal_ratio: 4.0 }
+    }
+}
+
+// A made up name for a new empire
+pub fn random_name(rng: &mut SimRng) -> String {
+    const SYLLABLES: [&str; 20] = ["ka", "ro", "ma", "ti", "va", "len", "dor", "sa", "mi", "tha",
+                                   "bel", "ur", "go", "na", "zan", "el", "tor", "ri", "mon", "qua"];
+    const ENDINGS: [&str; 6] = ["ia", "or", "an", "um", "eth", "is"];
+    let mut name: String = (0..rng.random_range(1..=2)).map(|_| SYLLABLES[rng.random_range(0..SYLLABLES.len())]).collect();
+    name.push_str(ENDINGS[rng.random_range(0..ENDINGS.len())]);
+    name[..1].to_uppercase() + &name[1..]
+}
+
+// How the distance of a cell from the capital is measured
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Reach {
+    // Straight hex distance, across sea and foreign land
+    #[default]
+    Hex,
+    // Steps through the cells of the empire, cells cut off from the capital have no control
+    Path,
+}
+
+// Control of an empire over its cells: 1 at the capital, falling with the distance d as
+// 1 / (1 + d / range), so it is halved at `range` cells
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Control {
+    pub reach: Reach,
+    pub range: f64,
+}
+
+impl Default for Control {
+    fn default() -> Self {
+        Control { reach: Reach::Hex, range: 20.0 }
+    }
+}
+
+impl Control {
+    pub fn at(&self, distance: f64) -> f64 {
+        1.0 / (1.0 + distance / self.range)
+    }
+}
+
+// The living empires. Ids are indices of a growable table; the id of an eliminated empire is
+// free again and the lowest free id is taken first, so the table stays as small as the largest
+// number of empires alive at once.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EmpireRegistry {
+    slots: Vec<Option<Empire>>,
+    free: BTreeSet<EmpireId>,
+}
+
+impl EmpireRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Add an empire founded at the given generation, the name defaults to "Empire <id>"
+    pub fn insert(&mut self, name: Option<String>, founded: u64, capital: (usize, usize), attributes: Attributes) -> EmpireId {
+        let id = self.free.pop_first().unwrap_or_else(|| {
+            self.slots.push(None);
+            (self.slots.len() - 1) as EmpireId
+        });
+        self.slots[id as usize] = Some(Empire { id, name: name.unwrap_or_else(|| format!("Empire {}", id)), founded, capital, attributes });
+        id
+    }
+
+    // Put back an empire with its id, e.g. read from a snapshot. Replaces the empire with that id.
+    pub fn restore(&mut self, empire: Empire) {
+        let id = empire.id as usize;
+        while self.slots.len() <= id {
+            self.free.insert(self.slots.len() as EmpireId);
+            self.slots.push(None);
+        }
+        self.free.remove(&empire.id);
+        self.slots[id] = Some(empire);
+    }
+
+    pub fn remove(&mut self, id: EmpireId) -> Option<Empire> {
+        let empire = self.slots.get_mut(id as usize)?.take()?;
+        self.free.insert(id);
+        Some(empire)
+    }
+
+    pub fn get(&self, id: EmpireId) -> Option<&Empire> {
+        self.slots.get(id as usize)?.as_ref()
+    }
+
+    pub fn get_mut(&mut self, id: EmpireId) -> Option<&mut Empire> {
+        self.slots.get_mut(id as usize)?.as_mut()
+    }
+
+    // By id
+    pub fn iter(&self) -> impl Iterator<Item = &Empire> {
+        self.slots.iter().flatten()
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Empire> {
+        self.slots.iter_mut().flatten()
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Size of the table, every id is below it
+    pub fn id_bound(&self) -> usize {
+        self.slots.len()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum EmpireEvent {
+    Founded { empire: EmpireId, x: usize, y: usize },
+    Captured { empire: EmpireId, x: usize, y: usize },
+    // Taken by war from another empire
+    Conquered { empire: EmpireId, from: EmpireId, x: usize, y: usize },
+    // The capital was lost, the new one is (x, y)
+    CapitalMoved { empire: EmpireId, x: usize, y: usize },
+    // A territory cut off from the capital became the new empire
+    Split { empire: EmpireId, from: EmpireId, cells: usize },
+    // The new empire rose up at (x, y)
+    Rebelled { empire: EmpireId, from: EmpireId, x: usize, y: usize },
+    // The relation of the empire towards the other one is now `relation`
+    RelationChanged { empire: EmpireId, other: EmpireId, relation: Relation },
+    Eliminated { empire: EmpireId },
+}
+
+// Shape of the generated maps
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TerrainPreset {
+    // The noise as it is, land up to the edges of the map
+    #[default]
+    Open,
+    // One landmass in the middle fading into the sea at the edges, half of the map if no land
+    // fraction is given
+    Continent,
+    // Many small islands in a sea all around the map, a quarter of the map if neither a sea
+    // level nor a land fraction is given. The noise is three times the frequency.
+    Islands,
+}
+
+impl TerrainPreset {
+    // Multiplies the frequency of the generator
+    pub fn frequency_scale(self) -> f64 {
+        match self {
+            TerrainPreset::Open | TerrainPreset::Continent => 1.0,
+            TerrainPreset::Islands => 3.0,
+        }
+    }
+
+    fn land_fraction(self) -> Option<f64> {
+        match self {
+            TerrainPreset::Open => None,
+            TerrainPreset::Continent => Some(0.5),
+            TerrainPreset::Islands => Some(0.25),
+        }
+    }
+
+    // Added to the noise, u and v from -1 to 1 between the edges: lower than any noise at the
+    // edges, so that they are sea
+    fn falloff(self, u: f64, v: f64) -> f64 {
+        let edge = -2.0 * u.abs().max(v.abs()).powi(8);
+        match self {
+            TerrainPreset::Open => 0.0,
+            TerrainPreset::Continent => 0.5 - (u * u + v * v) + edge,
+            TerrainPreset::Islands => edge,
+        }
+    }
+}
+
+// Fractal Perlin elevation, the same for the same parameters on any machine. The land is the
+// highest land_fraction of the cells when it is set, or above the sea level, or the land fraction
+// of the preset when neither is set, or above 0. Built from a
+// TerrainSpec for the scenarios, or by hand:
+//   TerrainGenerator::new(42).with_preset(TerrainPreset::Islands).terrain(&geo, |_, _| true)
+#[derive(Clone, Debug, PartialEq)]
+pub struct TerrainGenerator {
+    seed: u32,
+    octaves: usize,
+    frequency: f64,
+    persistence: f64,
+    lacunarity: f64,
+    sea_level: Option<f64>,
+    land_fraction: Option<f64>,
+    preset: TerrainPreset,
+}
+
+impl TerrainGenerator {
+    // noise::Fbm defaults
+    pub fn new(seed: u32) -> Self {
+        let spec = TerrainSpec::default();
+        Self {
+            seed,
+            octaves: spec.octaves,
+            frequency: spec.frequency,
+            persistence: spec.persistence,
+            lacunarity: spec.lacunarity,
+            sea_level: None,
+            land_fraction: None,
+            preset: TerrainPreset::Open,
+        }
+    }
+
+    // The seed is drawn from rng when the spec has none
+    pub fn from_spec(spec: &TerrainSpec, rng: &mut SimRng) -> Self {
+        TerrainGenerator::new(spec.seed.unwrap_or_else(|| rng.random()))
+            .with_octaves(spec.octaves)
+            .with_frequency(spec.frequency)
+            .with_persistence(spec.persistence)
+            .with_lacunarity(spec.lacunarity)
+            .with_sea_level(spec.sea_level)
+            .with_land_fraction(spec.land_fraction)
+            .with_preset(spec.preset)
+    }
+
+    pub fn with_octaves(mut self, octaves: usize) -> Self {
+        self.octaves = octaves;
+        self
+    }
+
+    pub fn with_frequency(mut self, frequency: f64) -> Self {
+        self.frequency = frequency;
+        self
+    }
+
+    pub fn with_persistence(mut self, persistence: f64) -> Self {
+        self.persistence = persistence;
+        self
+    }
+
+    pub fn with_lacunarity(mut self, lacunarity: f64) -> Self {
+        self.lacunarity = lacunarity;
+        self
+    }
+
+    // Ignored with a land fraction, the preset one if None
+    pub fn with_sea_level(mut self, sea_level: Option<f64>) -> Self {
+        self.sea_level = sea_level;
+        self
+    }
+
+    // 0-1 of the cells inside the mask
+    pub fn with_land_fraction(mut self, land_fraction: Option<f64>) -> Self {
+        self.land_fraction = land_fraction;
+        self
+    }
+
+    pub fn with_preset(mut self, preset: TerrainPreset) -> Self {
+        self.preset = preset;
+        self
+    }
+
+    pub fn seed(&self) -> u32 {
+        self.seed
+    }
+
+    pub fn preset(&self) -> TerrainPreset {
+        self.preset
+    }
+
+    // Of the noise, scaled by the preset
+    pub fn frequency(&self) -> f64 {
+        self.frequency * self.preset.frequency_scale()
+    }
+
+    fn fbm(&self, seed: u32) -> noise::Fbm<noise::Perlin> {
+        noise::Fbm::<noise::Perlin>::new(seed)
+            .set_octaves(self.octaves)
+            .set_frequency(self.frequency())
+            .set_persistence(self.persistence)
+            .set_lacunarity(self.lacunarity)
+    }
+
+    // Noise of every cell, [row][column]: -INFINITY outside of the mask
+    fn field(&self, geo: &HexGeometry, seed: u32, mask: impl Fn(usize, usize) -> bool) -> Vec<Vec<f64>> {
+        let noise = self.fbm(seed);
+        let lowest_max = geo.cols.min(geo.rows) as f64;
+        (0..geo.rows).map(|y| (0..geo.cols).map(|x| {
+            if !mask(x, y) {
+                return f64::NEG_INFINITY;
+            }
+            // Generally noises are made to work between -1 and 1 and output -1 to 1
+            noise.get([x as f64 / lowest_max, y as f64 / lowest_max])
+        }).collect()).collect()
+    }
+
+    // Elevation of every cell, [row][column], -INFINITY outside of the mask
+    pub fn elevation(&self, geo: &HexGeometry, mask: impl Fn(usize, usize) -> bool) -> Vec<Vec<f64>> {
+        let mut elevation = self.field(geo, self.seed, mask);
+        for (y, row) in elevation.iter_mut().enumerate() {
+            let v = 2.0 * (y as f64 + 0.5) / geo.rows as f64 - 1.0;
+            for (x, e) in row.iter_mut().enumerate() {
+                *e += self.preset.falloff(2.0 * (x as f64 + 0.5) / geo.cols as f64 - 1.0, v);
+            }
+        }
+        elevation
+    }
+
+    // Elevation from which the cells are land
+    pub fn sea_level(&self, elevation: &[Vec<f64>]) -> f64 {
+        let fraction = match (self.land_fraction, self.sea_level) {
+            (Some(fraction), _) => fraction,
+            (None, Some(sea_level)) => return sea_level,
+            (None, None) => match self.preset.land_fraction() {
+                Some(fraction) => fraction,
+                None => return 0.0,
+            },
+        };
+        let mut cells: Vec<f64> = elevation.iter().flatten().copied().filter(|e| e.is_finite()).collect();
+        cells.sort_by(|a, b| b.total_cmp(a));
+        let land = (fraction.clamp(0.0, 1.0) * cells.len() as f64).round() as usize;
+        if land == 0 { f64::INFINITY } else { cells[land - 1] }
+    }
+
+    // Earth on the land, sea elsewhere
+    pub fn terrain(&self, geo: &HexGeometry, mask: impl Fn(usize, usize) -> bool) -> Vec<Vec<Terrain>> {
+        let elevation = self.elevation(geo, mask);
+        let sea_level = self.sea_level(&elevation);
+        elevation.iter().map(|row| row.iter().map(|&e| if e >= sea_level { Terrain::Earth } else { Terrain::Sea }).collect()).collect()
+    }
+
+    // Land with biomes and rivers: the moisture is another noise, the temperature falls with the
+    // latitude and the height. By priority, high land is mountain, cold land tundra, hot and dry
+    // land desert, wet land forest and the rest plains. Rivers start on random high and wet cells,
+    // drawn from the seed too, and flow to the lowest neighbour until the sea, another river or a
+    // pit.
+    pub fn land(&self, geo: &HexGeometry, mask: impl Fn(usize, usize) -> bool) -> Land {
+        let mut rng = rng::seeded(self.seed.into());
+        let elevation = self.elevation(geo, mask);
+        let sea_level = self.sea_level(&elevation);
+        let highest = elevation.iter().flatten().copied().fold(sea_level, f64::max);
+        let height = |e: f64| ((e - sea_level) / (highest - sea_level).max(f64::EPSILON)).clamp(0.0, 1.0);
+        let moisture: Vec<Vec<f64>> = self.field(geo, self.seed.wrapping_add(1), |_, _| true).into_iter()
+            .map(|row| row.into_iter().map(|m| ((m + 1.0) / 2.0).clamp(0.0, 1.0)).collect()).collect();
+        let temperature: Vec<Vec<f64>> = (0..geo.rows).map(|y| (0..geo.cols).map(|x| {
+            let latitude = (2.0 * (y as f64 + 0.5) / geo.rows as f64 - 1.0).abs();
+            (1.0 - latitude - 0.5 * height(elevation[y][x])).clamp(0.0, 1.0)
+        }).collect()).collect();
+        let terrain: Vec<Vec<Terrain>> = (0..geo.rows).map(|y| (0..geo.cols).map(|x| {
+            let (e, m, t) = (elevation[y][x], moisture[y][x], temperature[y][x]);
+            if e < sea_level {
+                Terrain::Sea
+            } else if height(e) >= MOUNTAIN_HEIGHT {
+                Terrain::Mountain
+            } else if t < 0.2 {
+                Terrain::Tundra
+            } else if t > 0.6 && m < 0.35 {
+                Terrain::Desert
+            } else if m > 0.55 {
+                Terrain::Forest
+            } else {
+                Terrain::Plains
+            }
+        }).collect()).collect();
+
+        let mut rivers = vec![vec![false; geo.cols]; geo.rows];
+        for y in 0..geo.rows {
+            for x in 0..geo.cols {
+                if !terrain[y][x].is_land() || height(elevation[y][x]) < 0.3 || moisture[y][x] < 0.5 || rivers[y][x] { continue }
+                if !rng.random_bool(RIVER_SOURCES) { continue }
+                let (mut cx, mut cy) = (x, y);
+                loop {
+                    rivers[cy][cx] = true;
+                    let lowest = geo.neighbours(OffsetCoord{x: cx as isize, y: cy as isize}.axial()).into_iter()
+                        .map(|n| n.offset())
+                        .map(|o| (o.x as usize, o.y as usize))
+                        .min_by(|a, b| elevation[a.1][a.0].total_cmp(&elevation[b.1][b.0]));
+                    let Some((nx, ny)) = lowest else { break };
+                    if elevation[ny][nx] >= elevation[cy][cx] || !terrain[ny][nx].is_land() || rivers[ny][nx] { break }
+                    (cx, cy) = (nx, ny);
+                }
+            }
+        }
+        Land { elevation, sea_level, moisture, temperature, terrain, rivers }
+    }
+}
+
+// Earth where the noise is above the sea level and `mask` allows it, see TerrainGenerator
+pub fn noise_terrain(geo: &HexGeometry, spec: &TerrainSpec, mask: impl Fn(usize, usize) -> bool, rng: &mut SimRng) -> Vec<Vec<Terrain>> {
+    TerrainGenerator::from_spec(spec, rng).terrain(geo, mask)
+}
+
+// A generated map, [row][column]
+#[derive(Clone, Debug, PartialEq)]
+pub struct Land {
+    // Fbm noise, the sea is below sea_level
+    pub elevation: Vec<Vec<f64>>,
+    pub sea_level: f64,
+    // 0-1
+    pub moisture: Vec<Vec<f64>>,
+    // 0-1, cold towards the top and bottom rows and on high land
+    pub temperature: Vec<Vec<f64>>,
+    pub terrain: Vec<Vec<Terrain>>,
+    pub rivers: Vec<Vec<bool>>,
+}
+
+// Height over the sea, 0-1 up to the highest cell, from which the mountains start
+const MOUNTAIN_HEIGHT: f64 = 0.5;
+// Chance of a river source on high and wet land
+const RIVER_SOURCES: f64 = 0.05;
+
+// Land with biomes and rivers on the noise terrain of the spec, see TerrainGenerator::land
+pub fn biome_land(geo: &HexGeometry, spec: &TerrainSpec, mask: impl Fn(usize, usize) -> bool, rng: &mut SimRng) -> Land {
+    TerrainGenerator::from_spec(spec, rng).land(geo, mask)
+}
+
+pub struct EmpireSimulation<'a> {
+    pub(crate) geo: &'a HexGeometry,
+    // [y:0-rows][x:0-cols]. External is the row, internal is col/cell
+    pub terrain: Vec<Vec<Terrain>>,
+    pub rivers: Vec<Vec<bool>>,
+    pub owners: Vec<Vec<Owner>>,
+    pub(crate) empires: EmpireRegistry,
+    pub(crate) rng: SimRng,
+    pub(crate) generation: u64,
+    pub(crate) conflict: Conflict,
+    pub(crate) warfare: Option<Warfare>,
+    pub(crate) control: Control,
+    pub(crate) rebellion: Option<Rebellion>,
+    pub(crate) diplomacy: Option<Diplomacy>,
+    pub(crate) relations: Relations,
+    observers: Observers<Owner, EmpireEvent>,
+}
+
+impl<'a> EmpireSimulation<'a> {
+    // Map without empires, terrain is [row][column]
+    pub fn new(geo: &'a HexGeometry, terrain: Vec<Vec<Terrain>>, rng: SimRng) -> Self {
+        assert!(terrain.len() == geo.rows && terrain.iter().all(|row| row.len() == geo.cols), "The terrain must be rows x cols");
+        EmpireSimulation {
+            geo,
+            terrain,
+            rivers: vec![vec![false; geo.cols]; geo.rows],
+            owners: vec![vec![None; geo.cols]; geo.rows],
+            empires: EmpireRegistry::new(),
+            rng,
+            generation: 0,
+            conflict: Conflict::default(),
+            warfare: None,
+            control: Control::default(),
+            rebellion: None,
+            diplomacy: None,
+            relations: Relations::new(),
+            observers: Observers::new(),
+        }
+    }
+
+    // Map with the terrain and rivers of generated land
+    pub fn from_land(geo: &'a HexGeometry, land: Land, rng: SimRng) -> Self {
+        EmpireSimulation::new(geo, land.terrain, rng).with_rivers(land.rivers)
+    }
+
+    pub fn with_rivers(mut self, rivers: Vec<Vec<bool>>) -> Self {
+        assert!(rivers.len() == self.geo.rows && rivers.iter().all(|row| row.len() == self.geo.cols), "The rivers must be rows x cols");
+        self.rivers = rivers;
+        self
+    }
+
+    pub fn with_conflict(mut self, conflict: Conflict) -> Self {
+        self.conflict = conflict;
+        self
+    }
+
+    pub fn conflict(&self) -> Conflict {
+        self.conflict
+    }
+
+    // Let empires conquer each other's cells
+    pub fn with_warfare(mut self, warfare: Warfare) -> Self {
+        self.warfare = Some(warfare);
+        self
+    }
+
+    pub fn warfare(&self) -> Option<Warfare> {
+        self.warfare
+    }
+
+    pub fn with_control(mut self, control: Control) -> Self {
+        self.control = control;
+        self
+    }
+
+    pub fn control(&self) -> Control {
+        self.control
+    }
+
+    // Let empires split and rebel
+    pub fn with_rebellion(mut self, rebellion: Rebellion) -> Self {
+        self.rebellion = Some(rebellion);
+        self
+    }
+
+    pub fn rebellion(&self) -> Option<Rebellion> {
+        self.rebellion
+    }
+
+    // Let empires make war and peace by themselves
+    pub fn with_diplomacy(mut self, diplomacy: Diplomacy) -> Self {
+        self.diplomacy = Some(diplomacy);
+        self
+    }
+
+    pub fn diplomacy(&self) -> Option<Diplomacy> {
+        self.diplomacy
+    }
+
+    // Relation of empire a towards empire b. Empires never set are at peace with Diplomacy and at
+    // war without. An empire is its own ally.
+    pub fn relation(&self, a: EmpireId, b: EmpireId) -> Relation {
+        if a == b {
+            return Relation::Alliance;
+        }
+        self.relations.get(a, b).unwrap_or(if self.diplomacy.is_some() { Relation::Peace } else { Relation::War })
+    }
+
+    // Force the relation of empire a towards empire b, b gets the reverse one.
+    // Ignored unless both empires are alive, a later empire with the id starts without it.
+    pub fn set_relation(&mut self, a: EmpireId, b: EmpireId, relation: Relation) {
+        if a == b || self.empires.get(a).is_none() || self.empires.get(b).is_none() || self.relation(a, b) == relation {
+            return;
+        }
+        self.relations.set(a, b, relation);
+        self.observers.event(&EmpireEvent::RelationChanged { empire: a, other: b, relation });
+    }
+
+    pub fn relations(&self) -> &Relations {
+        &self.relations
+    }
+
+    // The connected territories of the empires: owner and cells, by first cell in row order
+    pub fn territories(&self) -> Vec<(EmpireId, Vec<(usize, usize)>)> {
+        let mut seen = vec![vec![false; self.geo.cols]; self.geo.rows];
+        let mut territories = Vec::new();
+        for y in 0..self.geo.rows {
+            for x in 0..self.geo.cols {
+                let Some(id) = self.owners[y][x] else { continue };
+                if seen[y][x] { continue }
+                // Flood fill over the cells of the same owner
+                seen[y][x] = true;
+                let mut cells = vec![(x, y)];
+                let mut next = 0;
+                while next < cells.len() {
+                    let (cx, cy) = cells[next];
+                    next += 1;
+                    for n in self.geo.neighbours(OffsetCoord{x: cx as isize, y: cy as isize}.axial()) {
+                        let o = n.offset();
+                        let (nx, ny) = (o.x as usize, o.y as usize);
+                        if !seen[ny][nx] && self.owners[ny][nx] == Some(id) {
+                            seen[ny][nx] = true;
+                            cells.push((nx, ny));
+                        }
+                    }
+                }
+                territories.push((id, cells));
+            }
+        }
+        territories
+    }
+
+    // Control of every cell by its owner, 0 for free cells, [row][column]
+    pub fn control_map(&self) -> Vec<Vec<f64>> {
+        let mut control = vec![vec![0.0; self.geo.cols]; self.geo.rows];
+        match self.control.reach {
+            Reach::Hex => {
+                for (y, row) in control.iter_mut().enumerate() {
+                    for (x, c) in row.iter_mut().enumerate() {
+                        let Some(e) = self.owners[y][x].and_then(|id| self.empires.get(id)) else { continue };
+                        let capital = OffsetCoord{x: e.capital.0 as isize, y: e.capital.1 as isize}.axial();
+                        let distance = self.geo.distance(OffsetCoord{x: x as isize, y: y as isize}.axial(), capital);
+                        *c = self.control.at(distance as f64);
+                    }
+                }
+            }
+            Reach::Path => {
+                // Breadth first from all the capitals at once, each through its own cells
+                let mut queue = VecDeque::new();
+                let mut seen = vec![vec![false; self.geo.cols]; self.geo.rows];
+                for e in self.empires() {
+                    let (x, y) = e.capital;
+                    if self.owners[y][x] == Some(e.id) {
+                        seen[y][x] = true;
+                        queue.push_back((x, y, 0_u32));
+                    }
+                }
+                while let Some((x, y, distance)) = queue.pop_front() {
+                    control[y][x] = self.control.at(distance as f64);
+                    for n in self.geo.neighbours(OffsetCoord{x: x as isize, y: y as isize}.axial()) {
+                        let o = n.offset();
+                        let (nx, ny) = (o.x as usize, o.y as usize);
+                        if !seen[ny][nx] && self.owners[ny][nx] == self.owners[y][x] {
+                            seen[ny][nx] = true;
+                            queue.push_back((nx, ny, distance + 1));
+                        }
+                    }
+                }
+            }
+        }
+        control
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn add_observer(&mut self, observer: Box<dyn StepObserver<Owner, EmpireEvent>>) {
+        self.observers.add(observer);
+    }
+
+    pub fn empire(&self, id: EmpireId) -> Option<&Empire> {
+        self.empires.get(id)
+    }
+
+    // The living empires, by id
+    pub fn empires(&self) -> impl Iterator<Item = &Empire> {
+        self.empires.iter()
+    }
+
+    // Free land, of any biome
+    pub fn is_free_earth(&self, x: usize, y: usize) -> bool {
+        self.terrain[y][x].is_land() && self.owners[y][x].is_none()
+    }
+
+    // Cost of expanding into the cell, see Terrain::cost
+    pub fn cost(&self, x: usize, y: usize) -> f64 {
+        self.terrain[y][x].cost() * if self.rivers[y][x] { RIVER_COST } else { 1.0 }
+    }
+
+    // Multiplier of the strength defending the cell, see Terrain::defence
+    pub fn defence(&self, x: usize, y: usize) -> f64 {
+        self.terrain[y][x].defence() * if self.rivers[y][x] { RIVER_DEFENCE } else { 1.0 }
+    }
+
+    // Cell state for the views, see owner_state
+    pub fn state(&self, x: usize, y: usize) -> u32 {
+        match self.owners[y][x] {
+            Some(id) => owner_state(id),
+            None if self.rivers[y][x] && self.terrain[y][x].is_land() => RIVER,
+            None => self.terrain[y][x] as u32,
+        }
+    }
+
+    // Number of cells of every empire, by id
+    pub fn sizes(&self) -> Vec<usize> {
+        let mut cells = vec![0_usize; self.empires.id_bound()];
+        for id in self.owners.iter().flatten().flatten() {
+            cells[*id as usize] += 1;
+        }
+        cells
+    }
+
+    // The empire capturing a free cell, from the empires around it with their neighbouring cells
+    fn resolve(&mut self, around: &[(EmpireId, u8, f64)], sizes: &[usize]) -> Option<EmpireId> {
+        if self.conflict == Conflict::Weighted {
+            let total: u32 = around.iter().map(|&(_, n, _)| n as u32).sum();
+            if total == 0 {
+                return None;
+            }
+            let mut pick = self.rng.random_range(0..total);
+            for &(id, n, _) in around {
+                if pick < n as u32 {
+                    return Some(id);
+                }
+                pick -= n as u32;
+            }
+            unreachable!("The pick is below the total");
+        }
+        let most = around.iter().map(|&(_, n, _)| n).max()?;
+        let mut tied = around.iter().filter(|&&(_, n, _)| n == most).map(|&(id, _, _)| id);
+        let founded = |id: EmpireId| self.empires.get(id).map_or(u64::MAX, |e| e.founded);
+        // The last keys keep the choice independent of the order of the neighbours
+        match self.conflict {
+            Conflict::Weighted => unreachable!(),
+            Conflict::Strongest => tied.min_by_key(|&id| (std::cmp::Reverse(sizes[id as usize]), founded(id), id)),
+            Conflict::Oldest => tied.min_by_key(|&id| (founded(id), id)),
+            Conflict::KeepOnTie => {
+                let first = tied.next();
+                if tied.next().is_some() { None } else { first }
+            }
+        }
+    }
+
+    // The empire conquering the owned cell (x, y) from its owner, see Warfare.
+    // `around` has the empires nearby with their cells and best control around this one.
+    fn battle(&mut self, x: usize, y: usize, defender: EmpireId, around: &[(EmpireId, u8, f64)], sizes: &[usize], control: f64) -> Option<EmpireId> {
+        let warfare = self.warfare?;
+        let force = |id: EmpireId, cells: u8, control: f64| {
+            let Some(e) = self.empires.get(id) else { return (0.0, 0.0) };
+            (e.attributes.strength / sizes[id as usize].max(1) as f64 * cells as f64 * control, e.attributes.aggression)
+        };
+        let own = around.iter().find(|&&(id, _, _)| id == defender).map_or(0, |&(_, n, _)| n);
+        let defence = force(defender, own + 1, control).0 * self.defence(x, y);
+        // Strongest attacker, the lowest id on ties
+        let (attacker, attack) = around.iter()
+            .filter(|&&(id, _, _)| self.relation(id, defender) == Relation::War)
+            .map(|&(id, n, c)| {
+                let (f, aggression) = force(id, n, c);
+                (id, f * (0.5 + aggression))
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1).then(b.0.cmp(&a.0)))?;
+        if attack <= 0.0 {
+            return None;
+        }
+        let odds = (attack / defence).powf(warfare.sharpness);
+        let chance = warfare.rate * if odds.is_finite() { odds / (1.0 + odds) } else { 1.0 };
+        (self.rng.random::<f64>() < chance).then_some(attacker)
+    }
+
+    pub fn step(&mut self) {
+        let prev_owners = self.owners.clone();
+        let sizes = self.sizes();
+        let control = if self.warfare.is_some() { self.control_map() } else { Vec::new() };
+        for y in 0..self.geo.rows {
+            for x in 0..self.geo.cols {
+                if self.terrain[y][x] == Terrain::Sea { continue }
+                let defender = prev_owners[y][x];
+                if defender.is_some() && self.warfare.is_none() { continue }
+                let neighbours = self.geo.neighbours(OffsetCoord{x: x as isize,y: y as isize}.axial());
+                // Empires nearby with their number of cells around this one and their best control
+                // of them (with warfare only)
+                let mut around = [(0, 0_u8, 0.0); 6];
+                let mut len = 0;
+                for axcord in neighbours.iter() {
+                    let o = axcord.offset();
+                    let Some(empire_id) = prev_owners[o.y as usize][o.x as usize] else { continue };
+                    let c = control.get(o.y as usize).map_or(0.0, |row| row[o.x as usize]);
+                    if let Some(e) = around[..len].iter_mut().find(|(id, _, _)| *id == empire_id) {
+                        e.1 += 1;
+                        e.2 = f64::max(e.2, c);
+                    } else {
+                        around[len] = (empire_id, 1, c);
+                        len += 1;
+                    }
+                }
+                if len == 0 {
+                    // No empire nearby
+                    continue;
+                }
+                if let Some(defender) = defender {
+                    let Some(id) = self.battle(x, y, defender, &around[..len], &sizes, control[y][x]) else { continue };
+                    if !self.observers.is_empty() {
+                        self.observers.cell_changed(y * self.geo.cols + x, &Some(defender), &Some(id));
+                        self.observers.event(&EmpireEvent::Conquered { empire: id, from: defender, x, y });
+                    }
+                    self.owners[y][x] = Some(id);
+                    continue;
+                }
+                // Vassals leave the free cells next to their overlord to it
+                if !self.relations.is_empty() {
+                    let all = around;
+                    let mut kept = 0;
+                    for &e in all[..len].iter() {
+                        if !all[..len].iter().any(|&(other, _, _)| self.relation(e.0, other) == Relation::Vassal) {
+                            around[kept] = e;
+                            kept += 1;
+                        }
+                    }
+                    len = kept;
+                }
+                let Some(id) = self.resolve(&around[..len], &sizes) else { continue };
+                let chance = self.empires.get(id).map_or(1.0, |e| e.attributes.expansion_chance(sizes[id as usize])) / self.cost(x, y);
+                // Certain captures draw nothing, so empires always expanding are not random
+                if chance < 1.0 && !self.rng.random_bool(chance) {
+                    continue;
+                }
+                if !self.observers.is_empty() {
+                    self.observers.cell_changed(y * self.geo.cols + x, &None, &Some(id));
+                    self.observers.event(&EmpireEvent::Captured { empire: id, x, y });
+                }
+                self.owners[y][x] = Some(id);
+            }
+        }
+        // An empire left without cells is dead, free its id
+        let cells = self.sizes();
+        let dead: Vec<EmpireId> = self.empires.iter().map(|e| e.id).filter(|&id| cells[id as usize] == 0).collect();
+        for id in dead {
+            self.empires.remove(id);
+            self.relations.forget(id);
+            self.observers.event(&EmpireEvent::Eliminated { empire: id });
+        }
+        for e in self.empires.iter_mut() {
+            e.attributes.evolve(cells[e.id as usize]);
+        }
+        self.move_lost_capitals();
+        if let Some(rebellion) = self.rebellion {
+            self.break_up(rebellion);
+        }
+        if let Some(diplomacy) = self.diplomacy {
+            self.negotiate(diplomacy);
+        }
+        self.generation += 1;
+        self.observers.generation_end(self.generation);
+    }
+
+    // An empire which lost its capital moves it to its nearest cell, the first in row order on ties
+    fn move_lost_capitals(&mut self) {
+        let lost: Vec<EmpireId> = self.empires.iter().filter(|e| self.owners[e.capital.1][e.capital.0] != Some(e.id)).map(|e| e.id).collect();
+        if lost.is_empty() {
+            return;
+        }
+        let mut is_lost = vec![false; self.empires.id_bound()];
+        for &id in lost.iter() {
+            is_lost[id as usize] = true;
+        }
+        let mut nearest: Vec<Option<(f32, usize, usize)>> = vec![None; self.empires.id_bound()];
+        for (y, row) in self.owners.iter().enumerate() {
+            for (x, owner) in row.iter().enumerate() {
+                let Some(id) = *owner else { continue };
+                if !is_lost[id as usize] { continue }
+                let (cx, cy) = self.empires.get(id).map(|e| e.capital).unwrap_or((x, y));
+                let distance = self.geo.distance(OffsetCoord{x: x as isize, y: y as isize}.axial(), OffsetCoord{x: cx as isize, y: cy as isize}.axial());
+                let best = &mut nearest[id as usize];
+                if best.is_none_or(|(d, _, _)| distance < d) {
+                    *best = Some((distance, x, y));
+                }
+            }
+        }
+        for id in lost {
+            let Some((_, x, y)) = nearest[id as usize] else { continue };
+            if let Some(e) = self.empires.get_mut(id) {
+                e.capital = (x, y);
+            }
+            self.observers.event(&EmpireEvent::CapitalMoved { empire: id, x, y });
+        }
+    }
+
+    // Give cells of `from` to a new empire with a random name and the capital on the first cell,
+    // keeping `sizes` up to date. The cells take their share of its population and strength.
+    fn secede(&mut self, from: EmpireId, cells: &[(usize, usize)], sizes: &mut Vec<usize>) -> EmpireId {
+        let name = random_name(&mut self.rng);
+        let share = cells.len() as f64 / sizes[from as usize].max(1) as f64;
+        let mut attributes = self.empires.get(from).map_or_else(Attributes::default, |e| e.attributes);
+        attributes.population *= share;
+        attributes.strength *= share;
+        if let Some(parent) = self.empires.get_mut(from) {
+            parent.attributes.population -= attributes.population;
+            parent.attributes.strength -= attributes.strength;
+        }
+        let id = self.empires.insert(Some(name), self.generation, cells[0], attributes);
+        for &(x, y) in cells {
+            self.observers.cell_changed(y * self.geo.cols + x, &Some(from), &Some(id));
+            self.owners[y][x] = Some(id);
+        }
+        sizes.resize(self.empires.id_bound(), 0);
+        sizes[from as usize] -= cells.len();
+        sizes[id as usize] = cells.len();
+        id
+    }
+
+    // Split the cut off territories, then the rebellions, see Rebellion
+    fn break_up(&mut self, rebellion: Rebellion) {
+        let mut sizes = self.sizes();
+        // Empires which split, had a rebellion, or rose up, in this step
+        let mut risen = BTreeSet::new();
+        if rebellion.split_size > 0 {
+            for (owner, cells) in self.territories() {
+                let Some(e) = self.empires.get(owner) else { continue };
+                if cells.len() < rebellion.split_size || cells.contains(&e.capital) { continue }
+                let id = self.secede(owner, &cells, &mut sizes);
+                risen.insert(id);
+                self.observers.event(&EmpireEvent::Split { empire: id, from: owner, cells: cells.len() });
+            }
+        }
+        if rebellion.rate <= 0.0 {
+            return;
+        }
+        let control = self.control_map();
+        for y in 0..self.geo.rows {
+            for x in 0..self.geo.cols {
+                let Some(owner) = self.owners[y][x] else { continue };
+                let c = control[y][x];
+                if c >= rebellion.threshold || risen.contains(&owner) { continue }
+                if self.rng.random::<f64>() >= rebellion.rate * (1.0 - c / rebellion.threshold) { continue }
+                // The cell and the cells of the empire around it without control
+                let mut cells = vec![(x, y)];
+                for n in self.geo.neighbours(OffsetCoord{x: x as isize, y: y as isize}.axial()) {
+                    let o = n.offset();
+                    let (nx, ny) = (o.x as usize, o.y as usize);
+                    if self.owners[ny][nx] == Some(owner) && control[ny][nx] < rebellion.threshold {
+                        cells.push((nx, ny));
+                    }
+                }
+                // Not the whole empire
+                if cells.len() >= sizes[owner as usize] { continue }
+                let id = self.secede(owner, &cells, &mut sizes);
+                risen.extend([owner, id]);
+                self.set_relation(id, owner, Relation::War);
+                self.observers.event(&EmpireEvent::Rebelled { empire: id, from: owner, x, y });
+            }
+        }
+    }
+
+    // Change the relations of the empires sharing a border, see Diplomacy
+    fn negotiate(&mut self, diplomacy: Diplomacy) {
+        let mut borders = BTreeSet::new();
+        for y in 0..self.geo.rows {
+            for x in 0..self.geo.cols {
+                let Some(a) = self.owners[y][x] else { continue };
+                for n in self.geo.neighbours(OffsetCoord{x: x as isize, y: y as isize}.axial()) {
+                    let o = n.offset();
+                    match self.owners[o.y as usize][o.x as usize] {
+                        Some(b) if a < b => { borders.insert((a, b)); }
+                        _ => {}
+                    }
+                }
+            }
+        }
+        let mut enemies: BTreeMap<EmpireId, BTreeSet<EmpireId>> = BTreeMap::new();
+        for (a, b, _) in self.relations.iter().filter(|r| r.2 == Relation::War) {
+            enemies.entry(a).or_default().insert(b);
+            enemies.entry(b).or_default().insert(a);
+        }
+        let common_enemy = |a: EmpireId, b: EmpireId| match (enemies.get(&a), enemies.get(&b)) {
+            (Some(ea), Some(eb)) => !ea.is_disjoint(eb),
+            _ => false,
+        };
+        let strength = |id: EmpireId| self.empires.get(id).map_or(0.0, |e| e.attributes.strength);
+        let mut changes = Vec::new();
+        for (a, b) in borders {
+            let (strong, weak) = if strength(a) >= strength(b) { (a, b) } else { (b, a) };
+            let ratio = strength(strong) / strength(weak).max(f64::MIN_POSITIVE);
+            let aggression = self.empires.get(strong).map_or(0.0, |e| e.attributes.aggression);
+            let (chance, relation) = match self.relation(weak, strong) {
+                Relation::Peace if ratio >= diplomacy.war_ratio => (diplomacy.rate * aggression, Relation::War),
+                Relation::Peace if common_enemy(a, b) => (diplomacy.rate, Relation::Alliance),
+                Relation::War if ratio >= diplomacy.vassal_ratio => (diplomacy.rate, Relation::Vassal),
+                Relation::War if ratio < diplomacy.war_ratio => (diplomacy.rate, Relation::Peace),
+                Relation::Alliance if !common_enemy(a, b) => (diplomacy.rate, Relation::Peace),
+                // The vassal of a weaker empire
+                Relation::Overlord => (diplomacy.rate, Relation::War),
+                _ => continue,
+            };
+            if self.rng.random::<f64>() < chance {
+                changes.push((weak, strong, relation));
+            }
+        }
+        for (weak, strong, relation) in changes {
+            self.set_relation(weak, strong, relation);
+        }
+    }
+
+    // A random free earth cell: a few random tries, then a search of the whole map
+    pub fn find_free_earth(&mut self) -> Option<(usize, usize)> {
+        let attempts = 100;
+        for _ in 0..attempts {
+            let x = self.rng.random_range(0..self.geo.cols);
+            let y = self.rng.random_range(0..self.geo.rows);
+            if self.is_free_earth(x, y) {
+                return Some((x, y));
+            }
+        }
+        let mut earth_cells = Vec::new();
+        for y in 0..self.geo.rows {
+            for x in 0..self.geo.cols {
+                if self.is_free_earth(x, y) {
+                    earth_cells.push((x, y));
+                }
+            }
+        }
+        if earth_cells.is_empty() {
+            return None;
+        }
+        Some(earth_cells[self.rng.random_range(0..earth_cells.len())])
+    }
+
+    // Found an empire in the given free earth cell, the name defaults to "Empire <id>".
+    // None if the cell is not free earth.
+    pub fn found_empire(&mut self, x: usize, y: usize, name: Option<String>, attributes: Attributes) -> Option<EmpireId> {
+        if !self.is_free_earth(x, y) {
+            return None;
+        }
+        let id = self.empires.insert(name, self.generation, (x, y), attributes);
+        self.observers.cell_changed(y * self.geo.cols + x, &None, &Some(id));
+        self.observers.event(&EmpireEvent::Founded { empire: id, x, y });
+        self.owners[y][x] = Some(id);
+        Some(id)
+    }
+
+    // Found an unnamed empire with random attributes on a random free earth cell
+    pub fn interact_new_random_empire(&mut self) -> Option<EmpireId> {
+        let (x, y) = self.find_free_earth()?;
+        let attributes = Attributes::random(&mut self.rng);
+        self.found_empire(x, y, None, attributes)
+    }
+
+    // The empire owning the cell under a point in world coordinates
+    pub fn get_empire_by_pos(&self, pos: Vector2) -> Option<&Empire> {
+        let (x, y) = self.geo.cell_at(pos)?;
+        self.empire(self.owners[y][x]?)
+    }
+}
+
+// Distance of the earth cells from the coast: the coast becomes 2, then one more ring inland
+// every step with the distance + 1. Sea stays SEA, land not reached yet stays EARTH.
+pub struct CoastDistance<'a> {
+    pub(crate) geo: &'a HexGeometry,
+    pub states: Vec<Vec<u32>>, // [y:0-rows][x:0-cols]. External is the row, internal is col/cell
+}
+
+impl<'a> CoastDistance<'a> {
+    pub fn new(geo: &'a HexGeometry, terrain: &[Vec<Terrain>]) -> Self {
+        assert!(terrain.len() == geo.rows && terrain.iter().all(|row| row.len() == geo.cols), "The terrain must be rows x cols");
+        let states = terrain.iter().map(|row| row.iter().map(|t| if t.is_land() { EARTH } else { SEA }).collect()).collect();
+        CoastDistance { geo, states }
+    }
+
+    pub fn step(&mut self) {
+        let prev_state = self.states.clone();
+        for (y, row) in self.states.iter_mut().enumerate() {
+            for (x, state) in row.iter_mut().enumerate() {
+                if state != &EARTH { continue }
+                let neighbours = self.geo.neighbours(OffsetCoord{x: x as isize,y: y as isize}.axial());
+                // Sea cells nearby AND Greatest neighbour value
+                let nearby = neighbours.iter().fold((0_u8, 0_u32), |acc, axcord| {
+                    let o = axcord.offset();
+                    let n = prev_state[o.y as usize][o.x as usize];
+                    (if n == SEA { acc.0+1 } else { acc.0 }, acc.1.max(n))
+                });
+                if nearby.0 != 0 {
+                    *state = 2;
+                } else if nearby.1 >= 2 {
+                    *state = nearby.1.saturating_add(1);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use crate::raster::Palette;
+    use crate::hex_geom::AxialCoord;
+    use crate::rng;
+
+    fn earth(geo: &HexGeometry) -> Vec<Vec<Terrain>> {
+        vec![vec![Terrain::Earth; geo.cols]; geo.rows]
+    }
+
+    #[derive(Default)]
+    struct Events(Vec<EmpireEvent>);
+
+    impl StepObserver<Owner, EmpireEvent> for Events {
+        fn on_event(&mut self, event: &EmpireEvent) {
+            self.0.push(event.clone());
+        }
+    }
+
+    #[test]
+    fn test_expansion() {
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 5, 5, 1.0);
+        let mut terrain = earth(&geo);
+        terrain[2][3] = Terrain::Sea;
+        let mut sim = EmpireSimulation::new(&geo, terrain, rng::seeded(1));
+        let events = Rc::new(RefCell::new(Events::default()));
+        sim.add_observer(Box::new(events.clone()));
+        assert_eq!(sim.found_empire(2, 2, Some("Rome".to_string()), Attributes::default()), Some(0));
+        // Not on sea, nor on an owned cell
+        assert_eq!(sim.found_empire(3, 2, None, Attributes::default()), None);
+        assert_eq!(sim.found_empire(2, 2, None, Attributes::default()), None);
+        sim.step();
+        // The 5 earth neighbours are captured, the sea is not
+        let owned: Vec<(usize, usize)> = (0..5).flat_map(|y| (0..5).map(move |x| (x, y))).filter(|&(x, y)| sim.owners[y][x] == Some(0)).collect();
+        assert_eq!(owned, vec![(1, 1), (2, 1), (1, 2), (2, 2), (1, 3), (2, 3)]);
+        assert_eq!((sim.owners[2][3], sim.state(3, 2), sim.state(2, 2), sim.state(0, 0)), (None, SEA, owner_state(0), EARTH));
+        assert_eq!(sim.generation(), 1);
+        let captured = events.borrow().0.iter().filter(|e| matches!(e, EmpireEvent::Captured { empire: 0, .. })).count();
+        assert_eq!(events.borrow().0[0], EmpireEvent::Founded { empire: 0, x: 2, y: 2 });
+        assert_eq!(captured, 5);
+        // The whole earth is owned in the end
+        for _ in 0..5 {
+            sim.step();
+        }
+        assert!((0..5).all(|y| (0..5).all(|x| !sim.is_free_earth(x, y))));
+    }
+
+    fn axial(x: usize, y: usize) -> AxialCoord {
+        OffsetCoord { x: x as isize, y: y as isize }.axial()
+    }
+
+    #[test]
+    fn test_majority_wins() {
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 3, 3, 1.0);
+        // (1, 1) touches (0, 1), (1, 0) and (2, 1): two cells of empire 1 and one of 0
+        for conflict in [Conflict::Strongest, Conflict::Oldest, Conflict::KeepOnTie] {
+            let mut sim = EmpireSimulation::new(&geo, earth(&geo), rng::seeded(1)).with_conflict(conflict);
+            sim.found_empire(0, 1, None, Attributes::default());
+            sim.found_empire(1, 0, None, Attributes::default());
+            sim.owners[1][2] = Some(1);
+            sim.step();
+            assert_eq!(sim.owners[1][1], Some(1), "{:?}", conflict);
+        }
+    }
+
+    #[test]
+    fn test_ties() {
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 3, 3, 1.0);
+        // (1, 1) between one cell of empire 0 and one of empire 1, empire 1 is bigger
+        let tie = |conflict| {
+            let mut sim = EmpireSimulation::new(&geo, earth(&geo), rng::seeded(1)).with_conflict(conflict);
+            sim.found_empire(0, 1, None, Attributes::default());
+            sim.generation = 1;
+            sim.found_empire(2, 1, None, Attributes::default());
+            sim.owners[0][0] = Some(1);
+            sim.owners[2][0] = Some(1);
+            sim.step();
+            sim.owners[1][1]
+        };
+        assert_eq!(tie(Conflict::Strongest), Some(1));
+        assert_eq!(tie(Conflict::Oldest), Some(0));
+        assert_eq!(tie(Conflict::KeepOnTie), None);
+    }
+
+    #[test]
+    fn test_isotropic_growth() {
+        // From a single seed that always expands, every policy grows a regular hexagon
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 31, 31, 1.0);
+        for conflict in [Conflict::Weighted, Conflict::Strongest, Conflict::Oldest, Conflict::KeepOnTie] {
+            let mut sim = EmpireSimulation::new(&geo, earth(&geo), rng::seeded(4)).with_conflict(conflict);
+            sim.found_empire(15, 15, None, Attributes::founding(1.0, 0.0));
+            for _ in 0..10 {
+                sim.step();
+            }
+            for y in 0..31 {
+                for x in 0..31 {
+                    let inside = geo.distance(axial(x, y), axial(15, 15)) <= 10.0;
+                    assert_eq!(sim.owners[y][x].is_some(), inside, "{:?} at {},{}", conflict, x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_mirrored_seeds_mirror_the_map() {
+        // Two empires meeting in the middle: swapping their places gives the map turned by 180
+        // degrees around the middle, whatever the direction of the neighbours
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 31, 31, 1.0);
+        let run = |conflict, a: (usize, usize), b: (usize, usize)| {
+            let mut sim = EmpireSimulation::new(&geo, earth(&geo), rng::seeded(4)).with_conflict(conflict);
+            sim.found_empire(a.0, a.1, None, Attributes::default());
+            sim.found_empire(b.0, b.1, None, Attributes::default());
+            // Still away from the edges of the map
+            for _ in 0..8 {
+                sim.step();
+            }
+            sim.owners
+        };
+        let (p, q) = ((10, 12), (19, 18));
+        let (pa, qa) = (axial(p.0, p.1), axial(q.0, q.1));
+        for conflict in [Conflict::Strongest, Conflict::Oldest, Conflict::KeepOnTie] {
+            let (one, other) = (run(conflict, p, q), run(conflict, q, p));
+            let mut contested = 0;
+            for (y, row) in one.iter().enumerate() {
+                for (x, owner) in row.iter().enumerate() {
+                    let c = axial(x, y);
+                    let o = AxialCoord { q: pa.q + qa.q - c.q, r: pa.r + qa.r - c.r }.offset();
+                    if o.x < 0 || o.y < 0 || o.x >= 31 || o.y >= 31 {
+                        continue;
+                    }
+                    assert_eq!(*owner, other[o.y as usize][o.x as usize], "{:?} at {},{}", conflict, x, y);
+                    let d = geo.distance(c, axial(p.0, p.1));
+                    contested += (d <= 8.0 && d == geo.distance(axial(x, y), axial(q.0, q.1))) as usize;
+                }
+            }
+            assert!(contested > 0);
+        }
+    }
+
+    #[test]
+    fn test_weighted_is_fair() {
+        // Two empires at the same distance from the contested cells share them evenly over many runs
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 31, 31, 1.0);
+        let (mut first, mut second) = (0, 0);
+        for seed in 0..40 {
+            let mut sim = EmpireSimulation::new(&geo, earth(&geo), rng::seeded(seed));
+            sim.found_empire(10, 15, None, Attributes::default());
+            sim.found_empire(20, 15, None, Attributes::default());
+            for _ in 0..8 {
+                sim.step();
+            }
+            let sizes = sim.sizes();
+            first += sizes[0];
+            second += sizes[1];
+        }
+        let share = first as f64 / (first + second) as f64;
+        assert!((0.48..0.52).contains(&share), "{}", share);
+    }
+
+    #[test]
+    fn test_attributes() {
+        let a = Attributes::founding(0.5, 1.0);
+        assert_eq!(a.expansion_chance(10), 0.5 + 0.5 * SETTLERS / (10.0 * PEOPLE_PER_CELL));
+        // A full territory and all the aggression: always expands
+        assert_eq!(Attributes { population: 10.0 * PEOPLE_PER_CELL, ..a }.expansion_chance(10), 1.0);
+        assert_eq!(Attributes { aggression: 0.0, ..a }.expansion_chance(10), 0.5);
+
+        // The population grows up to what the territory feeds, the strength follows it
+        let mut a = Attributes::founding(0.5, 0.5);
+        for _ in 0..200 {
+            let before = a.population;
+            a.evolve(5);
+            assert!(a.population >= before && a.population <= 5.0 * PEOPLE_PER_CELL);
+        }
+        assert!((a.population - 5.0 * PEOPLE_PER_CELL).abs() < 1.0);
+        assert!((a.strength - a.population * 0.15).abs() < 1.0);
+        // And shrinks with it
+        a.evolve(2);
+        assert!(a.population < 5.0 * PEOPLE_PER_CELL);
+    }
+
+    #[test]
+    fn test_expansion_rates() {
+        // Same map, same seed: the empire with the higher expansion grows larger
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 60, 30, 1.0);
+        let mut sim = EmpireSimulation::new(&geo, earth(&geo), rng::seeded(3));
+        let slow = sim.found_empire(10, 15, None, Attributes::founding(0.2, 0.0)).unwrap();
+        let fast = sim.found_empire(50, 15, None, Attributes::founding(0.8, 0.0)).unwrap();
+        for _ in 0..12 {
+            sim.step();
+        }
+        let sizes = sim.sizes();
+        assert!(sizes[fast as usize] > 2 * sizes[slow as usize], "{:?}", sizes);
+        // Growing slower than one ring per step
+        assert!(sizes[fast as usize] < 3 * 12 * 13 + 1);
+        let (slow, fast) = (sim.empire(slow).unwrap(), sim.empire(fast).unwrap());
+        assert!(fast.attributes.population > slow.attributes.population);
+    }
+
+    #[test]
+    fn test_terrain_costs() {
+        // Two empires racing down the same strip, one through mountains
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 40, 6, 1.0);
+        let mut terrain = earth(&geo);
+        for (y, row) in terrain.iter_mut().enumerate() {
+            row.fill(if y < 3 { Terrain::Mountain } else { Terrain::Plains });
+        }
+        terrain[2].fill(Terrain::Sea);
+        terrain[3].fill(Terrain::Sea);
+        let mut sim = EmpireSimulation::new(&geo, terrain, rng::seeded(4));
+        let high = sim.found_empire(0, 0, None, Attributes::default()).unwrap();
+        let low = sim.found_empire(0, 5, None, Attributes::default()).unwrap();
+        for _ in 0..10 {
+            sim.step();
+        }
+        let sizes = sim.sizes();
+        assert!(sizes[low as usize] > 2 * sizes[high as usize], "{:?}", sizes);
+        // Rivers ease the expansion and the defence
+        sim.rivers[1][30] = true;
+        assert_eq!((sim.cost(30, 1), sim.cost(30, 0)), (Terrain::Mountain.cost() * RIVER_COST, Terrain::Mountain.cost()));
+        assert_eq!(sim.defence(30, 1), Terrain::Mountain.defence() * RIVER_DEFENCE);
+        assert_eq!((sim.state(30, 1), sim.state(30, 0), sim.state(30, 2), sim.state(0, 0)), (RIVER, Terrain::Mountain as u32, SEA, owner_state(high)));
+    }
+
+    #[test]
+    fn test_terrain_generator() {
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 60, 40, 1.0);
+        let all = |_, _| true;
+        // Reproducible from the seed alone, the same as noise_terrain with the same spec
+        let spec = TerrainSpec { seed: Some(5), octaves: 4, frequency: 2.0, ..Default::default() };
+        let generator = TerrainGenerator::from_spec(&spec, &mut rng::seeded(1));
+        assert_eq!(generator, TerrainGenerator::new(5).with_octaves(4).with_frequency(2.0));
+        assert_eq!(generator.terrain(&geo, all), noise_terrain(&geo, &spec, all, &mut rng::seeded(2)));
+        assert_eq!(generator.land(&geo, all), generator.land(&geo, all));
+        assert_ne!(generator.terrain(&geo, all), TerrainGenerator::new(6).with_octaves(4).with_frequency(2.0).terrain(&geo, all));
+        // The seed is drawn from the rng without one
+        let drawn = TerrainGenerator::from_spec(&TerrainSpec::default(), &mut rng::seeded(1));
+        assert_eq!(drawn.seed(), TerrainGenerator::from_spec(&TerrainSpec::default(), &mut rng::seeded(1)).seed());
+
+        let land = |terrain: &[Vec<Terrain>]| terrain.iter().flatten().filter(|t| t.is_land()).count();
+        for fraction in [0.0, 0.2, 0.7, 1.0] {
+            let terrain = TerrainGenerator::new(3).with_land_fraction(Some(fraction)).terrain(&geo, all);
+            assert_eq!(land(&terrain), (fraction * 2400.0) as usize);
+        }
+        // Of the cells inside the mask
+        let terrain = TerrainGenerator::new(3).with_land_fraction(Some(1.0)).terrain(&geo, |x, _| x < 30);
+        assert_eq!(land(&terrain), 1200);
+
+        // Sea at the edges of the presets, a continent is one block of land
+        let edges = |terrain: &[Vec<Terrain>]| (0..geo.rows).all(|y| (0..geo.cols).all(|x| {
+            !(x == 0 || y == 0 || x == geo.cols - 1 || y == geo.rows - 1) || !terrain[y][x].is_land()
+        }));
+        let continent = TerrainGenerator::new(3).with_preset(TerrainPreset::Continent).terrain(&geo, all);
+        let islands = TerrainGenerator::new(3).with_preset(TerrainPreset::Islands).terrain(&geo, all);
+        assert!(edges(&continent) && edges(&islands));
+        assert_eq!((land(&continent), land(&islands)), (1200, 600));
+        let (many, few) = (landmasses(&geo, &islands), landmasses(&geo, &continent));
+        assert!(many > 3 * few, "{} islands, {} continents", many, few);
+        // A sea level replaces the land fraction of the preset, not the land fraction
+        let high = TerrainGenerator::new(3).with_preset(TerrainPreset::Islands).with_sea_level(Some(0.9));
+        assert_eq!(land(&high.terrain(&geo, all)), 0);
+        assert_eq!(land(&high.with_land_fraction(Some(0.5)).terrain(&geo, all)), 1200);
+        assert_eq!(TerrainGenerator::new(3).with_frequency(2.0).with_preset(TerrainPreset::Islands).frequency(), 6.0);
+    }
+
+    fn landmasses(geo: &HexGeometry, terrain: &[Vec<Terrain>]) -> usize {
+        let mut seen = vec![vec![false; geo.cols]; geo.rows];
+        let mut count = 0;
+        for (y, x) in (0..geo.rows).flat_map(|y| (0..geo.cols).map(move |x| (y, x))) {
+            if seen[y][x] || !terrain[y][x].is_land() { continue }
+            count += 1;
+            let mut queue = VecDeque::from([(x, y)]);
+            seen[y][x] = true;
+            while let Some((cx, cy)) = queue.pop_front() {
+                for n in geo.neighbours(axial(cx, cy)) {
+                    let (nx, ny) = (n.offset().x as usize, n.offset().y as usize);
+                    if !seen[ny][nx] && terrain[ny][nx].is_land() {
+                        seen[ny][nx] = true;
+                        queue.push_back((nx, ny));
+                    }
+                }
+            }
+        }
+        count
+    }
+
+    #[test]
+    fn test_biome_land() {
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 60, 40, 1.0);
+        let spec = TerrainSpec { seed: Some(11), sea_level: Some(-0.1), ..Default::default() };
+        let land = biome_land(&geo, &spec, |_, _| true, &mut rng::seeded(2));
+        assert_eq!(land, biome_land(&geo, &spec, |_, _| true, &mut rng::seeded(2)));
+        // The sea of the noise terrain, with every biome on the land
+        let plain = noise_terrain(&geo, &spec, |_, _| true, &mut rng::seeded(2));
+        for (terrain, plain) in land.terrain.iter().flatten().zip(plain.iter().flatten()) {
+            assert_eq!(terrain.is_land(), *plain == Terrain::Earth);
+        }
+        for biome in [Terrain::Plains, Terrain::Forest, Terrain::Desert, Terrain::Mountain, Terrain::Tundra] {
+            assert!(land.terrain.iter().flatten().any(|&t| t == biome), "no {:?}", biome);
+        }
+        assert!(!land.terrain.iter().flatten().any(|&t| t == Terrain::Earth));
+        // Rivers on land only, flowing downhill
+        let rivers: Vec<(usize, usize)> = (0..geo.rows).flat_map(|y| (0..geo.cols).map(move |x| (x, y))).filter(|&(x, y)| land.rivers[y][x]).collect();
+        assert!(!rivers.is_empty());
+        assert!(rivers.iter().all(|&(x, y)| land.terrain[y][x].is_land()));
+        for &(x, y) in rivers.iter() {
+            // Ending in a pit, at the sea or in the lowest neighbour
+            let (nx, ny) = geo.neighbours(axial(x, y)).into_iter().map(|n| n.offset()).map(|o| (o.x as usize, o.y as usize))
+                .min_by(|a, b| land.elevation[a.1][a.0].total_cmp(&land.elevation[b.1][b.0])).unwrap();
+            assert!(land.elevation[ny][nx] >= land.elevation[y][x] || !land.terrain[ny][nx].is_land() || land.rivers[ny][nx], "river at {},{}", x, y);
+        }
+        // The mask is sea
+        let land = biome_land(&geo, &spec, |x, _| x < 30, &mut rng::seeded(2));
+        assert!(land.terrain.iter().all(|row| row[30..].iter().all(|&t| t == Terrain::Sea)));
+        let mut sim = EmpireSimulation::from_land(&geo, land.clone(), rng::seeded(1));
+        assert_eq!((&sim.terrain, &sim.rivers), (&land.terrain, &land.rivers));
+        assert!(sim.interact_new_random_empire().is_some());
+    }
+
+    // Two empires splitting an earth map in half at column 20, with their capitals
+    fn front<'a>(geo: &'a HexGeometry, warfare: Warfare, empires: [((usize, usize), Attributes); 2]) -> EmpireSimulation<'a> {
+        let mut sim = EmpireSimulation::new(geo, earth(geo), rng::seeded(8)).with_warfare(warfare);
+        let [a, b] = empires.map(|((x, y), attributes)| sim.found_empire(x, y, None, attributes).unwrap());
+        for row in sim.owners.iter_mut() {
+            for (x, o) in row.iter_mut().enumerate() {
+                *o = Some(if x < 20 { a } else { b });
+            }
+        }
+        sim
+    }
+
+    #[test]
+    fn test_warfare() {
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 40, 10, 1.0);
+        let even = [((0, 5), Attributes::default()), ((39, 5), Attributes::default())];
+        // Without warfare, or with a rate of 0, the front never moves
+        let mut sim = front(&geo, Warfare::default(), even);
+        sim.warfare = None;
+        let before = sim.owners.clone();
+        for _ in 0..10 {
+            sim.step();
+        }
+        assert_eq!(sim.owners, before);
+        let mut sim = front(&geo, Warfare { rate: 0.0, ..Default::default() }, even);
+        for _ in 0..10 {
+            sim.step();
+        }
+        assert_eq!(sim.owners, before);
+
+        // Every conquest is an event
+        let mut sim = front(&geo, Warfare { rate: 0.5, ..Default::default() }, even);
+        let events = Rc::new(RefCell::new(Events::default()));
+        sim.add_observer(Box::new(events.clone()));
+        for _ in 0..10 {
+            sim.step();
+        }
+        let conquests = events.borrow().0.iter().filter(|e| matches!(e, EmpireEvent::Conquered { .. })).count();
+        let changed = before.iter().flatten().zip(sim.owners.iter().flatten()).filter(|(a, b)| a != b).count();
+        assert!(changed > 0 && conquests >= changed, "{} conquests, {} changed", conquests, changed);
+
+        // A province cut off from the capital has no control and falls at once
+        let mut sim = front(&geo, Warfare { rate: 1.0, ..Default::default() }, even).with_control(Control { reach: Reach::Path, range: 20.0 });
+        sim.owners[5][30] = Some(0);
+        sim.step();
+        assert_eq!(sim.owners[5][30], Some(1));
+    }
+
+    #[test]
+    fn test_warfare_advantages() {
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 40, 10, 1.0);
+        let war = Warfare { rate: 0.5, ..Default::default() };
+        let fight = |empires| {
+            let mut sim = front(&geo, war, empires);
+            for _ in 0..20 {
+                sim.step();
+            }
+            sim.sizes()
+        };
+        // A capital close to the front
+        let sizes = fight([((17, 5), Attributes::default()), ((39, 5), Attributes::default())]);
+        assert!(sizes[0] > sizes[1], "{:?}", sizes);
+        // Aggression
+        let sizes = fight([((0, 5), Attributes::default()), ((39, 5), Attributes::founding(1.0, 1.0))]);
+        assert!(sizes[1] > sizes[0], "{:?}", sizes);
+        // Fewer cells change hands in the mountains than on the plains
+        let changed = |terrain| {
+            let mut sim = front(&geo, Warfare { rate: 1.0, sharpness: 1.0 }, [((0, 5), Attributes::default()), ((39, 5), Attributes::default())]);
+            sim.terrain.iter_mut().for_each(|row| row.fill(terrain));
+            let before = sim.owners.clone();
+            for _ in 0..40 {
+                sim.step();
+            }
+            before.iter().flatten().zip(sim.owners.iter().flatten()).filter(|(a, b)| a != b).count()
+        };
+        let (plains, mountains) = (changed(Terrain::Plains), changed(Terrain::Mountain));
+        assert!(plains > mountains, "{} {}", plains, mountains);
+    }
+
+    #[test]
+    fn test_control_map() {
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 9, 5, 1.0);
+        let mut sim = EmpireSimulation::new(&geo, earth(&geo), rng::seeded(1));
+        let a = sim.found_empire(0, 2, None, Attributes::default()).unwrap();
+        // Cut in two by (4, 2) and (5, 2)
+        for x in [1, 2, 3, 6, 7] {
+            sim.owners[2][x] = Some(a);
+        }
+        let control = Control { reach: Reach::Hex, range: 3.0 };
+        sim = sim.with_control(control);
+        let map = sim.control_map();
+        assert_eq!((map[2][0], map[2][3], map[2][6], map[2][5], map[0][0]), (1.0, 0.5, control.at(6.0), 0.0, 0.0));
+        sim = sim.with_control(Control { reach: Reach::Path, ..control });
+        let map = sim.control_map();
+        assert_eq!((map[2][0], map[2][3], map[2][6], map[2][7]), (1.0, 0.5, 0.0, 0.0));
+    }
+
+    #[test]
+    fn test_capital_moves() {
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 9, 3, 1.0);
+        let mut terrain = earth(&geo);
+        terrain[0] = vec![Terrain::Sea; 9];
+        terrain[2] = vec![Terrain::Sea; 9];
+        let mut sim = EmpireSimulation::new(&geo, terrain, rng::seeded(1));
+        let events = Rc::new(RefCell::new(Events::default()));
+        sim.add_observer(Box::new(events.clone()));
+        let a = sim.found_empire(4, 1, None, Attributes::default()).unwrap();
+        for x in [2, 3, 5, 6] {
+            sim.owners[1][x] = Some(a);
+        }
+        // Lost to another empire
+        sim.owners[1][4] = None;
+        let b = sim.found_empire(4, 1, None, Attributes::default()).unwrap();
+        sim.step();
+        // (3, 1) and (5, 1) are as near, the first one wins
+        assert_eq!((sim.empire(a).unwrap().capital, sim.empire(b).unwrap().capital), ((3, 1), (4, 1)));
+        assert!(events.borrow().0.contains(&EmpireEvent::CapitalMoved { empire: a, x: 3, y: 1 }));
+    }
+
+    #[test]
+    fn test_split() {
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 12, 3, 1.0);
+        let mut terrain = earth(&geo);
+        terrain[0] = vec![Terrain::Sea; 12];
+        terrain[2] = vec![Terrain::Sea; 12];
+        let no_rebels = Rebellion { split_size: 3, rate: 0.0, ..Default::default() };
+        let mut sim = EmpireSimulation::new(&geo, terrain, rng::seeded(1)).with_rebellion(no_rebels);
+        let events = Rc::new(RefCell::new(Events::default()));
+        sim.add_observer(Box::new(events.clone()));
+        let a = sim.found_empire(0, 1, None, Attributes { population: 1200.0, ..Default::default() }).unwrap();
+        // Three territories, split by the free (3, 1) and (6, 1)
+        for x in [1, 2, 4, 7, 8, 9, 10, 11] {
+            sim.owners[1][x] = Some(a);
+        }
+        sim.terrain[1][3] = Terrain::Sea;
+        sim.terrain[1][6] = Terrain::Sea;
+        assert_eq!(sim.territories(), vec![(a, vec![(0, 1), (1, 1), (2, 1)]), (a, vec![(4, 1)]), (a, vec![(7, 1), (8, 1), (9, 1), (10, 1), (11, 1)])]);
+        sim.step();
+        // The one on the right is large enough to be independent, the other one stays
+        let b = sim.owners[1][7].unwrap();
+        assert_ne!(b, a);
+        assert!((7..12).all(|x| sim.owners[1][x] == Some(b)) && sim.owners[1][5] == Some(a));
+        assert!(events.borrow().0.contains(&EmpireEvent::Split { empire: b, from: a, cells: 5 }));
+        let (a, b) = (sim.empire(a).unwrap(), sim.empire(b).unwrap());
+        assert_eq!((b.capital, b.founded), ((7, 1), 0));
+        assert_ne!(b.name, format!("Empire {}", b.id));
+        // The population is shared by the cells, then grows on each side
+        assert!(b.attributes.population > 500.0 && a.attributes.population > 500.0);
+    }
+
+    #[test]
+    fn test_rebellion() {
+        // A large empire controlling only the cells next to its capital
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 10, 10, 1.0);
+        let rebellion = Rebellion { split_size: 0, rate: 1.0, threshold: 0.4 };
+        let mut sim = EmpireSimulation::new(&geo, earth(&geo), rng::seeded(2))
+            .with_control(Control { reach: Reach::Hex, range: 1.0 })
+            .with_rebellion(rebellion);
+        let events = Rc::new(RefCell::new(Events::default()));
+        sim.add_observer(Box::new(events.clone()));
+        let a = sim.found_empire(0, 0, None, Attributes::default()).unwrap();
+        for o in sim.owners.iter_mut().flatten() {
+            *o = Some(a);
+        }
+        sim.step();
+        // A single rebellion in this step, away from the capital
+        let rebels: Vec<&Empire> = sim.empires().filter(|e| e.id != a).collect();
+        assert_eq!(rebels.len(), 1);
+        let (x, y) = rebels[0].capital;
+        assert!(geo.distance(axial(x, y), axial(0, 0)) >= 2.0);
+        assert!(events.borrow().0.contains(&EmpireEvent::Rebelled { empire: rebels[0].id, from: a, x, y }));
+        let cells = sim.sizes()[rebels[0].id as usize];
+        assert!((2..=7).contains(&cells), "{}", cells);
+        // Without control loss nobody rebels
+        let mut sim = EmpireSimulation::new(&geo, earth(&geo), rng::seeded(2)).with_rebellion(rebellion);
+        sim.found_empire(0, 0, None, Attributes::default());
+        for _ in 0..5 {
+            sim.step();
+        }
+        assert_eq!(sim.empires().count(), 1);
+    }
+
+    #[test]
+    fn test_split_and_rebellion() {
+        // A territory cut off by the sea at (3, 1), far from the capital
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 12, 3, 1.0);
+        let mut terrain = earth(&geo);
+        terrain[0] = vec![Terrain::Sea; 12];
+        terrain[2] = vec![Terrain::Sea; 12];
+        terrain[1][3] = Terrain::Sea;
+        let rebellion = Rebellion { split_size: 3, rate: 1.0, threshold: 0.9 };
+        let mut sim = EmpireSimulation::new(&geo, terrain, rng::seeded(1))
+            .with_control(Control { reach: Reach::Hex, range: 1.0 })
+            .with_rebellion(rebellion);
+        let a = sim.found_empire(0, 1, None, Attributes::default()).unwrap();
+        for x in [1, 2, 4, 5, 6, 7, 8, 9, 10, 11] {
+            sim.owners[1][x] = Some(a);
+        }
+        sim.step();
+        // The territory splits and does not rebel in the same step, the rest of the empire does
+        let b = sim.owners[1][4].unwrap();
+        assert_ne!(b, a);
+        assert!((4..12).all(|x| sim.owners[1][x] == Some(b)));
+        assert_eq!(sim.sizes()[b as usize], 8);
+        assert!(sim.empires().count() > 2);
+        for _ in 0..5 {
+            sim.step();
+        }
+    }
+
+    #[test]
+    fn test_relations() {
+        let mut relations = Relations::new();
+        relations.set(3, 1, Relation::Vassal);
+        relations.set(1, 2, Relation::Alliance);
+        assert_eq!((relations.get(3, 1), relations.get(1, 3), relations.get(2, 3)), (Some(Relation::Vassal), Some(Relation::Overlord), None));
+        assert_eq!(relations.iter().collect::<Vec<_>>(), vec![(1, 2, Relation::Alliance), (1, 3, Relation::Overlord)]);
+        relations.forget(1);
+        assert!(relations.is_empty());
+
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 6, 3, 1.0);
+        let mut sim = EmpireSimulation::new(&geo, earth(&geo), rng::seeded(1));
+        let events = Rc::new(RefCell::new(Events::default()));
+        sim.add_observer(Box::new(events.clone()));
+        let a = sim.found_empire(0, 1, None, Attributes::default()).unwrap();
+        let b = sim.found_empire(5, 1, None, Attributes::default()).unwrap();
+        assert_eq!((sim.relation(a, b), sim.relation(a, a)), (Relation::War, Relation::Alliance));
+        sim.set_relation(b, a, Relation::Vassal);
+        assert_eq!(sim.relation(a, b), Relation::Overlord);
+        assert!(events.borrow().0.contains(&EmpireEvent::RelationChanged { empire: b, other: a, relation: Relation::Vassal }));
+        // Not with an empire that does not exist, even when its id is taken later
+        sim.set_relation(a, 9, Relation::Alliance);
+        sim.set_relation(a, b + 1, Relation::Alliance);
+        assert_eq!(sim.relations().iter().count(), 1);
+        let c = sim.found_empire(3, 0, None, Attributes::default()).unwrap();
+        assert_eq!((c, sim.relation(a, c)), (b + 1, Relation::War));
+        use crate::snapshot::{Snapshot, Snapshottable};
+        let snap = Snapshot::decode(&sim.snapshot().encode()).unwrap();
+        assert!(EmpireSimulation::from_snapshot(&geo, &snap).is_ok());
+        // The relations die with the empire
+        sim.owners[1][5] = None;
+        sim.step();
+        assert!(sim.relations().is_empty());
+        let sim = EmpireSimulation::new(&geo, earth(&geo), rng::seeded(1)).with_diplomacy(Diplomacy::default());
+        assert_eq!(sim.relation(a, b), Relation::Peace);
+    }
+
+    #[test]
+    fn test_only_war_fights() {
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 40, 10, 1.0);
+        let even = [((0, 5), Attributes::default()), ((39, 5), Attributes::default())];
+        for relation in [Relation::Peace, Relation::Alliance, Relation::Vassal, Relation::Overlord] {
+            let mut sim = front(&geo, Warfare { rate: 1.0, ..Default::default() }, even);
+            sim.set_relation(0, 1, relation);
+            let before = sim.owners.clone();
+            for _ in 0..5 {
+                sim.step();
+            }
+            assert_eq!(sim.owners, before, "{:?}", relation);
+        }
+    }
+
+    #[test]
+    fn test_vassals_leave_cells() {
+        // (1, 1) is between the two, the vassal is the oldest and would take it
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 5, 3, 1.0);
+        let mut terrain = earth(&geo);
+        terrain[0] = vec![Terrain::Sea; 5];
+        terrain[2] = vec![Terrain::Sea; 5];
+        let mut sim = EmpireSimulation::new(&geo, terrain, rng::seeded(1)).with_conflict(Conflict::Oldest);
+        let vassal = sim.found_empire(2, 1, None, Attributes::default()).unwrap();
+        let overlord = sim.found_empire(0, 1, None, Attributes::default()).unwrap();
+        sim.set_relation(vassal, overlord, Relation::Vassal);
+        sim.step();
+        assert_eq!((sim.owners[1][1], sim.owners[1][3]), (Some(overlord), Some(vassal)));
+    }
+
+    #[test]
+    fn test_diplomacy() {
+        // Two neighbours, one much stronger and aggressive
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 6, 3, 1.0);
+        let mut sim = EmpireSimulation::new(&geo, earth(&geo), rng::seeded(1))
+            .with_diplomacy(Diplomacy { rate: 1.0, ..Default::default() });
+        let strong = Attributes { population: 10000.0, strength: 2500.0, expansion: 1.0, aggression: 1.0 };
+        let a = sim.found_empire(0, 1, None, strong).unwrap();
+        let b = sim.found_empire(5, 1, None, Attributes::default()).unwrap();
+        for row in sim.owners.iter_mut() {
+            for (x, o) in row.iter_mut().enumerate() {
+                *o = Some(if x < 3 { a } else { b });
+            }
+        }
+        sim.step();
+        assert_eq!(sim.relation(a, b), Relation::War);
+        sim.step();
+        assert_eq!(sim.relation(b, a), Relation::Vassal);
+        // A vassal stronger than its overlord takes its freedom back
+        sim.set_relation(a, b, Relation::Vassal);
+        sim.step();
+        assert_eq!(sim.relation(a, b), Relation::War);
+    }
+
+    #[test]
+    fn test_elimination_and_reuse() {
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 4, 4, 1.0);
+        let mut sim = EmpireSimulation::new(&geo, earth(&geo), rng::seeded(7));
+        let events = Rc::new(RefCell::new(Events::default()));
+        sim.add_observer(Box::new(events.clone()));
+        let a = sim.interact_new_random_empire().unwrap();
+        let b = sim.interact_new_random_empire().unwrap();
+        assert_eq!((a, b), (0, 1));
+        assert_eq!(sim.empire(b).map(|e| e.name.as_str()), Some("Empire 1"));
+        // Empire 0 loses its only cell
+        for o in sim.owners.iter_mut().flatten() {
+            if *o == Some(a) {
+                *o = None;
+            }
+        }
+        sim.step();
+        assert!(sim.empire(a).is_none());
+        assert!(events.borrow().0.contains(&EmpireEvent::Eliminated { empire: a }));
+        assert_eq!(sim.empires().map(|e| e.id).collect::<Vec<_>>(), vec![b]);
+        // The free id is reused
+        let earth_cell = sim.find_free_earth().unwrap();
+        assert_eq!(sim.found_empire(earth_cell.0, earth_cell.1, None, Attributes::default()), Some(a));
+    }
+
+    #[test]
+    fn test_registry() {
+        let mut reg = EmpireRegistry::new();
+        for i in 0..1000 {
+            assert_eq!(reg.insert(None, 0, (0, 0), Attributes::default()), i);
+        }
+        assert_eq!(reg.remove(500).map(|e| e.name), Some("Empire 500".to_string()));
+        assert_eq!(reg.remove(3).map(|e| e.id), Some(3));
+        assert_eq!(reg.remove(3), None);
+        assert_eq!((reg.len(), reg.id_bound()), (998, 1000));
+        // Lowest free id first, then the table grows
+        assert_eq!(reg.insert(Some("Byzantium".to_string()), 0, (0, 0), Attributes::default()), 3);
+        assert_eq!(reg.insert(None, 0, (0, 0), Attributes::default()), 500);
+        assert_eq!(reg.insert(None, 0, (0, 0), Attributes::default()), 1000);
+        let mut restored = EmpireRegistry::new();
+        restored.restore(Empire { id: 4, name: "Sparta".to_string(), founded: 0, capital: (0, 0), attributes: Attributes::default() });
+        assert_eq!(restored.iter().map(|e| e.id).collect::<Vec<_>>(), vec![4]);
+        assert_eq!(restored.insert(None, 0, (0, 0), Attributes::default()), 0);
+    }
+
+    #[test]
+    fn test_many_empires() {
+        // One empire on every cell, far beyond the old 253
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 40, 30, 1.0);
+        let mut sim = EmpireSimulation::new(&geo, earth(&geo), rng::seeded(2));
+        for y in 0..30 {
+            for x in 0..40 {
+                assert_eq!(sim.found_empire(x, y, None, Attributes::default()), Some((y * 40 + x) as EmpireId));
+            }
+        }
+        assert_eq!(sim.empires().count(), 1200);
+        assert!(sim.find_free_earth().is_none());
+        // Every empire has a colour, neighbouring ids differ
+        let palette = Palette::empire();
+        let colors: Vec<_> = (0..1200).map(|id| palette.get(owner_state(id))).collect();
+        assert!(colors.windows(2).all(|w| w[0] != w[1]));
+        assert_eq!(palette.get(owner_state(1199)), crate::raster::empire_color(1199));
+    }
+
+    #[test]
+    fn test_find_free_earth() {
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 30, 30, 1.0);
+        let mut terrain = vec![vec![Terrain::Sea; 30]; 30];
+        let mut sim = EmpireSimulation::new(&geo, terrain.clone(), rng::seeded(3));
+        assert_eq!(sim.find_free_earth(), None);
+        assert_eq!(sim.interact_new_random_empire(), None);
+        // A single earth cell is found by the full search
+        terrain[17][4] = Terrain::Earth;
+        let mut sim = EmpireSimulation::new(&geo, terrain, rng::seeded(3));
+        assert_eq!(sim.find_free_earth(), Some((4, 17)));
+    }
+
+    #[test]
+    fn test_get_empire_by_pos() {
+        let geo = HexGeometry::new(Vector2::new(10.0, -5.0), 6, 5, 2.0);
+        let mut sim = EmpireSimulation::new(&geo, earth(&geo), rng::seeded(1));
+        sim.found_empire(3, 2, Some("Carthage".to_string()), Attributes::default());
+        for y in 0..5 {
+            for x in 0..6 {
+                assert_eq!(geo.cell_at(geo.cellcenter(x, y)), Some((x, y)));
+            }
+        }
+        let c = geo.cellcenter(3, 2);
+        assert_eq!(sim.get_empire_by_pos(c + Vector2::new(0.5, 0.5)).map(|e| e.name.as_str()), Some("Carthage"));
+        assert!(sim.get_empire_by_pos(geo.cellcenter(0, 0)).is_none());
+        assert!(sim.get_empire_by_pos(Vector2::new(0.0, 0.0)).is_none());
+    }
+
+    #[test]
+    fn test_coast_distance() {
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 7, 1, 1.0);
+        let (s, e) = (Terrain::Sea, Terrain::Earth);
+        let mut sim = CoastDistance::new(&geo, &[vec![s, e, e, e, e, e, s]]);
+        sim.step();
+        assert_eq!(sim.states[0], vec![SEA, 2, EARTH, EARTH, EARTH, 2, SEA]);
+        sim.step();
+        sim.step();
+        assert_eq!(sim.states[0], vec![SEA, 2, 3, 4, 3, 2, SEA]);
+    }
+}
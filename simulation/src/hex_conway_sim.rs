@@ -1,7 +1,7 @@
-use raylib::color::Color;
-use raylib::drawing::{RaylibDraw, RaylibDrawHandle};
-use raylib::math::{Vector2};
+use crate::conway_sim::chance;
 use crate::hex_geom::{HexGeometry, OffsetCoord};
+use crate::observer::{Observers, StepObserver};
+use crate::rng::{self, SimRng};
 
 // TODO: Due intervalli di max min su q e r e basta NON SONO SUFFICIENTI a determinare propriamente
 //  la zona da tenere viva. C'e bisogno di un modo diverso di delimitare una zona valida.
@@ -10,10 +10,16 @@ use crate::hex_geom::{HexGeometry, OffsetCoord};
 //  C'e forse il rischio di dover riscrivere tutto con coordinate Offset ma speriamo di no...
 
 pub struct HexConwaySimulation<'a> {
-    geo: &'a HexGeometry,
+    pub(crate) geo: &'a HexGeometry,
     pub states: Vec<Vec<u8>>, // [y:0-rows][x:0-cols]. External is the row, internal is col/cell
-    birth: Vec<u8>,
-    stay: Vec<u8>,
+    pub(crate) birth: Vec<u8>,
+    pub(crate) stay: Vec<u8>,
+    // See ConwaySimulation::with_probabilities
+    pub(crate) birth_p: f64,
+    pub(crate) stay_p: f64,
+    pub(crate) rng: SimRng,
+    pub(crate) generation: u64,
+    observers: Observers<u8>,
 }
 
 impl<'a> HexConwaySimulation<'a> {
@@ -24,9 +30,30 @@ impl<'a> HexConwaySimulation<'a> {
             states,
             birth: birth.to_vec(),
             stay: stay.to_vec(),
+            birth_p: 1.0,
+            stay_p: 1.0,
+            rng: rng::seeded(0),
+            generation: 0,
+            observers: Observers::new(),
         }
     }
 
+    pub fn with_probabilities(mut self, birth_p: f64, stay_p: f64, rng: SimRng) -> Self {
+        assert!((0.0..=1.0).contains(&birth_p) && (0.0..=1.0).contains(&stay_p), "Probabilities must be in [0, 1]");
+        self.birth_p = birth_p;
+        self.stay_p = stay_p;
+        self.rng = rng;
+        self
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn add_observer(&mut self, observer: Box<dyn StepObserver<u8>>) {
+        self.observers.add(observer);
+    }
+
     pub fn step(&mut self) {
         let prev_state = self.states.clone();
         for (y, row) in self.states.iter_mut().enumerate() {
@@ -36,34 +63,19 @@ impl<'a> HexConwaySimulation<'a> {
                     let o = axcord.offset();
                     if prev_state[o.y as usize][o.x as usize] > 0 { acc+1 } else { acc }
                 });
-                *state = if *state > 0 {
-                    if self.stay.contains(&alives) { 1 } else { 0 }
+                let alive = if *state > 0 {
+                    self.stay.contains(&alives) && chance(&mut self.rng, self.stay_p)
                 } else {
-                    if self.birth.contains(&alives) { 1 } else { 0 }
+                    self.birth.contains(&alives) && chance(&mut self.rng, self.birth_p)
+                };
+                let new = alive as u8;
+                if !self.observers.is_empty() && *state != new {
+                    self.observers.cell_changed(y * self.geo.cols + x, state, &new);
                 }
+                *state = new;
             }
         }
-    }
-
-    pub fn draw(&mut self, d: &mut RaylibDrawHandle) {
-        let w = self.geo.hex_width();
-        let h = self.geo.hex_height();
-        for (y, row) in self.states.iter().enumerate() {
-            let row_y_center = 0.5*h + (0.75*h)*y as f32;
-            let row_offset = if y % 2 == 0 { 0.5*w } else { w };
-            for (x, state) in row.iter().enumerate() {
-                let col_x_center = row_offset + x as f32*w;
-                let color = if *state > 0 { Color::WHITE } else { Color::BLACK };
-                d.draw_poly(Vector2{x: col_x_center, y: row_y_center}, 6, self.geo.size, 90.0, color);
-                d.draw_poly_lines(Vector2{x: col_x_center, y: row_y_center}, 6, self.geo.size, 90.0, Color::GRAY)
-            }
-        }
-        let mut rect = self.geo.rect();
-        let line_thick: f32 = 10.0;
-        rect.x -= line_thick;
-        rect.width += 2.0*line_thick;
-        rect.y -= line_thick;
-        rect.height += 2.0*line_thick;
-        d.draw_rectangle_lines_ex(rect, line_thick, Color::GRAY.alpha(0.5))
+        self.generation += 1;
+        self.observers.generation_end(self.generation);
     }
 }
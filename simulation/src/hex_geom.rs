@@ -9,7 +9,7 @@ In Pointy-top orient we have W = sqrt(3)*size and H = 2*size
 
 We use Offset coordinates in “odd-r” horizontal layout for drawing the cells
 */
-use raylib::prelude::*;
+use crate::math::{Rectangle, Vector2, Vector3};
 
 // TODO: Notiamo come qui dobbiamo esporre come geometria il SeqID che viene utilizzato per accedere
 //  alla memoria... ma noi idealmente dobbiamo offrire dalla Geometria un sistema di coordinate utile
@@ -63,6 +63,7 @@ impl AxialCoord {
     }
 }
 
+#[derive(Debug, PartialEq)]
 pub struct HexGeometry {
     pub origin: Vector2,
     pub cols: usize,
@@ -105,6 +106,40 @@ impl HexGeometry {
         2.0*self.size
     }
 
+    // Centre of the cell in column x, row y: odd rows are shifted right by half a hex
+    pub fn cellcenter(&self, x: usize, y: usize) -> Vector2 {
+        let w = self.hex_width();
+        let h = self.hex_height();
+        let row_offset = if y & 1 == 0 { 0.5*w } else { w };
+        self.origin + Vector2::new(row_offset + x as f32*w, 0.5*h + 0.75*h*y as f32)
+    }
+
+    // Clockwise on screen from the lower right one
+    pub fn corners(&self, x: usize, y: usize) -> [Vector2; 6] {
+        let c = self.cellcenter(x, y);
+        std::array::from_fn(|i| {
+            let a = (60.0 * i as f32 - 30.0).to_radians();
+            c + Vector2::new(self.size * a.cos(), self.size * a.sin())
+        })
+    }
+
+    // Cell (column, row) under a point in world coordinates, if any
+    pub fn cell_at(&self, pos: Vector2) -> Option<(usize, usize)> {
+        // Invert the scaling, apply origin offset and scale for the algo
+        let p = pos - self.origin;
+        let x = (p.x - 0.5*self.hex_width()) / (self.size * f32::sqrt(3.0));
+        let y = (-p.y + 0.5*self.hex_height()) / (self.size * f32::sqrt(3.0));
+        // Cartesian to Hex - Apply Charles Chamber algo https://www.redblobgames.com/grids/hexagons/more-pixel-to-hex.html#charles-chambers
+        let temp = f32::floor(x + f32::sqrt(3.0) * y + 1.0);
+        let q = f32::floor((f32::floor(2.0 * x + 1.0) + temp) / 3.0) as isize;
+        let r = f32::floor((temp + f32::floor(-x + f32::sqrt(3.0) * y + 1.0)) / 3.0) as isize;
+        let o = AxialCoord{ q, r: -r }.offset();
+        if o.x < 0 || o.x >= self.cols as isize || o.y < 0 || o.y >= self.rows as isize {
+            return None;
+        }
+        Some((o.x as usize, o.y as usize))
+    }
+
     pub fn rect(&self) -> Rectangle {
         let w = self.hex_width();
         let h = self.hex_height();
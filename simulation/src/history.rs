@@ -0,0 +1,394 @@
+use std::collections::VecDeque;
+use std::mem::size_of;
+use crate::conway_sim::{ConwaySimulation, State};
+use crate::empire::{Empire, EmpireId, EmpireRegistry, EmpireSimulation, Owner, Relation, Relations};
+use crate::geometry::Geometry;
+use crate::hex_conway_sim::HexConwaySimulation;
+use crate::rng::SimRng;
+
+// A simulation that can be saved and restored cell by cell.
+// Meta is the model state that does not live in the cells (rng position, empire table...) and
+// must be restored together with them to branch from a consistent past state.
+pub trait Rewindable {
+    type Cell: Clone + PartialEq;
+    type Meta: Clone;
+    fn generation(&self) -> u64;
+    fn cells(&self) -> Vec<Self::Cell>;
+    fn meta(&self) -> Self::Meta;
+    fn restore(&mut self, generation: u64, cells: &[Self::Cell], meta: Self::Meta);
+    // Heap memory held by a meta, every frame stores one and it counts in the memory budget
+    fn meta_bytes(meta: &Self::Meta) -> usize;
+}
+
+enum Frame<C, M> {
+    Key { generation: u64, cells: Vec<C>, meta: M, meta_bytes: usize },
+    // Cells that changed since the previous generation, with their new value
+    Delta { generation: u64, changes: Vec<(u32, C)>, meta: M, meta_bytes: usize },
+}
+
+impl<C, M> Frame<C, M> {
+    fn generation(&self) -> u64 {
+        match self {
+            Frame::Key { generation, .. } | Frame::Delta { generation, .. } => *generation,
+        }
+    }
+
+    fn is_key(&self) -> bool {
+        matches!(self, Frame::Key { .. })
+    }
+
+    // Approximate, heap allocations inside C are not accounted
+    fn bytes(&self) -> usize {
+        size_of::<Self>() + match self {
+            Frame::Key { cells, meta_bytes, .. } => cells.len() * size_of::<C>() + meta_bytes,
+            Frame::Delta { changes, meta_bytes, .. } => changes.len() * size_of::<(u32, C)>() + meta_bytes,
+        }
+    }
+}
+
+// Record of the past generations of a simulation: a full keyframe every keyframe_interval
+// generations and the per-cell deltas in between. When the memory budget is exceeded the oldest
+// keyframe and its deltas are dropped.
+// Call record() after every step (or any other change to the simulation).
+pub struct History<C, M> {
+    keyframe_interval: u64,
+    budget_bytes: usize,
+    frames: VecDeque<Frame<C, M>>,
+    bytes: usize,
+    // Cells at head_generation, the base for the next delta
+    head: Vec<C>,
+    head_generation: Option<u64>,
+}
+
+impl<C: Clone + PartialEq, M: Clone> History<C, M> {
+    pub fn new(keyframe_interval: u64, budget_bytes: usize) -> Self {
+        assert!(keyframe_interval > 0, "Keyframe interval must be at least 1");
+        Self {
+            keyframe_interval,
+            budget_bytes,
+            frames: VecDeque::new(),
+            bytes: 0,
+            head: Vec::new(),
+            head_generation: None,
+        }
+    }
+
+    // First and last generation that can be restored
+    pub fn range(&self) -> Option<(u64, u64)> {
+        Some((self.frames.front()?.generation(), self.frames.back()?.generation()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.bytes = 0;
+        self.head.clear();
+        self.head_generation = None;
+    }
+
+    // Store the current state of the simulation. If it is not newer than the last stored
+    // generation (e.g. after a rewind) the newer frames are discarded: we are branching.
+    pub fn record<S: Rewindable<Cell = C, Meta = M>>(&mut self, sim: &S) {
+        let generation = sim.generation();
+        self.truncate_from(generation);
+        let cells = sim.cells();
+        let meta = sim.meta();
+        let meta_bytes = S::meta_bytes(&meta);
+        let last_key = self.frames.iter().rev().find(|f| f.is_key()).map(|f| f.generation());
+        let contiguous = self.head_generation == Some(generation.wrapping_sub(1))
+            && self.frames.back().map(|f| f.generation()) == self.head_generation
+            && self.head.len() == cells.len();
+        let frame = match last_key {
+            Some(k) if contiguous && generation - k < self.keyframe_interval => {
+                let changes = cells.iter().zip(self.head.iter()).enumerate()
+                    .filter(|(_, (new, old))| new != old)
+                    .map(|(i, (new, _))| (i as u32, new.clone()))
+                    .collect();
+                Frame::Delta { generation, changes, meta, meta_bytes }
+            }
+            _ => Frame::Key { generation, cells: cells.clone(), meta, meta_bytes },
+        };
+        self.push(frame);
+        self.head = cells;
+        self.head_generation = Some(generation);
+        self.enforce_budget();
+    }
+
+    // Restore the simulation at the given generation, returns false if it is not in the history.
+    // The newer frames are kept, so it is possible to scrub back and forth.
+    pub fn seek<S: Rewindable<Cell = C, Meta = M>>(&mut self, sim: &mut S, generation: u64) -> bool {
+        let Some((cells, meta)) = self.reconstruct(generation) else { return false };
+        sim.restore(generation, &cells, meta);
+        self.head = cells;
+        self.head_generation = Some(generation);
+        true
+    }
+
+    // Go back by the given number of generations, stopping at the oldest one available
+    pub fn rewind<S: Rewindable<Cell = C, Meta = M>>(&mut self, sim: &mut S, generations: u64) -> bool {
+        let Some((first, _)) = self.range() else { return false };
+        let target = sim.generation().saturating_sub(generations).max(first);
+        self.seek(sim, target)
+    }
+
+    // Restore a past generation and forget everything after it, so the simulation continues
+    // from there on a new timeline
+    pub fn branch<S: Rewindable<Cell = C, Meta = M>>(&mut self, sim: &mut S, generation: u64) -> bool {
+        if !self.seek(sim, generation) {
+            return false;
+        }
+        self.truncate_from(generation + 1);
+        true
+    }
+
+    fn reconstruct(&self, generation: u64) -> Option<(Vec<C>, M)> {
+        let pos = self.frames.iter().position(|f| f.generation() == generation)?;
+        let key = (0..=pos).rev().find(|&i| self.frames[i].is_key())?;
+        let Frame::Key { cells, meta, .. } = &self.frames[key] else { unreachable!() };
+        let mut cells = cells.clone();
+        let mut meta = meta.clone();
+        for frame in self.frames.range(key + 1..=pos) {
+            if let Frame::Delta { changes, meta: m, .. } = frame {
+                for (i, c) in changes {
+                    cells[*i as usize] = c.clone();
+                }
+                meta = m.clone();
+            }
+        }
+        Some((cells, meta))
+    }
+
+    fn push(&mut self, frame: Frame<C, M>) {
+        self.bytes += frame.bytes();
+        self.frames.push_back(frame);
+    }
+
+    fn truncate_from(&mut self, generation: u64) {
+        while self.frames.back().is_some_and(|f| f.generation() >= generation) {
+            let frame = self.frames.pop_back().unwrap();
+            self.bytes -= frame.bytes();
+        }
+    }
+
+    fn enforce_budget(&mut self) {
+        while self.bytes > self.budget_bytes {
+            // Drop the oldest keyframe with its deltas, but never the last segment
+            let Some(next_key) = self.frames.iter().skip(1).position(|f| f.is_key()) else { break };
+            for _ in 0..=next_key {
+                let frame = self.frames.pop_front().unwrap();
+                self.bytes -= frame.bytes();
+            }
+        }
+    }
+}
+
+impl<T: Geometry> Rewindable for ConwaySimulation<'_, T> {
+    type Cell = State;
+    type Meta = SimRng;
+
+    fn generation(&self) -> u64 {
+        ConwaySimulation::generation(self)
+    }
+
+    fn cells(&self) -> Vec<State> {
+        self.states.clone()
+    }
+
+    fn meta(&self) -> SimRng {
+        self.rng.clone()
+    }
+
+    fn restore(&mut self, generation: u64, cells: &[State], meta: SimRng) {
+        self.states = cells.to_vec();
+        self.generation = generation;
+        self.rng = meta;
+    }
+
+    fn meta_bytes(_: &SimRng) -> usize {
+        0
+    }
+}
+
+impl Rewindable for HexConwaySimulation<'_> {
+    type Cell = u8;
+    type Meta = SimRng;
+
+    fn generation(&self) -> u64 {
+        HexConwaySimulation::generation(self)
+    }
+
+    fn cells(&self) -> Vec<u8> {
+        self.states.concat()
+    }
+
+    fn meta(&self) -> SimRng {
+        self.rng.clone()
+    }
+
+    fn restore(&mut self, generation: u64, cells: &[u8], meta: SimRng) {
+        for (row, saved) in self.states.iter_mut().zip(cells.chunks(self.geo.cols)) {
+            row.copy_from_slice(saved);
+        }
+        self.generation = generation;
+        self.rng = meta;
+    }
+
+    fn meta_bytes(_: &SimRng) -> usize {
+        0
+    }
+}
+
+impl Rewindable for EmpireSimulation<'_> {
+    // The terrain does not change, only the owners are recorded
+    type Cell = Owner;
+    type Meta = (EmpireRegistry, Relations, SimRng);
+
+    fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    fn cells(&self) -> Vec<Owner> {
+        self.owners.concat()
+    }
+
+    fn meta(&self) -> Self::Meta {
+        (self.empires.clone(), self.relations.clone(), self.rng.clone())
+    }
+
+    fn restore(&mut self, generation: u64, cells: &[Owner], meta: Self::Meta) {
+        for (row, saved) in self.owners.iter_mut().zip(cells.chunks(self.geo.cols)) {
+            row.copy_from_slice(saved);
+        }
+        self.generation = generation;
+        (self.empires, self.relations, self.rng) = meta;
+    }
+
+    // Approximate: the slots of the registry with the empire names, and the relation pairs
+    fn meta_bytes((empires, relations, _): &Self::Meta) -> usize {
+        empires.id_bound() * size_of::<Option<Empire>>()
+            + empires.iter().map(|e| e.name.len()).sum::<usize>()
+            + relations.iter().count() * size_of::<((EmpireId, EmpireId), Relation)>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rect_geom::RectGeometry;
+    use crate::rng;
+    use rand::Rng;
+    use crate::simulation::Simulation;
+    use crate::math::Vector2;
+
+    fn vals(sim: &ConwaySimulation<RectGeometry>) -> Vec<u8> {
+        sim.get_states().iter().map(|s| s.val).collect()
+    }
+
+    fn soup(geo: &RectGeometry) -> ConwaySimulation<'_, RectGeometry> {
+        let mut sim = ConwaySimulation::new(geo, &[3], &[2,3])
+            .with_probabilities(0.9, 0.95, rng::seeded(5));
+        let mut rng = rng::seeded(11);
+        for i in 0..geo.size() {
+            sim.get_mut(i).unwrap().val = rng.random_bool(0.35) as u8;
+        }
+        sim
+    }
+
+    #[test]
+    fn test_seek_and_rewind() {
+        let geo = RectGeometry::new(Vector2::new(0.0, 0.0), 16, 16, Vector2::new(1.0, 1.0));
+        let mut sim = soup(&geo);
+        let mut history = History::new(4, usize::MAX);
+        let mut expected = Vec::new();
+        history.record(&sim);
+        expected.push(vals(&sim));
+        for _ in 0..10 {
+            sim.step();
+            history.record(&sim);
+            expected.push(vals(&sim));
+        }
+        assert_eq!(history.range(), Some((0, 10)));
+        for g in [3, 0, 9, 4, 10, 7] {
+            assert!(history.seek(&mut sim, g));
+            assert_eq!(sim.generation(), g);
+            assert_eq!(vals(&sim), expected[g as usize]);
+        }
+        assert!(history.rewind(&mut sim, 2));
+        assert_eq!(vals(&sim), expected[5]);
+        assert!(!history.seek(&mut sim, 11));
+    }
+
+    #[test]
+    fn test_branch_replays_identically() {
+        // The rng is part of the history, so stepping again from a past state gives the same future
+        let geo = RectGeometry::new(Vector2::new(0.0, 0.0), 16, 16, Vector2::new(1.0, 1.0));
+        let mut sim = soup(&geo);
+        let mut history = History::new(3, usize::MAX);
+        history.record(&sim);
+        for _ in 0..8 {
+            sim.step();
+            history.record(&sim);
+        }
+        let at_eight = vals(&sim);
+        assert!(history.branch(&mut sim, 5));
+        assert_eq!(history.range(), Some((0, 5)));
+        for _ in 0..3 {
+            sim.step();
+            history.record(&sim);
+        }
+        assert_eq!(vals(&sim), at_eight);
+        assert_eq!(history.range(), Some((0, 8)));
+    }
+
+    #[test]
+    fn test_memory_budget_drops_oldest() {
+        let geo = RectGeometry::new(Vector2::new(0.0, 0.0), 16, 16, Vector2::new(1.0, 1.0));
+        let mut sim = soup(&geo);
+        let mut history = History::new(5, 4096);
+        history.record(&sim);
+        for _ in 0..50 {
+            sim.step();
+            history.record(&sim);
+        }
+        let (first, last) = history.range().unwrap();
+        assert_eq!(last, 50);
+        assert!(first > 0);
+        assert!(history.memory_usage() <= 4096, "{} bytes", history.memory_usage());
+        let now = vals(&sim);
+        assert!(history.seek(&mut sim, first));
+        assert!(history.seek(&mut sim, last));
+        assert_eq!(vals(&sim), now);
+    }
+
+    #[test]
+    fn test_empire_names_count_in_budget() {
+        use crate::empire::{Attributes, Terrain};
+        use crate::hex_geom::HexGeometry;
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 8, 8, 1.0);
+        let mut sim = EmpireSimulation::new(&geo, vec![vec![Terrain::Earth; 8]; 8], rng::seeded(3));
+        for i in 0..4 {
+            sim.found_empire(2 * i, 2 * i, Some("N".repeat(1000)), Attributes::default());
+        }
+        let mut history = History::new(5, 64 * 1024);
+        history.record(&sim);
+        for _ in 0..50 {
+            sim.step();
+            history.record(&sim);
+        }
+        // Every frame holds the 4000 bytes of names, even the deltas that change no cell
+        assert!(history.memory_usage() >= history.len() * 4000);
+        assert!(history.memory_usage() <= 64 * 1024, "{} bytes", history.memory_usage());
+        assert!(history.range().unwrap().0 > 0);
+    }
+}
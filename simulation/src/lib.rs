@@ -1,6 +1,22 @@
+pub mod math;
 pub mod geometry;
 pub mod rect_geom;
 pub mod simulation;
 pub mod conway_sim;
 pub mod hex_geom;
 pub mod hex_conway_sim;
+pub mod empire;
+#[cfg(feature = "raylib")]
+pub mod draw;
+pub mod rng;
+pub mod cycle;
+pub mod history;
+pub mod observer;
+pub mod pattern;
+pub mod snapshot;
+pub mod scenario;
+pub mod raster;
+pub mod svg;
+pub mod terminal;
+pub mod recorder;
+pub mod render;
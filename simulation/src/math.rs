@@ -0,0 +1,185 @@
+use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
+
+// Own small math types, so the core does not need raylib.
+// With the `raylib` feature they convert to and from the raylib ones with into().
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Vector2 {
+    pub x: f32,
+    pub y: f32,
+}
+
+impl Vector2 {
+    pub const fn new(x: f32, y: f32) -> Self {
+        Self { x, y }
+    }
+
+    pub const fn zero() -> Self {
+        Self { x: 0.0, y: 0.0 }
+    }
+
+    pub fn length(&self) -> f32 {
+        (self.x * self.x + self.y * self.y).sqrt()
+    }
+
+    pub fn distance_to(&self, v: Vector2) -> f32 {
+        (*self - v).length()
+    }
+}
+
+impl Add for Vector2 {
+    type Output = Vector2;
+    fn add(self, v: Vector2) -> Vector2 {
+        Vector2::new(self.x + v.x, self.y + v.y)
+    }
+}
+
+impl Add<f32> for Vector2 {
+    type Output = Vector2;
+    fn add(self, s: f32) -> Vector2 {
+        Vector2::new(self.x + s, self.y + s)
+    }
+}
+
+impl Sub for Vector2 {
+    type Output = Vector2;
+    fn sub(self, v: Vector2) -> Vector2 {
+        Vector2::new(self.x - v.x, self.y - v.y)
+    }
+}
+
+impl Sub<f32> for Vector2 {
+    type Output = Vector2;
+    fn sub(self, s: f32) -> Vector2 {
+        Vector2::new(self.x - s, self.y - s)
+    }
+}
+
+// Component-wise
+impl Mul for Vector2 {
+    type Output = Vector2;
+    fn mul(self, v: Vector2) -> Vector2 {
+        Vector2::new(self.x * v.x, self.y * v.y)
+    }
+}
+
+impl Mul<f32> for Vector2 {
+    type Output = Vector2;
+    fn mul(self, s: f32) -> Vector2 {
+        Vector2::new(self.x * s, self.y * s)
+    }
+}
+
+impl Div<f32> for Vector2 {
+    type Output = Vector2;
+    fn div(self, s: f32) -> Vector2 {
+        Vector2::new(self.x / s, self.y / s)
+    }
+}
+
+impl Neg for Vector2 {
+    type Output = Vector2;
+    fn neg(self) -> Vector2 {
+        Vector2::new(-self.x, -self.y)
+    }
+}
+
+impl AddAssign for Vector2 {
+    fn add_assign(&mut self, v: Vector2) {
+        *self = *self + v;
+    }
+}
+
+impl SubAssign for Vector2 {
+    fn sub_assign(&mut self, v: Vector2) {
+        *self = *self - v;
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Vector3 {
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+impl Vector3 {
+    pub const fn new(x: f32, y: f32, z: f32) -> Self {
+        Self { x, y, z }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Rectangle {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Rectangle {
+    pub const fn new(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Self { x, y, width, height }
+    }
+
+    pub fn contains(&self, p: Vector2) -> bool {
+        self.x <= p.x && p.x < self.x + self.width && self.y <= p.y && p.y < self.y + self.height
+    }
+}
+
+#[cfg(feature = "raylib")]
+mod raylib_conv {
+    use super::*;
+
+    impl From<Vector2> for raylib::math::Vector2 {
+        fn from(v: Vector2) -> Self {
+            raylib::math::Vector2 { x: v.x, y: v.y }
+        }
+    }
+
+    impl From<raylib::math::Vector2> for Vector2 {
+        fn from(v: raylib::math::Vector2) -> Self {
+            Vector2::new(v.x, v.y)
+        }
+    }
+
+    impl From<Vector3> for raylib::math::Vector3 {
+        fn from(v: Vector3) -> Self {
+            raylib::math::Vector3 { x: v.x, y: v.y, z: v.z }
+        }
+    }
+
+    impl From<raylib::math::Vector3> for Vector3 {
+        fn from(v: raylib::math::Vector3) -> Self {
+            Vector3::new(v.x, v.y, v.z)
+        }
+    }
+
+    impl From<Rectangle> for raylib::math::Rectangle {
+        fn from(r: Rectangle) -> Self {
+            raylib::math::Rectangle { x: r.x, y: r.y, width: r.width, height: r.height }
+        }
+    }
+
+    impl From<raylib::math::Rectangle> for Rectangle {
+        fn from(r: raylib::math::Rectangle) -> Self {
+            Rectangle::new(r.x, r.y, r.width, r.height)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vector2() {
+        let v = Vector2::new(1.0, 2.0);
+        assert_eq!(v + Vector2::new(2.0, 2.0), Vector2::new(3.0, 4.0));
+        assert_eq!((v + 0.5) * Vector2::new(2.0, 4.0) / 2.0, Vector2::new(1.5, 5.0));
+        assert_eq!(-v - 1.0, Vector2::new(-2.0, -3.0));
+        assert_eq!(Vector2::zero().distance_to(Vector2::new(3.0, 4.0)), 5.0);
+        assert!(Rectangle::new(0.0, 0.0, 2.0, 1.5).contains(v * 0.5));
+        assert!(!Rectangle::new(0.0, 0.0, 2.0, 1.5).contains(v));
+    }
+}
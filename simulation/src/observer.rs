@@ -0,0 +1,175 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Model with no specific events (e.g. Conway)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NoEvent {}
+
+// Hook into the step of a simulation. S is the cell state and E the model-specific event type.
+// All methods have an empty default, implement only what you need.
+pub trait StepObserver<S, E = NoEvent> {
+    // id is the sequential index of the cell (row major for the hex grids)
+    fn on_cell_change(&mut self, _id: usize, _old: &S, _new: &S) {}
+    fn on_event(&mut self, _event: &E) {}
+    // Called once all the changes of the generation have been notified
+    fn on_generation_end(&mut self, _generation: u64) {}
+}
+
+// Keep an Rc to the observer to read its results while the simulation owns the other half
+impl<S, E, T: StepObserver<S, E>> StepObserver<S, E> for Rc<RefCell<T>> {
+    fn on_cell_change(&mut self, id: usize, old: &S, new: &S) {
+        self.borrow_mut().on_cell_change(id, old, new)
+    }
+
+    fn on_event(&mut self, event: &E) {
+        self.borrow_mut().on_event(event)
+    }
+
+    fn on_generation_end(&mut self, generation: u64) {
+        self.borrow_mut().on_generation_end(generation)
+    }
+}
+
+// The observers registered on a simulation
+pub struct Observers<S, E = NoEvent> {
+    list: Vec<Box<dyn StepObserver<S, E>>>,
+}
+
+impl<S, E> Default for Observers<S, E> {
+    fn default() -> Self {
+        Self { list: Vec::new() }
+    }
+}
+
+impl<S, E> Observers<S, E> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, observer: Box<dyn StepObserver<S, E>>) {
+        self.list.push(observer);
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+    }
+
+    // Simulations check this to skip the per-cell bookkeeping when nobody is listening
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn cell_changed(&mut self, id: usize, old: &S, new: &S) {
+        for o in self.list.iter_mut() {
+            o.on_cell_change(id, old, new);
+        }
+    }
+
+    pub fn event(&mut self, event: &E) {
+        for o in self.list.iter_mut() {
+            o.on_event(event);
+        }
+    }
+
+    pub fn generation_end(&mut self, generation: u64) {
+        for o in self.list.iter_mut() {
+            o.on_generation_end(generation);
+        }
+    }
+}
+
+// Plugin: number of changed cells for every generation
+#[derive(Default, Debug)]
+pub struct ChangeStats {
+    current: usize,
+    pub per_generation: Vec<(u64, usize)>,
+}
+
+impl<S, E> StepObserver<S, E> for ChangeStats {
+    fn on_cell_change(&mut self, _id: usize, _old: &S, _new: &S) {
+        self.current += 1;
+    }
+
+    fn on_generation_end(&mut self, generation: u64) {
+        self.per_generation.push((generation, self.current));
+        self.current = 0;
+    }
+}
+
+// Plugin: every model event, tagged with the generation that produced it
+#[derive(Debug)]
+pub struct EventLog<E> {
+    pending: Vec<E>,
+    pub events: Vec<(u64, E)>,
+}
+
+impl<E> Default for EventLog<E> {
+    fn default() -> Self {
+        Self { pending: Vec::new(), events: Vec::new() }
+    }
+}
+
+impl<S, E: Clone> StepObserver<S, E> for EventLog<E> {
+    fn on_event(&mut self, event: &E) {
+        self.pending.push(event.clone());
+    }
+
+    fn on_generation_end(&mut self, generation: u64) {
+        self.events.extend(self.pending.drain(..).map(|e| (generation, e)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conway_sim::{ConwaySimulation, State};
+    use crate::rect_geom::RectGeometry;
+    use crate::math::Vector2;
+
+    #[derive(Default)]
+    struct Births(Vec<usize>);
+
+    impl StepObserver<State> for Births {
+        fn on_cell_change(&mut self, id: usize, old: &State, new: &State) {
+            if old.val == 0 && new.val > 0 {
+                self.0.push(id);
+            }
+        }
+    }
+
+    #[test]
+    fn test_blinker_changes() {
+        let geo = RectGeometry::new(Vector2::new(0.0, 0.0), 5, 5, Vector2::new(1.0, 1.0));
+        let mut sim = ConwaySimulation::new(&geo, &[3], &[2,3]);
+        for x in 1..4 {
+            sim.get_mut(geo.cell2id(x, 2).0).unwrap().val = 1;
+        }
+        let stats = Rc::new(RefCell::new(ChangeStats::default()));
+        let births = Rc::new(RefCell::new(Births::default()));
+        sim.add_observer(Box::new(stats.clone()));
+        sim.add_observer(Box::new(births.clone()));
+        sim.step();
+        sim.step();
+        // Two cells die and two are born at every generation
+        assert_eq!(stats.borrow().per_generation, vec![(1, 4), (2, 4)]);
+        let mut born = births.borrow().0.clone();
+        born.sort();
+        let mut expected = vec![geo.cell2id(2, 1).0, geo.cell2id(2, 3).0, geo.cell2id(1, 2).0, geo.cell2id(3, 2).0];
+        expected.sort();
+        assert_eq!(born, expected);
+    }
+
+    #[test]
+    fn test_event_log_tags_generation() {
+        let log = Rc::new(RefCell::new(EventLog::default()));
+        let mut observers: Observers<u8, &str> = Observers::new();
+        observers.add(Box::new(log.clone()));
+        observers.event(&"founded");
+        observers.generation_end(1);
+        observers.generation_end(2);
+        observers.event(&"captured");
+        observers.event(&"eliminated");
+        observers.generation_end(3);
+        assert_eq!(log.borrow().events, vec![(1, "founded"), (3, "captured"), (3, "eliminated")]);
+    }
+}
@@ -0,0 +1,372 @@
+use std::fmt;
+use crate::conway_sim::ConwaySimulation;
+use crate::rect_geom::RectGeometry;
+
+pub mod rle;
+pub mod plaintext;
+pub mod life;
+pub mod macrocell;
+
+// Most alive cells a pattern file can hold, larger ones are rejected before being expanded
+pub const MAX_CELLS: usize = 1 << 24;
+
+// A two-state pattern: the alive cells inside a width x height box, (0,0) is the top-left corner.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Pattern {
+    pub width: usize,
+    pub height: usize,
+    pub cells: Vec<(usize, usize)>, // Sorted by row, then column
+    pub rule: Option<String>,
+    pub name: Option<String>,
+    pub comments: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Rotation {
+    #[default]
+    R0,
+    R90, // Clockwise
+    R180,
+    R270,
+}
+
+// Mirror on the vertical axis first (if flip), then rotate
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Transform {
+    pub rotation: Rotation,
+    pub flip: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PatternError {
+    pub line: usize, // 1-based
+    pub column: usize, // 1-based
+    pub message: String,
+}
+
+impl PatternError {
+    pub fn new(line: usize, column: usize, message: impl Into<String>) -> Self {
+        Self { line, column, message: message.into() }
+    }
+}
+
+impl fmt::Display for PatternError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for PatternError {}
+
+impl Pattern {
+    pub fn new(width: usize, height: usize, cells: impl IntoIterator<Item = (usize, usize)>) -> Self {
+        let mut p = Pattern { width, height, cells: cells.into_iter().collect(), ..Default::default() };
+        p.normalize();
+        p
+    }
+
+    // Pattern from signed coordinates, moved to the top-left corner of their bounding box
+    pub fn from_coords(cells: &[(isize, isize)]) -> Self {
+        let min_x = cells.iter().map(|c| c.0).min().unwrap_or(0);
+        let min_y = cells.iter().map(|c| c.1).min().unwrap_or(0);
+        let width = cells.iter().map(|c| (c.0 - min_x + 1) as usize).max().unwrap_or(0);
+        let height = cells.iter().map(|c| (c.1 - min_y + 1) as usize).max().unwrap_or(0);
+        Pattern::new(width, height, cells.iter().map(|&(x, y)| ((x - min_x) as usize, (y - min_y) as usize)))
+    }
+
+    pub fn is_alive(&self, x: usize, y: usize) -> bool {
+        self.cells.binary_search_by_key(&(y, x), |&(cx, cy)| (cy, cx)).is_ok()
+    }
+
+    pub(crate) fn normalize(&mut self) {
+        self.cells.sort_unstable_by_key(|&(x, y)| (y, x));
+        self.cells.dedup();
+    }
+
+    pub fn transformed(&self, t: Transform) -> Pattern {
+        let (w, h) = (self.width, self.height);
+        let (width, height) = match t.rotation {
+            Rotation::R0 | Rotation::R180 => (w, h),
+            Rotation::R90 | Rotation::R270 => (h, w),
+        };
+        let cells = self.cells.iter().map(|&(x, y)| {
+            let x = if t.flip { w - 1 - x } else { x };
+            match t.rotation {
+                Rotation::R0 => (x, y),
+                Rotation::R90 => (h - 1 - y, x),
+                Rotation::R180 => (w - 1 - x, h - 1 - y),
+                Rotation::R270 => (y, w - 1 - x),
+            }
+        });
+        let mut p = Pattern::new(width, height, cells);
+        p.rule = self.rule.clone();
+        p.name = self.name.clone();
+        p.comments = self.comments.clone();
+        p
+    }
+
+    // Copy the given region of a grid. Cells outside of the grid are dead.
+    pub fn from_grid<G: RectGrid + ?Sized>(grid: &G, x: usize, y: usize, width: usize, height: usize) -> Pattern {
+        let (gw, gh) = grid.grid_size();
+        let cells = (y..(y + height).min(gh))
+            .flat_map(|cy| (x..(x + width).min(gw)).map(move |cx| (cx, cy)))
+            .filter(|&(cx, cy)| grid.is_alive(cx, cy))
+            .map(|(cx, cy)| (cx - x, cy - y));
+        Pattern::new(width, height, cells)
+    }
+
+    // Overwrite the pattern box at (x, y) of the grid, after applying the transform.
+    // The part falling outside the grid is clipped.
+    pub fn stamp<G: RectGrid + ?Sized>(&self, grid: &mut G, x: usize, y: usize, t: Transform) {
+        let p = self.transformed(t);
+        let (gw, gh) = grid.grid_size();
+        for py in 0..p.height {
+            for px in 0..p.width {
+                if x + px < gw && y + py < gh {
+                    grid.set_alive(x + px, y + py, false);
+                }
+            }
+        }
+        for &(px, py) in p.cells.iter() {
+            if x + px < gw && y + py < gh {
+                grid.set_alive(x + px, y + py, true);
+            }
+        }
+    }
+}
+
+// A two-state simulation on a rectangular grid, where patterns can be stamped and exported
+pub trait RectGrid {
+    fn grid_size(&self) -> (usize, usize);
+    fn is_alive(&self, x: usize, y: usize) -> bool;
+    fn set_alive(&mut self, x: usize, y: usize, alive: bool);
+}
+
+impl RectGrid for ConwaySimulation<'_, RectGeometry> {
+    fn grid_size(&self) -> (usize, usize) {
+        (self.geo().cells.x, self.geo().cells.y)
+    }
+
+    fn is_alive(&self, x: usize, y: usize) -> bool {
+        self.get(self.geo().cell2id(x, y).0).is_some_and(|s| s.val > 0)
+    }
+
+    fn set_alive(&mut self, x: usize, y: usize, alive: bool) {
+        let id = self.geo().cell2id(x, y).0;
+        if let Some(s) = self.get_mut(id) {
+            s.val = alive as u8;
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Rle,
+    Plaintext,
+    Life105,
+    Life106,
+    Macrocell,
+}
+
+impl Format {
+    pub const ALL: [Format; 5] = [Format::Rle, Format::Plaintext, Format::Life105, Format::Life106, Format::Macrocell];
+
+    // Guess the format from the content, file extensions are not reliable in the pattern collections
+    pub fn detect(text: &str) -> Option<Format> {
+        let mut lines = text.lines().map(str::trim).filter(|l| !l.is_empty());
+        let first = lines.next()?;
+        if first.starts_with(macrocell::HEADER) {
+            return Some(Format::Macrocell);
+        }
+        if first.starts_with(life::HEADER_105) {
+            return Some(Format::Life105);
+        }
+        if first.starts_with(life::HEADER_106) {
+            return Some(Format::Life106);
+        }
+        // RLE comments start with #, plaintext ones with !
+        let body = std::iter::once(first).chain(lines).find(|l| !l.starts_with('#') && !l.starts_with('!'));
+        if first.starts_with('!') || body.is_some_and(|l| l.chars().all(|c| matches!(c, '.' | 'O' | '*'))) {
+            return Some(Format::Plaintext);
+        }
+        if body.is_some_and(|l| l.starts_with('x') && l.contains('=')) {
+            return Some(Format::Rle);
+        }
+        None
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Rle => "rle",
+            Format::Plaintext => "cells",
+            Format::Life105 | Format::Life106 => "lif",
+            Format::Macrocell => "mc",
+        }
+    }
+}
+
+// Parse a pattern in any of the supported formats
+pub fn parse(text: &str) -> Result<Pattern, PatternError> {
+    match Format::detect(text) {
+        Some(Format::Rle) => rle::parse(text),
+        Some(Format::Plaintext) => plaintext::parse(text),
+        Some(Format::Life105) => life::parse_105(text),
+        Some(Format::Life106) => life::parse_106(text),
+        Some(Format::Macrocell) => macrocell::parse(text),
+        None => Err(PatternError::new(1, 1, "unknown pattern format, expected RLE, plaintext, Life 1.05/1.06 or macrocell")),
+    }
+}
+
+pub fn write(pattern: &Pattern, format: Format) -> String {
+    match format {
+        Format::Rle => rle::write(pattern),
+        Format::Plaintext => plaintext::write(pattern),
+        Format::Life105 => life::write_105(pattern),
+        Format::Life106 => life::write_106(pattern),
+        Format::Macrocell => macrocell::write(pattern),
+    }
+}
+
+// Load a pattern from the name of a catalogue entry or from the path of a pattern file
+pub fn load(name_or_path: &str) -> Result<Pattern, Box<dyn std::error::Error>> {
+    if let Some(text) = catalogue::get(name_or_path) {
+        return Ok(rle::parse(text)?);
+    }
+    let text = std::fs::read_to_string(name_or_path)?;
+    Ok(parse(&text)?)
+}
+
+// Pattern named by the command line arguments (without the program name): the first one that
+// is not the numeric seed. Ok(None) if there is none.
+pub fn pattern_from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Option<Pattern>, String> {
+    let Some(arg) = args.into_iter().find(|a| a.parse::<u64>().is_err()) else {
+        return Ok(None);
+    };
+    load(&arg).map(Some).map_err(|e| format!("Cannot load pattern '{}': {}", arg, e))
+}
+
+// Parse a two-state rule in B/S notation ("B3/S23") or in the old S/B notation ("23/3").
+// Returns (birth, stay).
+pub fn parse_rule(rule: &str) -> Option<(Vec<u8>, Vec<u8>)> {
+    let digits = |s: &str| -> Option<Vec<u8>> {
+        s.chars().map(|c| c.to_digit(10).filter(|&d| d <= 8).map(|d| d as u8)).collect()
+    };
+    let (a, b) = rule.trim().split_once('/')?;
+    let (a_up, b_up) = (a.to_ascii_uppercase(), b.to_ascii_uppercase());
+    if let (Some(birth), Some(stay)) = (a_up.strip_prefix('B'), b_up.strip_prefix('S')) {
+        Some((digits(birth)?, digits(stay)?))
+    } else if let (Some(stay), Some(birth)) = (a_up.strip_prefix('S'), b_up.strip_prefix('B')) {
+        Some((digits(birth)?, digits(stay)?))
+    } else {
+        Some((digits(b)?, digits(a)?))
+    }
+}
+
+pub fn format_rule(birth: &[u8], stay: &[u8]) -> String {
+    let join = |v: &[u8]| v.iter().map(|d| d.to_string()).collect::<String>();
+    format!("B{}/S{}", join(birth), join(stay))
+}
+
+// Classic Life patterns, in RLE
+pub mod catalogue {
+    pub const BLOCK: &str = "#N Block\nx = 2, y = 2, rule = B3/S23\n2o$2o!\n";
+    pub const BEEHIVE: &str = "#N Beehive\nx = 4, y = 3, rule = B3/S23\nb2o$o2bo$b2o!\n";
+    pub const BLINKER: &str = "#N Blinker\nx = 3, y = 1, rule = B3/S23\n3o!\n";
+    pub const TOAD: &str = "#N Toad\nx = 4, y = 2, rule = B3/S23\nb3o$3o!\n";
+    pub const PULSAR: &str = "#N Pulsar\nx = 13, y = 13, rule = B3/S23\n2b3o3b3o2$o4bobo4bo$o4bobo4bo$o4bobo4bo$2b3o3b3o2$2b3o3b3o$o4bobo4bo$o4bobo4bo$o4bobo4bo2$2b3o3b3o!\n";
+    pub const GLIDER: &str = "#N Glider\nx = 3, y = 3, rule = B3/S23\nbo$2bo$3o!\n";
+    pub const LWSS: &str = "#N Lightweight spaceship\nx = 5, y = 4, rule = B3/S23\nbo2bo$o4b$o3bo$4o!\n";
+    pub const R_PENTOMINO: &str = "#N R-pentomino\nx = 3, y = 3, rule = B3/S23\nb2o$2o$bo!\n";
+    pub const DIEHARD: &str = "#N Diehard\nx = 8, y = 3, rule = B3/S23\n6bo$2o$bo3b3o!\n";
+    pub const ACORN: &str = "#N Acorn\nx = 7, y = 3, rule = B3/S23\nbo$3bo$2o2b3o!\n";
+    pub const GOSPER_GLIDER_GUN: &str = "#N Gosper glider gun\nx = 36, y = 9, rule = B3/S23\n24bo$22bobo$12b2o6b2o12b2o$11bo3bo4b2o12b2o$2o8bo5bo3b2o$2o8bo3bob2o4bobo$10bo5bo7bo$11bo3bo$12b2o!\n";
+
+    pub const ALL: [(&str, &str); 11] = [
+        ("block", BLOCK), ("beehive", BEEHIVE), ("blinker", BLINKER), ("toad", TOAD), ("pulsar", PULSAR),
+        ("glider", GLIDER), ("lwss", LWSS), ("r-pentomino", R_PENTOMINO), ("diehard", DIEHARD),
+        ("acorn", ACORN), ("gosper-glider-gun", GOSPER_GLIDER_GUN),
+    ];
+
+    pub fn get(name: &str) -> Option<&'static str> {
+        ALL.iter().find(|(n, _)| *n == name).map(|(_, rle)| *rle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cycle::{run_until_stable, Stability};
+    use crate::math::Vector2;
+
+    #[test]
+    fn test_transform() {
+        // .o
+        // ..
+        // oo
+        let p = Pattern::new(2, 3, [(1, 0), (0, 2), (1, 2)]);
+        let r90 = p.transformed(Transform { rotation: Rotation::R90, flip: false });
+        assert_eq!((r90.width, r90.height), (3, 2));
+        assert_eq!(r90, Pattern::new(3, 2, [(0, 0), (2, 1), (0, 1)]));
+        let flipped = p.transformed(Transform { rotation: Rotation::R0, flip: true });
+        assert_eq!(flipped, Pattern::new(2, 3, [(0, 0), (0, 2), (1, 2)]));
+        let mut back = p.clone();
+        for _ in 0..4 {
+            back = back.transformed(Transform { rotation: Rotation::R90, flip: false });
+        }
+        assert_eq!(back, p);
+    }
+
+    #[test]
+    fn test_stamp_and_export() {
+        let geo = RectGeometry::new(Vector2::new(0.0, 0.0), 20, 20, Vector2::new(1.0, 1.0));
+        let mut sim = ConwaySimulation::new(&geo, &[3], &[2,3]);
+        let glider = rle::parse(catalogue::GLIDER).unwrap();
+        glider.stamp(&mut sim, 5, 5, Transform::default());
+        let exported = Pattern::from_grid(&sim, 5, 5, 3, 3);
+        assert_eq!(exported.cells, glider.cells);
+        // Rotated by 180 degrees the glider moves up-left
+        let mut sim = ConwaySimulation::new(&geo, &[3], &[2,3]);
+        glider.stamp(&mut sim, 10, 10, Transform { rotation: Rotation::R180, flip: false });
+        assert_eq!(run_until_stable(&mut sim, 8), Some(Stability::Spaceship { dx: -1, dy: -1, period: 4, generation: 0 }));
+    }
+
+    #[test]
+    fn test_detect_and_round_trip_all_formats() {
+        for (name, text) in catalogue::ALL {
+            let p = rle::parse(text).unwrap();
+            for format in Format::ALL {
+                let written = write(&p, format);
+                assert_eq!(Format::detect(&written), Some(format), "{} as {:?}", name, format);
+                let back = parse(&written).unwrap_or_else(|e| panic!("{} as {:?}: {}", name, format, e));
+                assert_eq!((back.width, back.height, &back.cells), (p.width, p.height, &p.cells), "{} as {:?}", name, format);
+            }
+        }
+        assert_eq!(Format::detect("#C comment
+x = 1, y = 1
+o!"), Some(Format::Rle));
+        assert_eq!(Format::detect("..O
+.O.
+"), Some(Format::Plaintext));
+        assert_eq!(parse("hello").unwrap_err(), PatternError::new(1, 1, "unknown pattern format, expected RLE, plaintext, Life 1.05/1.06 or macrocell"));
+    }
+
+    #[test]
+    fn test_parse_rule() {
+        assert_eq!(parse_rule("B3/S23"), Some((vec![3], vec![2, 3])));
+        assert_eq!(parse_rule("b36/s23"), Some((vec![3, 6], vec![2, 3])));
+        assert_eq!(parse_rule("23/3"), Some((vec![3], vec![2, 3])));
+        assert_eq!(parse_rule("B3/S29"), None);
+        assert_eq!(format_rule(&[3, 6], &[2, 3]), "B36/S23");
+    }
+
+    #[test]
+    fn test_pattern_from_args() {
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(pattern_from_args(args(&[])), Ok(None));
+        assert_eq!(pattern_from_args(args(&["42"])), Ok(None));
+        let glider = rle::parse(catalogue::GLIDER).unwrap();
+        assert_eq!(pattern_from_args(args(&["42", "glider"])), Ok(Some(glider.clone())));
+        assert_eq!(pattern_from_args(args(&["glider"])), Ok(Some(glider)));
+        assert!(pattern_from_args(args(&["42", "/no/such/file.rle"])).unwrap_err().starts_with("Cannot load pattern '/no/such/file.rle'"));
+    }
+}
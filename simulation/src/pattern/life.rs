@@ -0,0 +1,180 @@
+// Life 1.05 and Life 1.06 patterns.
+// 1.05 is a list of cell blocks, each placed by a #P line relative to the pattern centre:
+//   #Life 1.05
+//   #D Comment
+//   #N            (normal rule, or #R 23/3 in S/B notation)
+//   #P -1 -1
+//   .*.
+//   ..*
+//   ***
+// 1.06 is a list of alive cell coordinates, one "x y" per line:
+//   #Life 1.06
+//   0 -1
+//   1 0
+// Coordinates can be negative, the pattern is moved to the top-left corner of its bounding box.
+use super::{format_rule, parse_rule, Pattern, PatternError};
+
+pub const HEADER_105: &str = "#Life 1.05";
+pub const HEADER_106: &str = "#Life 1.06";
+
+// Signed integer of a line, with the column where it starts
+fn parse_int(ln: usize, col: usize, token: &str) -> Result<isize, PatternError> {
+    token.parse().map_err(|_| PatternError::new(ln, col, format!("invalid coordinate '{}'", token)))
+}
+
+// Whitespace separated tokens with their 1-based column
+fn tokens(line: &str) -> impl Iterator<Item = (usize, &str)> {
+    line.split(' ').scan(0, |pos, t| {
+        let col = *pos + 1;
+        *pos += t.len() + 1;
+        Some((col, t))
+    }).filter(|(_, t)| !t.is_empty())
+}
+
+fn parse_pair(ln: usize, offset: usize, line: &str) -> Result<(isize, isize), PatternError> {
+    let mut it = tokens(line);
+    let (Some((cx, x)), Some((cy, y))) = (it.next(), it.next()) else {
+        return Err(PatternError::new(ln, offset + 1, "expected two coordinates"));
+    };
+    if let Some((c, t)) = it.next() {
+        return Err(PatternError::new(ln, offset + c, format!("unexpected '{}' after the coordinates", t)));
+    }
+    Ok((parse_int(ln, offset + cx, x)?, parse_int(ln, offset + cy, y)?))
+}
+
+pub fn parse_105(text: &str) -> Result<Pattern, PatternError> {
+    let mut cells = Vec::new();
+    let mut rule = None;
+    let mut comments = Vec::new();
+    let mut block: Option<(isize, isize)> = None; // Origin of the current #P block
+    let mut row = 0;
+    for (ln, line) in text.lines().enumerate() {
+        let ln = ln + 1;
+        let line = line.trim_end();
+        if ln == 1 {
+            if line != HEADER_105 {
+                return Err(PatternError::new(1, 1, format!("expected '{}' header", HEADER_105)));
+            }
+            continue;
+        }
+        if let Some(rest) = line.strip_prefix("#D") {
+            comments.push(rest.trim().to_string());
+        } else if line == "#N" {
+            rule = Some("B3/S23".to_string());
+        } else if let Some(rest) = line.strip_prefix("#R") {
+            let (birth, stay) = parse_rule(rest)
+                .ok_or_else(|| PatternError::new(ln, 4, format!("invalid rule '{}'", rest.trim())))?;
+            rule = Some(format_rule(&birth, &stay));
+        } else if let Some(rest) = line.strip_prefix("#P") {
+            block = Some(parse_pair(ln, 2, rest)?);
+            row = 0;
+        } else if line.starts_with('#') {
+            // Unknown line, ignore
+        } else if line.is_empty() {
+            row += block.is_some() as isize;
+        } else {
+            let Some((px, py)) = block else {
+                return Err(PatternError::new(ln, 1, "cells before the first '#P' line"));
+            };
+            for (col, c) in line.char_indices() {
+                match c {
+                    '.' => {}
+                    '*' => cells.push((px + col as isize, py + row)),
+                    c => return Err(PatternError::new(ln, col + 1, format!("unexpected character '{}', expected '.' or '*'", c))),
+                }
+            }
+            row += 1;
+        }
+    }
+    if text.lines().next().is_none() {
+        return Err(PatternError::new(1, 1, format!("expected '{}' header", HEADER_105)));
+    }
+    let mut pattern = Pattern::from_coords(&cells);
+    pattern.rule = rule;
+    pattern.comments = comments;
+    Ok(pattern)
+}
+
+pub fn write_105(pattern: &Pattern) -> String {
+    let mut out = format!("{}\n", HEADER_105);
+    if let Some(name) = &pattern.name {
+        out += &format!("#D {}\n", name);
+    }
+    for c in pattern.comments.iter() {
+        out += &format!("#D {}\n", c);
+    }
+    match pattern.rule.as_deref().and_then(parse_rule) {
+        Some((birth, stay)) if birth == [3] && stay == [2, 3] => out += "#N\n",
+        Some((birth, stay)) => {
+            let join = |v: &[u8]| v.iter().map(|d| d.to_string()).collect::<String>();
+            out += &format!("#R {}/{}\n", join(&stay), join(&birth));
+        }
+        None => {}
+    }
+    // A single block centred on the origin, trailing dead cells omitted
+    out += &format!("#P {} {}\n", -(pattern.width as isize / 2), -(pattern.height as isize / 2));
+    for y in 0..pattern.height {
+        let end = (0..pattern.width).rev().find(|&x| pattern.is_alive(x, y)).map_or(0, |x| x + 1);
+        let line: String = (0..end).map(|x| if pattern.is_alive(x, y) { '*' } else { '.' }).collect();
+        out += if line.is_empty() { "." } else { &line };
+        out.push('\n');
+    }
+    out
+}
+
+pub fn parse_106(text: &str) -> Result<Pattern, PatternError> {
+    let mut cells = Vec::new();
+    for (ln, line) in text.lines().enumerate() {
+        let ln = ln + 1;
+        if ln == 1 {
+            if line.trim_end() != HEADER_106 {
+                return Err(PatternError::new(1, 1, format!("expected '{}' header", HEADER_106)));
+            }
+            continue;
+        }
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        cells.push(parse_pair(ln, 0, line)?);
+    }
+    if text.lines().next().is_none() {
+        return Err(PatternError::new(1, 1, format!("expected '{}' header", HEADER_106)));
+    }
+    Ok(Pattern::from_coords(&cells))
+}
+
+pub fn write_106(pattern: &Pattern) -> String {
+    let mut out = format!("{}\n", HEADER_106);
+    for &(x, y) in pattern.cells.iter() {
+        out += &format!("{} {}\n", x, y);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_life_105() {
+        let text = "#Life 1.05\n#D Two blocks\n#R 23/36\n#P -3 -1\n**\n**\n#P 1 0\n*\n.*\n";
+        let p = parse_105(text).unwrap();
+        assert_eq!(p.rule.as_deref(), Some("B36/S23"));
+        assert_eq!(p.comments, vec!["Two blocks"]);
+        assert_eq!((p.width, p.height), (6, 3));
+        assert_eq!(p.cells, vec![(0, 0), (1, 0), (0, 1), (1, 1), (4, 1), (5, 2)]);
+        assert_eq!(parse_105(&write_105(&p)).unwrap(), p);
+        assert_eq!(parse_105("#Life 1.05\n#P 0 x\n").unwrap_err(), PatternError::new(2, 6, "invalid coordinate 'x'"));
+        assert_eq!(parse_105("#Life 1.05\n#P 0 0\n.*o\n").unwrap_err().column, 3);
+    }
+
+    #[test]
+    fn test_life_106() {
+        let p = parse_106("#Life 1.06\n0 -1\n1 0\n-1 1\n0 1\n1 1\n").unwrap();
+        assert_eq!((p.width, p.height), (3, 3));
+        assert_eq!(p.cells, vec![(1, 0), (2, 1), (0, 2), (1, 2), (2, 2)]);
+        assert_eq!(write_106(&p), "#Life 1.06\n1 0\n2 1\n0 2\n1 2\n2 2\n");
+        assert_eq!(parse_106("#Life 1.06\n0 0\n3  4 5\n").unwrap_err(), PatternError::new(3, 6, "unexpected '5' after the coordinates"));
+        assert_eq!(parse_106("#Life 1.06\n7\n").unwrap_err(), PatternError::new(2, 1, "expected two coordinates"));
+    }
+}
@@ -0,0 +1,277 @@
+// Golly macrocell patterns: a quadtree where identical subtrees are stored once.
+//   [M2] (golly 4.2)
+//   #R B3/S23
+//   .*$..*$***$
+//   4 1 0 0 0
+// Every line after the header is a node, numbered from 1. The 8x8 leaves are rows of . and *
+// ended by $ (trailing dead cells and rows omitted). A "k nw ne sw se" line is a 2^k square
+// made of the four child nodes of level k-1, 0 being an empty child. The last node is the root.
+use std::collections::HashMap;
+use super::{Pattern, PatternError, MAX_CELLS};
+
+pub const HEADER: &str = "[M2]";
+
+const LEAF_LEVEL: u32 = 3;
+
+enum Node {
+    Leaf([u8; 8]), // One bit per cell, bit x of row y
+    Inner { level: u32, children: [usize; 4] }, // nw, ne, sw, se
+}
+
+impl Node {
+    fn level(&self) -> u32 {
+        match self {
+            Node::Leaf(_) => LEAF_LEVEL,
+            Node::Inner { level, .. } => *level,
+        }
+    }
+
+    // Alive cells, from the population of the nodes before it
+    fn population(&self, populations: &[usize]) -> usize {
+        match self {
+            Node::Leaf(rows) => rows.iter().map(|r| r.count_ones() as usize).sum(),
+            Node::Inner { children, .. } => children.iter().filter(|&&c| c > 0)
+                .fold(0, |sum: usize, &c| sum.saturating_add(populations[c - 1])),
+        }
+    }
+}
+
+fn parse_leaf(ln: usize, line: &str) -> Result<Node, PatternError> {
+    let mut rows = [0_u8; 8];
+    let (mut x, mut y) = (0, 0);
+    for (col, c) in line.char_indices() {
+        let col = col + 1;
+        match c {
+            '.' | '*' => {
+                if x >= 8 || y >= 8 {
+                    return Err(PatternError::new(ln, col, "cell outside of the 8x8 leaf"));
+                }
+                if c == '*' {
+                    rows[y] |= 1 << x;
+                }
+                x += 1;
+            }
+            '$' => {
+                x = 0;
+                y += 1;
+            }
+            c => return Err(PatternError::new(ln, col, format!("unexpected character '{}' in leaf", c))),
+        }
+    }
+    Ok(Node::Leaf(rows))
+}
+
+fn parse_inner(ln: usize, line: &str, nodes: &[Node]) -> Result<Node, PatternError> {
+    let mut fields = Vec::new();
+    let mut pos = 0;
+    for t in line.split(' ') {
+        if !t.is_empty() {
+            fields.push((pos + 1, t));
+        }
+        pos += t.len() + 1;
+    }
+    if fields.len() != 5 {
+        return Err(PatternError::new(ln, 1, format!("expected 'level nw ne sw se', found {} fields", fields.len())));
+    }
+    let mut values = [0_usize; 5];
+    for (v, &(col, t)) in values.iter_mut().zip(fields.iter()) {
+        *v = t.parse().map_err(|_| PatternError::new(ln, col, format!("invalid number '{}'", t)))?;
+    }
+    let level = values[0] as u32;
+    if level <= LEAF_LEVEL || level > 62 {
+        return Err(PatternError::new(ln, fields[0].0, format!("unsupported node level {}, only two-state patterns are supported", level)));
+    }
+    let mut children = [0; 4];
+    for i in 0..4 {
+        let (col, child) = (fields[i + 1].0, values[i + 1]);
+        if child > nodes.len() {
+            return Err(PatternError::new(ln, col, format!("node {} is not defined yet", child)));
+        }
+        if child > 0 && nodes[child - 1].level() != level - 1 {
+            return Err(PatternError::new(ln, col, format!("node {} is not of level {}", child, level - 1)));
+        }
+        children[i] = child;
+    }
+    Ok(Node::Inner { level, children })
+}
+
+fn collect(nodes: &[Node], id: usize, x: usize, y: usize, cells: &mut Vec<(usize, usize)>) {
+    if id == 0 {
+        return;
+    }
+    match &nodes[id - 1] {
+        Node::Leaf(rows) => {
+            for (dy, row) in rows.iter().enumerate() {
+                cells.extend((0..8).filter(|dx| row & (1 << dx) != 0).map(|dx| (x + dx, y + dy)));
+            }
+        }
+        Node::Inner { level, children } => {
+            let half = 1 << (level - 1);
+            collect(nodes, children[0], x, y, cells);
+            collect(nodes, children[1], x + half, y, cells);
+            collect(nodes, children[2], x, y + half, cells);
+            collect(nodes, children[3], x + half, y + half, cells);
+        }
+    }
+}
+
+pub fn parse(text: &str) -> Result<Pattern, PatternError> {
+    let mut pattern = Pattern::default();
+    let mut nodes: Vec<Node> = Vec::new();
+    let mut populations = Vec::new();
+    for (ln, line) in text.lines().enumerate() {
+        let ln = ln + 1;
+        let line = line.trim_end();
+        if ln == 1 {
+            if !line.starts_with(HEADER) {
+                return Err(PatternError::new(1, 1, format!("expected '{}' header", HEADER)));
+            }
+            continue;
+        }
+        if let Some(comment) = line.strip_prefix('#') {
+            let (tag, rest) = comment.split_at(comment.len().min(1));
+            match tag {
+                "R" => pattern.rule = Some(rest.trim().to_string()),
+                "N" => pattern.name = Some(rest.trim().to_string()),
+                "C" => pattern.comments.push(rest.trim().to_string()),
+                _ => {} // #G generation and unknown lines
+            }
+            continue;
+        }
+        if line.is_empty() {
+            continue;
+        }
+        let node = match line.chars().next() {
+            Some('.' | '*' | '$') => parse_leaf(ln, line)?,
+            _ => parse_inner(ln, line, &nodes)?,
+        };
+        let population = node.population(&populations);
+        if population > MAX_CELLS {
+            return Err(PatternError::new(ln, 1, format!("more than {} alive cells", MAX_CELLS)));
+        }
+        populations.push(population);
+        nodes.push(node);
+    }
+    if text.lines().next().is_none() {
+        return Err(PatternError::new(1, 1, format!("expected '{}' header", HEADER)));
+    }
+    let mut cells = Vec::new();
+    collect(&nodes, nodes.len(), 0, 0, &mut cells);
+    let cells: Vec<(isize, isize)> = cells.into_iter().map(|(x, y)| (x as isize, y as isize)).collect();
+    let shape = Pattern::from_coords(&cells);
+    pattern.width = shape.width;
+    pattern.height = shape.height;
+    pattern.cells = shape.cells;
+    Ok(pattern)
+}
+
+// Builds the node list bottom-up, sharing identical subtrees
+#[derive(Default)]
+struct Builder {
+    lines: Vec<String>,
+    ids: HashMap<String, usize>,
+}
+
+impl Builder {
+    fn node(&mut self, line: String) -> usize {
+        if let Some(&id) = self.ids.get(&line) {
+            return id;
+        }
+        self.lines.push(line.clone());
+        self.ids.insert(line, self.lines.len());
+        self.lines.len()
+    }
+
+    // cells are relative to the top-left corner of the 2^level square
+    fn build(&mut self, cells: Vec<(usize, usize)>, level: u32) -> usize {
+        if cells.is_empty() {
+            return 0;
+        }
+        if level == LEAF_LEVEL {
+            let mut rows = [[false; 8]; 8];
+            for (x, y) in cells {
+                rows[y][x] = true;
+            }
+            let last_row = rows.iter().rposition(|r| r.contains(&true)).unwrap();
+            let mut line = String::new();
+            for row in rows.iter().take(last_row + 1) {
+                let end = row.iter().rposition(|&c| c).map_or(0, |x| x + 1);
+                line.extend(row[..end].iter().map(|&c| if c { '*' } else { '.' }));
+                line.push('$');
+            }
+            return self.node(line);
+        }
+        let half = 1 << (level - 1);
+        let mut quadrants: [Vec<(usize, usize)>; 4] = Default::default();
+        for (x, y) in cells {
+            let q = (x >= half) as usize + 2 * (y >= half) as usize;
+            quadrants[q].push((x % half, y % half));
+        }
+        let [nw, ne, sw, se] = quadrants.map(|q| self.build(q, level - 1));
+        self.node(format!("{} {} {} {} {}", level, nw, ne, sw, se))
+    }
+}
+
+pub fn write(pattern: &Pattern) -> String {
+    let mut out = format!("{} (empiresim)\n", HEADER);
+    if let Some(rule) = &pattern.rule {
+        out += &format!("#R {}\n", rule);
+    }
+    if let Some(name) = &pattern.name {
+        out += &format!("#N {}\n", name);
+    }
+    for c in pattern.comments.iter() {
+        out += &format!("#C {}\n", c);
+    }
+    let mut level = LEAF_LEVEL;
+    while (1 << level) < pattern.width.max(pattern.height) {
+        level += 1;
+    }
+    let mut builder = Builder::default();
+    if builder.build(pattern.cells.clone(), level) == 0 {
+        // The root must exist even when empty
+        builder.node("$".to_string());
+    }
+    for line in builder.lines {
+        out += &line;
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::{catalogue, rle};
+
+    #[test]
+    fn test_parse_and_share_nodes() {
+        // Two gliders in the nw and se quadrants of a 16x16 square, the leaf is stored once
+        let text = "[M2] (golly 4.2)\n#R B3/S23\n.*$..*$***$\n4 1 0 0 1\n";
+        let p = parse(text).unwrap();
+        assert_eq!(p.rule.as_deref(), Some("B3/S23"));
+        assert_eq!((p.width, p.height), (11, 11));
+        assert_eq!(p.cells.len(), 10);
+        assert!(p.is_alive(1, 0) && p.is_alive(10, 10) && p.is_alive(8, 10));
+        let written = write(&p);
+        assert_eq!(parse(&written).unwrap(), p);
+
+        let gun = rle::parse(catalogue::GOSPER_GLIDER_GUN).unwrap();
+        assert_eq!(parse(&write(&gun)).unwrap().cells, gun.cells);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(parse("[M2]\n.*$\n4 1 2 0 0\n").unwrap_err(), PatternError::new(3, 5, "node 2 is not defined yet"));
+        assert_eq!(parse("[M2]\n.*$\n5 1 0 0 0\n").unwrap_err(), PatternError::new(3, 3, "node 1 is not of level 4"));
+        assert_eq!(parse("[M2]\n.*o$\n").unwrap_err(), PatternError::new(2, 3, "unexpected character 'o' in leaf"));
+        assert_eq!(parse("[M2]\n1 0 1 1 0\n").unwrap_err().column, 1);
+        // Each level four times the one below, 4^27 cells in a few lines
+        let mut text = "[M2]\n*$\n".to_string();
+        for level in 4..=30 {
+            text += &format!("{} {} {} {} {}\n", level, level - 3, level - 3, level - 3, level - 3);
+        }
+        let e = parse(&text).unwrap_err();
+        assert_eq!((e.line, e.message), (15, format!("more than {} alive cells", MAX_CELLS)));
+    }
+}
@@ -0,0 +1,73 @@
+// LifeWiki plaintext (.cells) patterns:
+//   !Name: Glider
+//   !Comment
+//   .O.
+//   ..O
+//   OOO
+// . = dead, O = alive (* is accepted too). Rows may omit trailing dead cells.
+use super::{Pattern, PatternError};
+
+pub fn parse(text: &str) -> Result<Pattern, PatternError> {
+    let mut pattern = Pattern::default();
+    let mut y = 0;
+    let mut last_row = 0; // 1 + index of the last non-empty row
+    for (ln, line) in text.lines().enumerate() {
+        let ln = ln + 1;
+        if let Some(comment) = line.strip_prefix('!') {
+            if let Some(name) = comment.strip_prefix("Name:") {
+                pattern.name = Some(name.trim().to_string());
+            } else {
+                pattern.comments.push(comment.trim().to_string());
+            }
+            continue;
+        }
+        let row = line.trim_end();
+        for (col, c) in row.char_indices() {
+            match c {
+                '.' => {}
+                'O' | '*' => pattern.cells.push((col, y)),
+                c => return Err(PatternError::new(ln, col + 1, format!("unexpected character '{}', expected '.' or 'O'", c))),
+            }
+        }
+        pattern.width = pattern.width.max(row.len());
+        y += 1;
+        if !row.is_empty() {
+            last_row = y;
+        }
+    }
+    pattern.height = last_row;
+    pattern.normalize();
+    Ok(pattern)
+}
+
+pub fn write(pattern: &Pattern) -> String {
+    let mut out = String::new();
+    if let Some(name) = &pattern.name {
+        out += &format!("!Name: {}\n", name);
+    }
+    for c in pattern.comments.iter() {
+        out += &format!("!{}\n", c);
+    }
+    // Full rows, so that the size survives the round trip
+    for y in 0..pattern.height {
+        out.extend((0..pattern.width).map(|x| if pattern.is_alive(x, y) { 'O' } else { '.' }));
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_write() {
+        let p = parse("!Name: Glider\n!A small spaceship\n.O\n..O\nOOO\n\n").unwrap();
+        assert_eq!(p.name.as_deref(), Some("Glider"));
+        assert_eq!(p.comments, vec!["A small spaceship"]);
+        assert_eq!((p.width, p.height), (3, 3));
+        assert_eq!(p.cells, vec![(1, 0), (2, 1), (0, 2), (1, 2), (2, 2)]);
+        assert_eq!(write(&p), "!Name: Glider\n!A small spaceship\n.O.\n..O\nOOO\n");
+        assert_eq!(parse("!Name: x\n..O\n.#O\n").unwrap_err(), PatternError::new(3, 2, "unexpected character '#', expected '.' or 'O'"));
+    }
+}
@@ -0,0 +1,274 @@
+// Golly/LifeWiki run length encoded patterns:
+//   #N Name
+//   #C Comment
+//   x = 3, y = 3, rule = B3/S23
+//   bo$2bo$3o!
+// b = dead, o = alive, $ = end of row, ! = end of pattern. A number before a tag repeats it.
+use super::{Pattern, PatternError, MAX_CELLS};
+
+const MAX_LINE: usize = 70;
+
+pub fn parse(text: &str) -> Result<Pattern, PatternError> {
+    let mut pattern = Pattern::default();
+    let mut header_seen = false;
+    let (mut x, mut y) = (0_usize, 0_usize);
+    let mut count: Option<(usize, usize)> = None; // (value, column where it started)
+    let mut ended = false;
+    let mut last = (1, 1);
+    for (ln, line) in text.lines().enumerate() {
+        let ln = ln + 1;
+        let trimmed = line.trim();
+        last = (ln, line.len() + 1);
+        if ended {
+            break;
+        }
+        if !header_seen {
+            if trimmed.is_empty() {
+                continue;
+            }
+            if let Some(comment) = trimmed.strip_prefix('#') {
+                let (tag, rest) = comment.split_at(comment.len().min(1));
+                match tag {
+                    "N" => pattern.name = Some(rest.trim().to_string()),
+                    "C" | "c" | "O" => pattern.comments.push(rest.trim().to_string()),
+                    _ => {} // #P, #R offsets and unknown lines
+                }
+                continue;
+            }
+            parse_header(trimmed, ln, line, &mut pattern)?;
+            header_seen = true;
+            continue;
+        }
+        for (col, c) in line.char_indices() {
+            let col = col + 1;
+            match c {
+                '0'..='9' => {
+                    let d = c.to_digit(10).unwrap() as usize;
+                    let (v, start) = count.unwrap_or((0, col));
+                    let v = v.checked_mul(10).and_then(|v| v.checked_add(d))
+                        .ok_or_else(|| PatternError::new(ln, start, "run count too large"))?;
+                    count = Some((v, start));
+                }
+                'b' | '.' | 'o' => {
+                    let (n, start) = count.take().unwrap_or((1, col));
+                    let end = x.checked_add(n).ok_or_else(|| PatternError::new(ln, start, "run count too large"))?;
+                    if c == 'o' {
+                        if end > pattern.width || y >= pattern.height {
+                            return Err(PatternError::new(ln, col, format!("alive cell outside the declared size {}x{}", pattern.width, pattern.height)));
+                        }
+                        if pattern.cells.len() + n > MAX_CELLS {
+                            return Err(PatternError::new(ln, col, format!("more than {} alive cells", MAX_CELLS)));
+                        }
+                        pattern.cells.extend((x..end).map(|cx| (cx, y)));
+                    }
+                    x = end;
+                }
+                '$' => {
+                    let (n, start) = count.take().unwrap_or((1, col));
+                    y = y.checked_add(n).ok_or_else(|| PatternError::new(ln, start, "run count too large"))?;
+                    x = 0;
+                }
+                '!' => {
+                    if let Some((_, start)) = count {
+                        return Err(PatternError::new(ln, start, "run count without a cell tag"));
+                    }
+                    ended = true;
+                    break;
+                }
+                c if c.is_ascii_alphabetic() => {
+                    return Err(PatternError::new(ln, col, format!("unsupported cell state '{}', only two-state patterns are supported", c)));
+                }
+                c if c.is_whitespace() => {}
+                c => return Err(PatternError::new(ln, col, format!("unexpected character '{}'", c))),
+            }
+        }
+    }
+    if !header_seen {
+        return Err(PatternError::new(last.0, 1, "missing 'x = .., y = ..' header"));
+    }
+    if !ended {
+        return Err(PatternError::new(last.0, last.1, "missing '!' at the end of the pattern"));
+    }
+    pattern.normalize();
+    Ok(pattern)
+}
+
+fn parse_header(trimmed: &str, ln: usize, line: &str, pattern: &mut Pattern) -> Result<(), PatternError> {
+    let offset = line.len() - line.trim_start().len();
+    let (mut width, mut height) = (None, None);
+    // Rules may hold commas themselves ("B3/S23:T10,10"), a piece without '=' continues the rule
+    let mut items: Vec<(usize, &str)> = Vec::new();
+    let mut pos = 0;
+    for piece in trimmed.split(',') {
+        match items.last_mut() {
+            Some((start, item)) if !piece.contains('=') && item.split_once('=').is_some_and(|(k, _)| k.trim() == "rule") => {
+                *item = &trimmed[*start..pos + piece.len()];
+            }
+            _ => items.push((pos, piece)),
+        }
+        pos += piece.len() + 1;
+    }
+    for (start, item) in items {
+        let col = offset + start + 1 + (item.len() - item.trim_start().len());
+        let Some((key, value)) = item.split_once('=') else {
+            return Err(PatternError::new(ln, col, format!("expected 'key = value' in header, found '{}'", item.trim())));
+        };
+        let value = value.trim();
+        match key.trim() {
+            "x" => width = Some(value.parse::<usize>().map_err(|_| PatternError::new(ln, col, format!("invalid width '{}'", value)))?),
+            "y" => height = Some(value.parse::<usize>().map_err(|_| PatternError::new(ln, col, format!("invalid height '{}'", value)))?),
+            "rule" => pattern.rule = Some(value.to_string()),
+            k => return Err(PatternError::new(ln, col, format!("unknown header key '{}'", k))),
+        }
+    }
+    match (width, height) {
+        (Some(w), Some(h)) => {
+            pattern.width = w;
+            pattern.height = h;
+            Ok(())
+        }
+        _ => Err(PatternError::new(ln, offset + 1, "header must declare both x and y")),
+    }
+}
+
+fn push(runs: &mut Vec<(usize, char)>, n: usize, tag: char) {
+    if n == 0 {
+        return;
+    }
+    match runs.last_mut() {
+        Some((c, t)) if *t == tag => *c += n,
+        _ => runs.push((n, tag)),
+    }
+}
+
+pub fn write(pattern: &Pattern) -> String {
+    let mut out = String::new();
+    if let Some(name) = &pattern.name {
+        out += &format!("#N {}\n", name);
+    }
+    for c in pattern.comments.iter() {
+        out += &format!("#C {}\n", c);
+    }
+    out += &format!("x = {}, y = {}", pattern.width, pattern.height);
+    if let Some(rule) = &pattern.rule {
+        out += &format!(", rule = {}", rule);
+    }
+    out.push('\n');
+
+    // Runs of (count, tag); trailing dead cells of a row and trailing empty rows are omitted
+    let mut runs: Vec<(usize, char)> = Vec::new();
+    let mut pending_rows = 0;
+    let mut cells = pattern.cells.iter().peekable();
+    for y in 0..pattern.height {
+        let mut x = 0;
+        let mut row_started = false;
+        while let Some(&&(cx, cy)) = cells.peek() {
+            if cy != y {
+                break;
+            }
+            if !row_started {
+                push(&mut runs, pending_rows, '$');
+                pending_rows = 0;
+                row_started = true;
+            }
+            push(&mut runs, cx - x, 'b');
+            push(&mut runs, 1, 'o');
+            x = cx + 1;
+            cells.next();
+        }
+        pending_rows += 1;
+    }
+
+    let mut line = String::new();
+    for (n, tag) in runs.into_iter().chain(std::iter::once((1, '!'))) {
+        let token = if n == 1 { tag.to_string() } else { format!("{}{}", n, tag) };
+        if line.len() + token.len() > MAX_LINE {
+            out += &line;
+            out.push('\n');
+            line.clear();
+        }
+        line += &token;
+    }
+    out += &line;
+    out.push('\n');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pattern::catalogue;
+    use crate::conway_sim::ConwaySimulation;
+    use crate::cycle::{run_until_stable, Stability};
+    use crate::pattern::Transform;
+    use crate::rect_geom::RectGeometry;
+    use crate::math::Vector2;
+
+    #[test]
+    fn test_catalogue_round_trip() {
+        for (name, text) in catalogue::ALL {
+            let p = parse(text).unwrap_or_else(|e| panic!("{}: {}", name, e));
+            assert!(!p.cells.is_empty(), "{} is empty", name);
+            let written = write(&p);
+            assert_eq!(parse(&written).unwrap(), p, "{} does not round trip:\n{}", name, written);
+        }
+    }
+
+    #[test]
+    fn test_parse_glider() {
+        let p = parse(catalogue::GLIDER).unwrap();
+        assert_eq!(p.name.as_deref(), Some("Glider"));
+        assert_eq!(p.rule.as_deref(), Some("B3/S23"));
+        assert_eq!((p.width, p.height), (3, 3));
+        assert_eq!(p.cells, vec![(1, 0), (2, 1), (0, 2), (1, 2), (2, 2)]);
+        assert_eq!(write(&p), catalogue::GLIDER);
+    }
+
+    #[test]
+    fn test_rule_with_commas() {
+        let p = parse("x = 2, y = 1, rule = B3/S23:T10,10\n2o!").unwrap();
+        assert_eq!(p.rule.as_deref(), Some("B3/S23:T10,10"));
+        assert_eq!(parse(&write(&p)).unwrap(), p);
+        let p = parse("x = 2, rule = B3/S23:T10,10, y = 1\n2o!").unwrap();
+        assert_eq!((p.rule.as_deref(), p.height), (Some("B3/S23:T10,10"), 1));
+        assert_eq!(parse("x = 3, y = 3, 10\no!").unwrap_err(), PatternError::new(1, 15, "expected 'key = value' in header, found '10'"));
+    }
+
+    #[test]
+    fn test_multiline_and_empty_rows() {
+        let p = parse("x = 3, y = 5\n o\n2$\n3o!  trailing text").unwrap();
+        assert_eq!(p.cells, vec![(0, 0), (0, 2), (1, 2), (2, 2)]);
+        let p = Pattern::new(200, 1, (0..200).step_by(2).map(|x| (x, 0)));
+        let text = write(&p);
+        assert!(text.lines().all(|l| l.len() <= MAX_LINE));
+        assert_eq!(parse(&text).unwrap(), p);
+    }
+
+    #[test]
+    fn test_errors_have_position() {
+        assert_eq!(parse("x = 3, y = 3\nbo$2bo$3q!").unwrap_err(), PatternError::new(2, 9, "unsupported cell state 'q', only two-state patterns are supported"));
+        assert_eq!(parse("x = 3, y = 3\nbo$2bo$3o").unwrap_err().line, 2);
+        assert_eq!(parse("x = 3, y = 3\nbo$2bo$4o!").unwrap_err(), PatternError::new(2, 9, "alive cell outside the declared size 3x3"));
+        assert_eq!(parse("x = 3, z = 3\no!").unwrap_err(), PatternError::new(1, 8, "unknown header key 'z'"));
+        // Runs that would overflow, or hold too many cells
+        assert_eq!(parse("x = 3, y = 1\n18446744073709551615bo!").unwrap_err(), PatternError::new(2, 22, "run count too large"));
+        assert_eq!(parse("x = 3, y = 1\nb18446744073709551615$18446744073709551615$o!").unwrap_err(), PatternError::new(2, 23, "run count too large"));
+        let huge = format!("x = {}, y = 1\n{}o!", usize::MAX, MAX_CELLS + 1);
+        assert_eq!(parse(&huge).unwrap_err().message, format!("more than {} alive cells", MAX_CELLS));
+        assert_eq!(parse("x = 3, y = 1\n9999999999o!").unwrap_err().message, "alive cell outside the declared size 3x1");
+        assert_eq!(parse("#C only comments").unwrap_err().message, "missing 'x = .., y = ..' header");
+    }
+
+    #[test]
+    fn test_catalogue_dynamics() {
+        let geo = RectGeometry::new(Vector2::new(0.0, 0.0), 30, 30, Vector2::new(1.0, 1.0));
+        let run = |text: &str| {
+            let mut sim = ConwaySimulation::new(&geo, &[3], &[2,3]);
+            parse(text).unwrap().stamp(&mut sim, 10, 10, Transform::default());
+            run_until_stable(&mut sim, 20)
+        };
+        assert_eq!(run(catalogue::BEEHIVE), Some(Stability::Still { generation: 0 }));
+        assert_eq!(run(catalogue::PULSAR), Some(Stability::Oscillator { period: 3, generation: 0 }));
+        assert_eq!(run(catalogue::LWSS), Some(Stability::Spaceship { dx: -2, dy: 0, period: 4, generation: 0 }));
+    }
+}
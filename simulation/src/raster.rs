@@ -0,0 +1,394 @@
+use std::io::{Read, Write};
+use std::path::Path;
+use crate::empire::{Terrain, FIRST_OWNER, RIVER};
+use crate::render::{CellView, Layout};
+
+// Software rendering of the simulations into RGBA images, with no window or GPU involved.
+// Used to export figures and to run headless on servers, and for golden-image tests.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rgba(pub [u8; 4]);
+
+impl Rgba {
+    pub const WHITE: Rgba = Rgba([255, 255, 255, 255]);
+    pub const BLACK: Rgba = Rgba([0, 0, 0, 255]);
+    pub const GRAY: Rgba = Rgba([130, 130, 130, 255]);
+    pub const DARKGRAY: Rgba = Rgba([80, 80, 80, 255]);
+    pub const CYAN: Rgba = Rgba([0, 255, 255, 255]);
+    pub const TRANSPARENT: Rgba = Rgba([0, 0, 0, 0]);
+
+    pub const fn rgb(r: u8, g: u8, b: u8) -> Rgba {
+        Rgba([r, g, b, 255])
+    }
+
+    // hue in degrees, saturation and value in [0, 1], like raylib's Color::color_from_hsv
+    pub fn hsv(hue: f32, sat: f32, val: f32) -> Rgba {
+        let channel = |n: f32| {
+            let k = (n + hue / 60.0) % 6.0;
+            let k = k.min(4.0 - k).clamp(0.0, 1.0);
+            ((val - val * sat * k) * 255.0).round() as u8
+        };
+        Rgba::rgb(channel(5.0), channel(3.0), channel(1.0))
+    }
+}
+
+// Colour of the states past the table of a Palette
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Beyond {
+    // The colour of state 255
+    Last,
+    // empire_color of the empire, see empire::owner_state
+    Empire,
+}
+
+// Colour of every cell state
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    colors: Vec<Rgba>, // One per state value, 256 entries
+    beyond: Beyond,
+}
+
+impl Palette {
+    // All the states in the given colour
+    pub fn uniform(color: Rgba) -> Self {
+        Self { colors: vec![color; 256], beyond: Beyond::Last }
+    }
+
+    // Only the first 256 states have their own colour, setting a later one sets state 255, the
+    // colour of all of them
+    pub fn with(mut self, state: u32, color: Rgba) -> Self {
+        self.colors[state.min(255) as usize] = color;
+        self
+    }
+
+    pub fn get(&self, state: u32) -> Rgba {
+        match self.colors.get(state as usize) {
+            Some(c) => *c,
+            None => match self.beyond {
+                Beyond::Last => self.colors[255],
+                Beyond::Empire => empire_color(state - FIRST_OWNER),
+            },
+        }
+    }
+
+    // Dead white, alive black
+    pub fn life() -> Self {
+        Palette::uniform(Rgba::BLACK).with(0, Rgba::WHITE)
+    }
+
+    // The terrain by biome, rivers and a colour per empire for any number of empires, see
+    // empire::owner_state
+    pub fn empire() -> Self {
+        let mut p = Palette::uniform(Rgba::BLACK).with(RIVER, Rgba::rgb(30, 90, 220));
+        for terrain in Terrain::ALL {
+            p = p.with(terrain as u32, terrain_color(terrain));
+        }
+        for state in FIRST_OWNER..256 {
+            p.colors[state as usize] = empire_color(state - FIRST_OWNER);
+        }
+        p.beyond = Beyond::Empire;
+        p
+    }
+}
+
+pub fn terrain_color(terrain: Terrain) -> Rgba {
+    match terrain {
+        Terrain::Sea => Rgba::CYAN,
+        Terrain::Earth => Rgba::DARKGRAY,
+        Terrain::Plains => Rgba::rgb(150, 190, 90),
+        Terrain::Forest => Rgba::rgb(40, 110, 50),
+        Terrain::Desert => Rgba::rgb(225, 200, 130),
+        Terrain::Mountain => Rgba::rgb(120, 100, 90),
+        Terrain::Tundra => Rgba::rgb(210, 220, 225),
+    }
+}
+
+// The colours of the hexspawnempire viewer: 13 hues, less saturated and darker every 60 ids.
+// The viewer underflowed past the 14th empire, the ids in between go on around the hue circle,
+// and the shades start over after 8 rounds instead of fading to grey.
+pub fn empire_color(id: u32) -> Rgba {
+    let layer = 13;
+    let shade = (id / 60 % 8) as i32;
+    let hue = 360.0 / layer as f32 * (id % 60) as f32;
+    Rgba::hsv(hue, 0.8_f32.powi(shade), 0.9_f32.powi(shade))
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RasterStyle {
+    // Pixels per cell: the side of a rect cell, the size (centre to corner) of a hex
+    pub scale: u32,
+    pub grid: Option<Rgba>,
+    // Outside of the cells
+    pub background: Rgba,
+    pub palette: Palette,
+}
+
+impl Default for RasterStyle {
+    fn default() -> Self {
+        Self { scale: 4, grid: None, background: Rgba::WHITE, palette: Palette::life() }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Canvas {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>, // RGBA, row major
+}
+
+impl Canvas {
+    pub fn new(width: u32, height: u32, fill: Rgba) -> Self {
+        Self { width, height, pixels: fill.0.repeat(width as usize * height as usize) }
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Rgba {
+        let i = (y as usize * self.width as usize + x as usize) * 4;
+        Rgba(self.pixels[i..i + 4].try_into().unwrap())
+    }
+
+    // Out of bounds pixels are ignored
+    pub fn set(&mut self, x: i64, y: i64, c: Rgba) {
+        if x >= 0 && y >= 0 && x < self.width as i64 && y < self.height as i64 {
+            let i = (y as usize * self.width as usize + x as usize) * 4;
+            self.pixels[i..i + 4].copy_from_slice(&c.0);
+        }
+    }
+
+    pub fn fill_rect(&mut self, x: i64, y: i64, w: u32, h: u32, c: Rgba) {
+        for py in y..y + h as i64 {
+            for px in x..x + w as i64 {
+                self.set(px, py, c);
+            }
+        }
+    }
+
+    // Bresenham
+    pub fn draw_line(&mut self, (x0, y0): (i64, i64), (x1, y1): (i64, i64), c: Rgba) {
+        let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+        let (sx, sy) = (if x0 < x1 { 1 } else { -1 }, if y0 < y1 { 1 } else { -1 });
+        let (mut x, mut y, mut err) = (x0, y0, dx + dy);
+        loop {
+            self.set(x, y, c);
+            if x == x1 && y == y1 {
+                break;
+            }
+            let e2 = 2 * err;
+            if e2 >= dy {
+                err += dy;
+                x += sx;
+            }
+            if e2 <= dx {
+                err += dx;
+                y += sy;
+            }
+        }
+    }
+
+    // Number of pixels that differ, all of them if the sizes differ
+    pub fn diff(&self, other: &Canvas) -> usize {
+        if (self.width, self.height) != (other.width, other.height) {
+            return (self.width as usize * self.height as usize).max(other.width as usize * other.height as usize);
+        }
+        self.pixels.chunks(4).zip(other.pixels.chunks(4)).filter(|(a, b)| a != b).count()
+    }
+
+    pub fn write_png<W: Write>(&self, w: W) -> Result<(), png::EncodingError> {
+        let mut encoder = png::Encoder::new(w, self.width, self.height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.pixels)?;
+        writer.finish()
+    }
+
+    pub fn save_png(&self, path: impl AsRef<Path>) -> Result<(), png::EncodingError> {
+        let file = std::fs::File::create(path)?;
+        self.write_png(std::io::BufWriter::new(file))
+    }
+
+    // Only 8 bit RGBA images, the ones written by write_png
+    pub fn read_png<R: Read>(r: R) -> Result<Canvas, png::DecodingError> {
+        let mut reader = png::Decoder::new(r).read_info()?;
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels)?;
+        if (info.color_type, info.bit_depth) != (png::ColorType::Rgba, png::BitDepth::Eight) {
+            let msg = format!("expected 8 bit RGBA, found {:?} {:?}", info.color_type, info.bit_depth);
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, msg).into());
+        }
+        pixels.truncate(info.buffer_size());
+        Ok(Canvas { width: info.width, height: info.height, pixels })
+    }
+}
+
+// cell(x, y) gives the state of a cell of a cols x rows rect grid
+pub fn rect_cells(cols: usize, rows: usize, cell: impl Fn(usize, usize) -> u32, style: &RasterStyle) -> Canvas {
+    let s = style.scale;
+    // One more pixel for the closing grid lines
+    let extra = style.grid.is_some() as u32;
+    let mut canvas = Canvas::new(cols as u32 * s + extra, rows as u32 * s + extra, style.background);
+    for y in 0..rows {
+        for x in 0..cols {
+            canvas.fill_rect((x * s as usize) as i64, (y * s as usize) as i64, s, s, style.palette.get(cell(x, y)));
+        }
+    }
+    if let Some(g) = style.grid {
+        for x in 0..=cols as i64 {
+            canvas.fill_rect(x * s as i64, 0, 1, canvas.height, g);
+        }
+        for y in 0..=rows as i64 {
+            canvas.fill_rect(0, y * s as i64, canvas.width, 1, g);
+        }
+    }
+    canvas
+}
+
+// Same as rect_cells for a pointy-top, odd-r hex grid, laid out like HexGeometry::rect()
+pub fn hex_cells(cols: usize, rows: usize, cell: impl Fn(usize, usize) -> u32, style: &RasterStyle) -> Canvas {
+    let size = style.scale as f64;
+    let w = 3_f64.sqrt() * size;
+    let width = cols as f64 * w + if rows > 1 { 0.5 * w } else { 0.0 };
+    let height = 2.0 * size + if rows > 1 { (rows - 1) as f64 * 1.5 * size } else { 0.0 };
+    let mut canvas = Canvas::new(width.ceil() as u32 + 1, height.ceil() as u32 + 1, style.background);
+    // Every pixel takes the colour of the hex containing its centre
+    for py in 0..canvas.height {
+        for px in 0..canvas.width {
+            // Relative to the centre of hex (0, 0)
+            let (x, y) = (px as f64 + 0.5 - 0.5 * w, py as f64 + 0.5 - size);
+            let (ox, oy) = pixel_to_offset(x, y, size);
+            if oy >= 0 && ox >= 0 && (oy as usize) < rows && (ox as usize) < cols {
+                canvas.set(px as i64, py as i64, style.palette.get(cell(ox as usize, oy as usize)));
+            }
+        }
+    }
+    if let Some(g) = style.grid {
+        for oy in 0..rows {
+            for ox in 0..cols {
+                let cx = (ox as f64 + 0.5 + 0.5 * (oy & 1) as f64) * w;
+                let cy = size + 1.5 * size * oy as f64;
+                let corner = |i: usize| {
+                    let a = (60.0 * i as f64 - 30.0).to_radians();
+                    ((cx + size * a.cos()).floor() as i64, (cy + size * a.sin()).floor() as i64)
+                };
+                for i in 0..6 {
+                    canvas.draw_line(corner(i), corner(i + 1), g);
+                }
+            }
+        }
+    }
+    canvas
+}
+
+// Offset (odd-r) coordinates of the hex containing the point, relative to the centre of hex (0, 0)
+fn pixel_to_offset(x: f64, y: f64, size: f64) -> (i64, i64) {
+    let q = (3_f64.sqrt() / 3.0 * x - y / 3.0) / size;
+    let r = (2.0 / 3.0 * y) / size;
+    // Cube rounding
+    let s = -q - r;
+    let (mut rq, mut rr, rs) = (q.round(), r.round(), s.round());
+    let (dq, dr, ds) = ((rq - q).abs(), (rr - r).abs(), (rs - s).abs());
+    if dq > dr && dq > ds {
+        rq = -rr - rs;
+    } else if dr > ds {
+        rr = -rq - rs;
+    }
+    let (q, r) = (rq as i64, rr as i64);
+    (q + (r - (r & 1)) / 2, r)
+}
+
+pub trait Rasterize {
+    fn rasterize(&self, style: &RasterStyle) -> Canvas;
+}
+
+impl<T: CellView + ?Sized> Rasterize for T {
+    fn rasterize(&self, style: &RasterStyle) -> Canvas {
+        match self.layout() {
+            Layout::Rect(geo) => rect_cells(geo.cells.x, geo.cells.y, |x, y| self.cell(x, y), style),
+            Layout::Hex(geo) => hex_cells(geo.cols, geo.rows, |x, y| self.cell(x, y), style),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conway_sim::ConwaySimulation;
+    use crate::hex_conway_sim::HexConwaySimulation;
+    use crate::rect_geom::RectGeometry;
+    use crate::hex_geom::HexGeometry;
+    use crate::pattern::{catalogue, rle, Transform};
+    use crate::math::Vector2;
+
+    // Compare with the image in golden/. Run with UPDATE_GOLDEN=1 to rewrite it after an
+    // intended change, and check the new image by eye before committing it.
+    fn assert_golden(name: &str, canvas: &Canvas) {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("golden").join(name);
+        if std::env::var_os("UPDATE_GOLDEN").is_some() {
+            canvas.save_png(&path).unwrap();
+        }
+        let file = std::fs::File::open(&path).unwrap_or_else(|e| panic!("{}: {}, run with UPDATE_GOLDEN=1 to create it", path.display(), e));
+        let golden = Canvas::read_png(file).unwrap();
+        let diff = canvas.diff(&golden);
+        assert_eq!(diff, 0, "{} differs from the golden image in {} pixels", name, diff);
+    }
+
+    #[test]
+    fn test_rect_golden() {
+        let geo = RectGeometry::new(Vector2::new(0.0, 0.0), 12, 8, Vector2::new(1.0, 1.0));
+        let mut sim = ConwaySimulation::new(&geo, &[3], &[2,3]);
+        rle::parse(catalogue::GLIDER).unwrap().stamp(&mut sim, 1, 1, Transform::default());
+        rle::parse(catalogue::BLINKER).unwrap().stamp(&mut sim, 7, 4, Transform::default());
+        let style = RasterStyle { scale: 5, grid: Some(Rgba::GRAY), ..Default::default() };
+        let canvas = sim.rasterize(&style);
+        assert_eq!((canvas.width, canvas.height), (61, 41));
+        // Alive cell (1, 0) of the glider, at (2, 1) on the grid
+        assert_eq!(canvas.get(2 * 5 + 2, 5 + 2), Rgba::BLACK);
+        assert_eq!(canvas.get(2, 2), Rgba::WHITE);
+        assert_eq!(canvas.get(5, 3), Rgba::GRAY);
+        assert_golden("rect_glider.png", &canvas);
+    }
+
+    #[test]
+    fn test_hex_golden() {
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 8, 6, 1.0);
+        let mut sim = HexConwaySimulation::new(&geo, &[2], &[3,5]);
+        sim.states[2][3] = 1;
+        sim.states[3][3] = 1;
+        sim.states[3][4] = 1;
+        let style = RasterStyle { scale: 6, grid: Some(Rgba::GRAY), background: Rgba::TRANSPARENT, ..Default::default() };
+        let canvas = sim.rasterize(&style);
+        // Centre of hex (3, 2) and of the odd row hex (4, 3)
+        let w = 3_f64.sqrt() * 6.0;
+        assert_eq!(canvas.get((3.5 * w) as u32, 6 + 18), Rgba::BLACK);
+        assert_eq!(canvas.get((5.0 * w) as u32, 6 + 27), Rgba::BLACK);
+        assert_eq!(canvas.get((1.5 * w) as u32, 6 + 18), Rgba::WHITE);
+        assert_eq!(canvas.get(0, 0), Rgba::TRANSPARENT);
+        assert_golden("hex_cells.png", &canvas);
+
+        let palette = Palette::empire();
+        let canvas = hex_cells(6, 4, |x, y| (y * 6 + x) as u32, &RasterStyle { scale: 5, palette, ..Default::default() });
+        assert_golden("hex_empire_palette.png", &canvas);
+    }
+
+    #[test]
+    fn test_png_round_trip() {
+        let mut canvas = Canvas::new(7, 3, Rgba::TRANSPARENT);
+        canvas.draw_line((0, 0), (6, 2), Rgba::rgb(10, 20, 30));
+        let mut bytes = Vec::new();
+        canvas.write_png(&mut bytes).unwrap();
+        assert_eq!(Canvas::read_png(bytes.as_slice()).unwrap(), canvas);
+        assert_eq!(Rgba::hsv(0.0, 1.0, 1.0), Rgba::rgb(255, 0, 0));
+        assert_eq!(Rgba::hsv(120.0, 1.0, 0.5), Rgba::rgb(0, 128, 0));
+    }
+
+    #[test]
+    fn test_palettes() {
+        let p = Palette::life().with(300, Rgba::CYAN);
+        assert_eq!((p.get(1), p.get(255), p.get(1000)), (Rgba::BLACK, Rgba::CYAN, Rgba::CYAN));
+        // The first hues of the viewer, full saturation, then a darker round
+        assert_eq!(empire_color(0), Rgba::rgb(255, 0, 0));
+        assert_eq!(empire_color(1), Rgba::hsv(360.0 / 13.0, 1.0, 1.0));
+        assert_eq!(empire_color(61), Rgba::hsv(360.0 / 13.0, 0.8, 0.9));
+        let p = Palette::empire();
+        assert_eq!((p.get(FIRST_OWNER + 2), p.get(FIRST_OWNER + 1000)), (empire_color(2), empire_color(1000)));
+    }
+}
@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Write;
+use std::path::Path;
+use crate::raster::{Canvas, Rasterize, RasterStyle};
+
+// Time-lapse of a simulation as an animated GIF or APNG, rendered with the software rasterizer.
+// Frames are kept in memory until finish(), crop to a region to record long runs of big maps.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnimationFormat {
+    Gif,
+    Apng,
+}
+
+impl AnimationFormat {
+    // From the file extension: .gif or .apng. A plain .png is a single image, not an animation.
+    pub fn from_path(path: impl AsRef<Path>) -> Option<AnimationFormat> {
+        match path.as_ref().extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "gif" => Some(AnimationFormat::Gif),
+            "apng" => Some(AnimationFormat::Apng),
+            _ => None,
+        }
+    }
+}
+
+// Rectangle of the rendered image, in pixels
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecorderOptions {
+    // Capture one generation every `every`
+    pub every: u64,
+    // Time between two frames. GIF has a 10ms resolution.
+    pub delay_ms: u16,
+    pub region: Option<Region>,
+    // Scale, grid and palette of the frames
+    pub style: RasterStyle,
+    // 0 loops forever
+    pub plays: u16,
+}
+
+impl Default for RecorderOptions {
+    fn default() -> Self {
+        Self { every: 1, delay_ms: 100, region: None, style: RasterStyle::default(), plays: 0 }
+    }
+}
+
+#[derive(Debug)]
+pub enum RecordError {
+    NoFrames,
+    // GIF images are at most 65535 pixels wide and high
+    TooLarge { width: u32, height: u32 },
+    Gif(gif::EncodingError),
+    Png(png::EncodingError),
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecordError::NoFrames => write!(f, "no frames recorded"),
+            RecordError::TooLarge { width, height } => write!(f, "{}x{} is too large for a GIF, crop to a region", width, height),
+            RecordError::Gif(e) => write!(f, "{}", e),
+            RecordError::Png(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for RecordError {}
+
+impl From<gif::EncodingError> for RecordError {
+    fn from(e: gif::EncodingError) -> Self {
+        RecordError::Gif(e)
+    }
+}
+
+impl From<png::EncodingError> for RecordError {
+    fn from(e: png::EncodingError) -> Self {
+        RecordError::Png(e)
+    }
+}
+
+impl From<std::io::Error> for RecordError {
+    fn from(e: std::io::Error) -> Self {
+        RecordError::Png(e.into())
+    }
+}
+
+pub struct Recorder {
+    pub options: RecorderOptions,
+    frames: Vec<Canvas>,
+    start: u64, // Generation of the first frame, `every` counts from it
+}
+
+impl Recorder {
+    pub fn new(options: RecorderOptions) -> Self {
+        assert!(options.every > 0, "Must capture at least one generation every `every`");
+        Self { options, frames: Vec::new(), start: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.start = 0;
+    }
+
+    // Drop the frames and start over from the current state of the simulation, which is the
+    // first frame
+    pub fn start(&mut self, generation: u64, sim: &(impl Rasterize + ?Sized)) {
+        self.frames.clear();
+        self.start = generation;
+        self.capture(generation, sim);
+    }
+
+    // Call after every step, only every Nth generation from the start is kept, none before it.
+    // Returns true if captured.
+    pub fn capture(&mut self, generation: u64, sim: &(impl Rasterize + ?Sized)) -> bool {
+        if !generation.checked_sub(self.start).is_some_and(|g| g.is_multiple_of(self.options.every)) {
+            return false;
+        }
+        let canvas = sim.rasterize(&self.options.style);
+        self.push(canvas);
+        true
+    }
+
+    // Add an already rendered frame, e.g. from raster::hex_cells for models without Rasterize
+    pub fn push(&mut self, canvas: Canvas) {
+        let canvas = match self.options.region {
+            Some(r) => crop(&canvas, r),
+            None => canvas,
+        };
+        self.frames.push(canvas);
+    }
+
+    pub fn finish<W: Write>(&self, w: W, format: AnimationFormat) -> Result<(), RecordError> {
+        if self.frames.is_empty() {
+            return Err(RecordError::NoFrames);
+        }
+        match format {
+            AnimationFormat::Gif => self.write_gif(w),
+            AnimationFormat::Apng => self.write_apng(w),
+        }
+    }
+
+    // The format is chosen from the extension, GIF if unknown
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), RecordError> {
+        let format = AnimationFormat::from_path(&path).unwrap_or(AnimationFormat::Gif);
+        let file = std::fs::File::create(path)?;
+        self.finish(std::io::BufWriter::new(file), format)
+    }
+
+    fn write_gif<W: Write>(&self, w: W) -> Result<(), RecordError> {
+        let (width, height) = (self.frames[0].width, self.frames[0].height);
+        let (Ok(width), Ok(height)) = (u16::try_from(width), u16::try_from(height)) else {
+            return Err(RecordError::TooLarge { width, height });
+        };
+        // Cell palettes are small: use the exact colours if they fit in a GIF palette
+        let mut colors: HashMap<[u8; 4], u8> = HashMap::new();
+        let exact = self.frames.iter().flat_map(|f| f.pixels.chunks(4)).all(|px| {
+            let px: [u8; 4] = px.try_into().unwrap();
+            let px = if px[3] == 0 { [0; 4] } else { px };
+            if colors.contains_key(&px) {
+                return true;
+            }
+            let n = colors.len();
+            colors.insert(px, n as u8);
+            n < 256
+        });
+        let mut encoder = if exact {
+            let mut palette = vec![0; colors.len() * 3];
+            for (c, &i) in colors.iter() {
+                palette[i as usize * 3..i as usize * 3 + 3].copy_from_slice(&c[..3]);
+            }
+            gif::Encoder::new(w, width, height, &palette)?
+        } else {
+            gif::Encoder::new(w, width, height, &[])?
+        };
+        encoder.set_repeat(match self.options.plays {
+            0 => gif::Repeat::Infinite,
+            n => gif::Repeat::Finite(n - 1),
+        })?;
+        let delay = self.options.delay_ms.div_ceil(10);
+        for canvas in self.frames.iter() {
+            let mut frame = if exact {
+                let buffer: Vec<u8> = canvas.pixels.chunks(4).map(|px| {
+                    let px: [u8; 4] = px.try_into().unwrap();
+                    colors[&if px[3] == 0 { [0; 4] } else { px }]
+                }).collect();
+                gif::Frame { width, height, buffer: buffer.into(), transparent: colors.get(&[0; 4]).copied(), ..Default::default() }
+            } else {
+                // Too many colours, let the encoder quantize every frame
+                let mut pixels = canvas.pixels.clone();
+                gif::Frame::from_rgba_speed(width, height, &mut pixels, 10)
+            };
+            frame.delay = delay;
+            encoder.write_frame(&frame)?;
+        }
+        Ok(())
+    }
+
+    fn write_apng<W: Write>(&self, w: W) -> Result<(), RecordError> {
+        let (width, height) = (self.frames[0].width, self.frames[0].height);
+        let mut encoder = png::Encoder::new(w, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_animated(self.frames.len() as u32, self.options.plays as u32)?;
+        encoder.set_frame_delay(self.options.delay_ms, 1000)?;
+        let mut writer = encoder.write_header()?;
+        for canvas in self.frames.iter() {
+            writer.write_image_data(&canvas.pixels)?;
+        }
+        Ok(writer.finish()?)
+    }
+}
+
+// The part of the region outside of the canvas is dropped
+pub fn crop(canvas: &Canvas, region: Region) -> Canvas {
+    let x0 = region.x.min(canvas.width);
+    let y0 = region.y.min(canvas.height);
+    let width = region.width.min(canvas.width - x0);
+    let height = region.height.min(canvas.height - y0);
+    let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+    for y in y0..y0 + height {
+        let start = (y as usize * canvas.width as usize + x0 as usize) * 4;
+        pixels.extend_from_slice(&canvas.pixels[start..start + width as usize * 4]);
+    }
+    Canvas { width, height, pixels }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conway_sim::ConwaySimulation;
+    use crate::pattern::{catalogue, rle, Transform};
+    use crate::raster::{Palette, Rgba};
+    use crate::rect_geom::RectGeometry;
+    use crate::math::Vector2;
+
+    fn glider_run(recorder: &mut Recorder) {
+        let geo = RectGeometry::new(Vector2::new(0.0, 0.0), 10, 10, Vector2::new(1.0, 1.0));
+        let mut sim = ConwaySimulation::new(&geo, &[3], &[2,3]);
+        rle::parse(catalogue::GLIDER).unwrap().stamp(&mut sim, 1, 1, Transform::default());
+        recorder.capture(sim.generation(), &sim);
+        for _ in 0..8 {
+            sim.step();
+            recorder.capture(sim.generation(), &sim);
+        }
+    }
+
+    #[test]
+    fn test_gif() {
+        let mut recorder = Recorder::new(RecorderOptions { every: 2, delay_ms: 150, ..Default::default() });
+        glider_run(&mut recorder);
+        assert_eq!(recorder.len(), 5);
+        let mut bytes = Vec::new();
+        recorder.finish(&mut bytes, AnimationFormat::Gif).unwrap();
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(bytes.as_slice()).unwrap();
+        assert_eq!((decoder.width(), decoder.height()), (40, 40));
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            assert_eq!(frame.delay, 15);
+            frames.push(frame.buffer.to_vec());
+        }
+        assert_eq!(frames.len(), 5);
+        assert_eq!(frames[0], recorder.frames[0].pixels);
+        assert_eq!(frames[4], recorder.frames[4].pixels);
+    }
+
+    #[test]
+    fn test_apng_cropped() {
+        let region = Region { x: 4, y: 4, width: 16, height: 100 };
+        let style = RasterStyle { scale: 4, palette: Palette::life().with(1, Rgba::rgb(200, 0, 0)), ..Default::default() };
+        let mut recorder = Recorder::new(RecorderOptions { region: Some(region), style, plays: 2, ..Default::default() });
+        glider_run(&mut recorder);
+        assert_eq!(recorder.len(), 9);
+        let mut bytes = Vec::new();
+        recorder.finish(&mut bytes, AnimationFormat::Apng).unwrap();
+
+        let mut reader = png::Decoder::new(bytes.as_slice()).read_info().unwrap();
+        let info = reader.info();
+        assert_eq!((info.width, info.height), (16, 36));
+        let control = info.animation_control.unwrap();
+        assert_eq!((control.num_frames, control.num_plays), (9, 2));
+        let mut buf = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut buf).unwrap();
+        assert_eq!(buf, recorder.frames[0].pixels);
+        // Cell (2, 1) of the glider is alive, it starts at pixel (4, 0) of the cropped image
+        assert_eq!(&buf[4 * 4..5 * 4], &[200, 0, 0, 255]);
+        assert_eq!(&buf[0..4], &[255, 255, 255, 255]);
+    }
+
+    #[test]
+    fn test_no_frames() {
+        let recorder = Recorder::new(RecorderOptions::default());
+        assert!(matches!(recorder.finish(Vec::new(), AnimationFormat::Gif), Err(RecordError::NoFrames)));
+        assert_eq!(AnimationFormat::from_path("run.APNG"), Some(AnimationFormat::Apng));
+        assert_eq!(AnimationFormat::from_path("run.png"), None);
+        assert_eq!(AnimationFormat::from_path("run.mp4"), None);
+        let mut recorder = Recorder::new(RecorderOptions::default());
+        recorder.push(Canvas::new(70000, 1, Rgba::WHITE));
+        assert!(matches!(recorder.finish(Vec::new(), AnimationFormat::Gif), Err(RecordError::TooLarge { width: 70000, height: 1 })));
+    }
+
+    #[test]
+    fn test_start_captures_the_first_frame() {
+        let geo = RectGeometry::new(Vector2::new(0.0, 0.0), 10, 10, Vector2::new(1.0, 1.0));
+        let mut sim = ConwaySimulation::new(&geo, &[3], &[2,3]);
+        rle::parse(catalogue::GLIDER).unwrap().stamp(&mut sim, 1, 1, Transform::default());
+        for _ in 0..3 {
+            sim.step();
+        }
+        let mut recorder = Recorder::new(RecorderOptions { every: 2, ..Default::default() });
+        recorder.start(sim.generation(), &sim);
+        assert_eq!(recorder.len(), 1);
+        assert_eq!(recorder.frames[0], sim.rasterize(&recorder.options.style));
+        // Every 2 generations from 3, not from 0
+        let captured: Vec<bool> = (0..4).map(|_| {
+            sim.step();
+            recorder.capture(sim.generation(), &sim)
+        }).collect();
+        assert_eq!(captured, vec![false, true, false, true]);
+        assert!(!recorder.capture(1, &sim));
+    }
+}
@@ -1,7 +1,5 @@
-use raylib::prelude::*;
 use crate::geometry::*;
-
-// TODO: Forse usare un vettore proprio castabile a raylib e' meglio per rendere la lib piu indip
+use crate::math::{Rectangle, Vector2};
 
 pub struct InnerNCells {pub x: usize, pub y: usize}
 impl InnerNCells {
@@ -0,0 +1,258 @@
+use std::ops::Range;
+use crate::conway_sim::ConwaySimulation;
+use crate::empire::{CoastDistance, EmpireSimulation};
+use crate::hex_conway_sim::HexConwaySimulation;
+use crate::hex_geom::HexGeometry;
+use crate::math::{Rectangle, Vector2};
+use crate::raster::{Canvas, Rasterize, RasterStyle};
+use crate::rect_geom::RectGeometry;
+use crate::svg::SvgStyle;
+use crate::terminal::{TermRender, TermStyle, Viewport};
+
+// One way to draw every model: a simulation exposes its cells through CellView, each backend
+// (raster, SVG, terminal, raylib with the feature) paints them with the colours of a Palette.
+// A model implementing CellView gets every output format.
+
+// The shape of the map, with the world coordinates of the cells
+#[derive(Clone, Copy)]
+pub enum Layout<'a> {
+    Rect(&'a RectGeometry),
+    Hex(&'a HexGeometry),
+}
+
+impl Layout<'_> {
+    // Columns and rows
+    pub fn dims(&self) -> (usize, usize) {
+        match self {
+            Layout::Rect(geo) => (geo.cells.x, geo.cells.y),
+            Layout::Hex(geo) => (geo.cols, geo.rows),
+        }
+    }
+
+    pub fn center(&self, x: usize, y: usize) -> Vector2 {
+        match self {
+            Layout::Rect(geo) => geo.cellcenter(x, y),
+            Layout::Hex(geo) => geo.cellcenter(x, y),
+        }
+    }
+
+    // Clockwise on screen
+    pub fn corners(&self, x: usize, y: usize) -> Vec<Vector2> {
+        match self {
+            Layout::Rect(geo) => {
+                let r = geo.cell_rectangle(x as i32, y as i32);
+                vec![Vector2::new(r.x, r.y), Vector2::new(r.x + r.width, r.y),
+                     Vector2::new(r.x + r.width, r.y + r.height), Vector2::new(r.x, r.y + r.height)]
+            }
+            Layout::Hex(geo) => geo.corners(x, y).to_vec(),
+        }
+    }
+
+    // Columns and rows of the cells that may overlap the area, e.g. the part of the map seen by
+    // the camera. Conservative by a cell, never outside of the map.
+    pub fn cells_in(&self, area: Rectangle) -> (Range<usize>, Range<usize>) {
+        let (cols, rows) = self.dims();
+        let span = |lo: f32, hi: f32, len: usize| {
+            let start = lo.floor().max(0.0) as usize;
+            let end = (hi.ceil().max(0.0) as usize).min(len);
+            start.min(end)..end
+        };
+        match self {
+            Layout::Rect(geo) => {
+                let start = geo.start();
+                (span((area.x - start.x) / geo.celsize.x, (area.x + area.width - start.x) / geo.celsize.x, cols),
+                 span((area.y - start.y) / geo.celsize.y, (area.y + area.height - start.y) / geo.celsize.y, rows))
+            }
+            Layout::Hex(geo) => {
+                // Row y spans 0.75*h*y .. 0.75*h*y + h, column x spans w*x .. w*(x+1.5) with the odd shift
+                let (w, h) = (geo.hex_width(), geo.hex_height());
+                let (x, y) = (area.x - geo.origin.x, area.y - geo.origin.y);
+                (span(x / w - 1.5, (x + area.width) / w + 1.0, cols),
+                 span((y - h) / (0.75 * h), (y + area.height) / (0.75 * h) + 1.0, rows))
+            }
+        }
+    }
+
+    pub fn bounds(&self) -> Rectangle {
+        match self {
+            Layout::Rect(geo) => {
+                let start = geo.start();
+                Rectangle::new(start.x, start.y, geo.cells.x as f32 * geo.celsize.x, geo.cells.y as f32 * geo.celsize.y)
+            }
+            Layout::Hex(geo) => geo.rect(),
+        }
+    }
+}
+
+pub trait CellView {
+    fn layout(&self) -> Layout<'_>;
+    // State of the cell in column x, row y, mapped to a colour by the Palette of the backend
+    fn cell(&self, x: usize, y: usize) -> u32;
+}
+
+pub trait Renderer {
+    type Output;
+    fn render(&mut self, view: &dyn CellView) -> Self::Output;
+}
+
+pub struct RasterRenderer {
+    pub style: RasterStyle,
+}
+
+impl Renderer for RasterRenderer {
+    type Output = Canvas;
+    fn render(&mut self, view: &dyn CellView) -> Canvas {
+        view.rasterize(&self.style)
+    }
+}
+
+pub struct SvgRenderer {
+    pub style: SvgStyle,
+    // (state, text), see svg::hex_svg
+    pub labels: Vec<(u32, String)>,
+}
+
+impl Renderer for SvgRenderer {
+    type Output = String;
+    fn render(&mut self, view: &dyn CellView) -> String {
+        crate::svg::view_svg(view, &self.labels, &self.style)
+    }
+}
+
+pub struct TermRenderer {
+    pub view: Viewport,
+    pub style: TermStyle,
+}
+
+impl Renderer for TermRenderer {
+    type Output = String;
+    fn render(&mut self, view: &dyn CellView) -> String {
+        view.frame(&self.view, &self.style)
+    }
+}
+
+impl CellView for ConwaySimulation<'_, RectGeometry> {
+    fn layout(&self) -> Layout<'_> {
+        Layout::Rect(self.geo())
+    }
+
+    fn cell(&self, x: usize, y: usize) -> u32 {
+        self.states[self.geo().cell2id(x, y).0].val as u32
+    }
+}
+
+impl CellView for HexConwaySimulation<'_> {
+    fn layout(&self) -> Layout<'_> {
+        Layout::Hex(self.geo)
+    }
+
+    fn cell(&self, x: usize, y: usize) -> u32 {
+        self.states[y][x] as u32
+    }
+}
+
+impl CellView for EmpireSimulation<'_> {
+    fn layout(&self) -> Layout<'_> {
+        Layout::Hex(self.geo)
+    }
+
+    fn cell(&self, x: usize, y: usize) -> u32 {
+        self.state(x, y)
+    }
+}
+
+impl CellView for CoastDistance<'_> {
+    fn layout(&self) -> Layout<'_> {
+        Layout::Hex(self.geo)
+    }
+
+    fn cell(&self, x: usize, y: usize) -> u32 {
+        self.states[y][x]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raster::{Palette, Rgba};
+    use crate::terminal::ColorMode;
+
+    // A model defined only here gets all the outputs
+    struct Stripes<'a> {
+        geo: &'a HexGeometry,
+    }
+
+    impl CellView for Stripes<'_> {
+        fn layout(&self) -> Layout<'_> {
+            Layout::Hex(self.geo)
+        }
+
+        fn cell(&self, _x: usize, y: usize) -> u32 {
+            (y % 2) as u32
+        }
+    }
+
+    #[test]
+    fn test_backends() {
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 4, 3, 1.0);
+        let sim = Stripes { geo: &geo };
+        let palette = Palette::life().with(1, Rgba::rgb(255, 0, 0));
+        let mut raster = RasterRenderer { style: RasterStyle { scale: 6, palette: palette.clone(), ..Default::default() } };
+        let mut svg = SvgRenderer { style: SvgStyle { palette: palette.clone(), ..Default::default() }, labels: vec![(1, "odd".to_string())] };
+        let mut term = TermRenderer { view: Viewport::new(0, 0, 4, 3), style: TermStyle { palette, color: ColorMode::Mono } };
+        let canvas = raster.render(&sim);
+        // Centre of hex (1, 1)
+        let c = geo.cellcenter(1, 1) * 6.0;
+        assert_eq!(canvas.get(c.x as u32, c.y as u32), Rgba::rgb(255, 0, 0));
+        let svg = svg.render(&sim);
+        assert_eq!(svg.matches("<polygon").count(), 12);
+        assert!(svg.contains(r##"<g id="state-1" fill="#ff0000">"##));
+        assert!(svg.contains(">odd</text>"));
+        assert_eq!(term.render(&sim).lines().nth(1), Some(" ● ● ● ● \x1B[K"));
+    }
+
+    #[test]
+    fn test_layout() {
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 4, 3, 2.0);
+        let layout = Layout::Hex(&geo);
+        let w = 3_f32.sqrt() * 2.0;
+        assert_eq!(layout.center(0, 0), Vector2::new(0.5 * w, 2.0));
+        assert_eq!(layout.center(1, 1), Vector2::new(2.0 * w, 5.0));
+        // Every corner at `size` from the centre
+        for c in layout.corners(2, 1) {
+            assert!((c.distance_to(layout.center(2, 1)) - 2.0).abs() < 1e-5);
+        }
+        let rect = RectGeometry::new(Vector2::new(0.0, 0.0), 4, 2, Vector2::new(1.0, 1.0));
+        let layout = Layout::Rect(&rect);
+        assert_eq!(layout.bounds(), Rectangle::new(-2.0, -1.0, 4.0, 2.0));
+        assert_eq!(layout.corners(0, 0)[2], layout.center(0, 0) + 0.5);
+    }
+
+    #[test]
+    fn test_cells_in() {
+        let rect = RectGeometry::new(Vector2::new(0.0, 0.0), 100, 50, Vector2::new(2.0, 2.0));
+        let layout = Layout::Rect(&rect);
+        assert_eq!(layout.cells_in(Rectangle::new(-1.0, -1.0, 4.0, 2.0)), (49..52, 24..26));
+        assert_eq!(layout.cells_in(Rectangle::new(-500.0, 10.0, 1000.0, 1000.0)), (0..100, 30..50));
+        assert_eq!(layout.cells_in(Rectangle::new(500.0, 0.0, 10.0, 10.0)), (100..100, 25..30));
+
+        // Every hex overlapping the area is in, checked against the corners
+        let geo = HexGeometry::new(Vector2::new(5.0, -3.0), 60, 40, 1.5);
+        let layout = Layout::Hex(&geo);
+        for area in [Rectangle::new(20.0, 10.0, 13.0, 7.0), Rectangle::new(-10.0, -10.0, 12.0, 12.0), Rectangle::new(140.0, 80.0, 50.0, 50.0)] {
+            let (xs, ys) = layout.cells_in(area);
+            for y in 0..40 {
+                for x in 0..60 {
+                    let corners = layout.corners(x, y);
+                    let overlaps = corners.iter().any(|c| area.contains(*c)) ||
+                        [area.x, area.x + area.width].iter().any(|&ax| [area.y, area.y + area.height].iter()
+                            .any(|&ay| Vector2::new(ax, ay).distance_to(layout.center(x, y)) < geo.hex_width() / 2.0));
+                    if overlaps {
+                        assert!(xs.contains(&x) && ys.contains(&y), "hex {},{} missing from {:?} {:?}", x, y, xs, ys);
+                    }
+                }
+            }
+            assert!(xs.len() <= (area.width / geo.hex_width()) as usize + 4);
+        }
+    }
+}
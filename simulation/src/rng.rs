@@ -0,0 +1,25 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use rand::SeedableRng;
+use rand_chacha::ChaCha8Rng;
+
+// Every source of randomness in the crate (stochastic rules, terrain seeds, spawn points...) must
+// be drawn from a SimRng injected by the caller, never from rand::rng(). This way a whole run can
+// be replayed bit-for-bit from its seed.
+// ChaCha8 is used instead of StdRng because its output is guaranteed stable across platforms and
+// rand releases, and its position in the stream can be saved and restored.
+pub type SimRng = ChaCha8Rng;
+
+pub fn seeded(seed: u64) -> SimRng {
+    SimRng::seed_from_u64(seed)
+}
+
+// Seed for interactive runs. Print it so that an interesting run can be replayed later.
+pub fn clock_seed() -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).expect("Clock before UNIX epoch");
+    now.as_secs() ^ (now.subsec_nanos() as u64) << 32
+}
+
+// Parse the seed from the first command line argument, or fallback to the clock.
+pub fn seed_from_args() -> u64 {
+    std::env::args().nth(1).and_then(|s| s.parse().ok()).unwrap_or_else(clock_seed)
+}
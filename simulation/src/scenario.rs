@@ -0,0 +1,686 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use rand::Rng;
+use crate::math::Vector2;
+use serde::{Deserialize, Serialize};
+use crate::conway_sim::ConwaySimulation;
+use crate::empire::{self, Attributes, Conflict, Control, Diplomacy, EmpireSimulation, Rebellion, TerrainPreset, Warfare};
+use crate::hex_conway_sim::HexConwaySimulation;
+use crate::hex_geom::{HexGeometry, OffsetCoord};
+use crate::pattern::{self, Pattern, Rotation, Transform};
+use crate::rect_geom::RectGeometry;
+use crate::rng::{self, SimRng};
+
+// A scenario describes a whole run: the map, how the terrain is generated, the model with its
+// rules, what is on the map at generation 0 and how the viewer shows it.
+// It is written by hand in TOML or JSON, e.g.:
+//   name = "Small world"
+//   seed = 42
+//   [geometry]
+//   type = "hex"
+//   cols = 200
+//   rows = 100
+//   size = 50.0
+//   [model]
+//   type = "empire"
+//   [[empires]]
+//   name = "Rome"
+//   x = 20
+//   y = 30
+// Missing sections and fields take the defaults below. Unknown fields are an error, to catch typos.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    pub name: Option<String>,
+    // Seed of the run, a clock seed is used if missing
+    pub seed: Option<u64>,
+    pub geometry: GeometrySpec,
+    #[serde(default)]
+    pub terrain: TerrainSpec,
+    pub model: ModelSpec,
+    #[serde(default)]
+    pub empires: Vec<EmpireSpec>,
+    #[serde(default)]
+    pub patterns: Vec<PatternSpec>,
+    #[serde(default)]
+    pub viewer: ViewerSpec,
+    // Directory of the scenario file, relative pattern paths are resolved from it instead of the
+    // working directory. Set by load(), never written.
+    #[serde(skip)]
+    pub base_dir: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Shape {
+    // The whole cols x rows grid
+    #[default]
+    Rectangle,
+    // The largest hexagon inscribed in the grid, cells outside of it are sea. Hex geometry only.
+    Hexagon,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum GeometrySpec {
+    Rect {
+        cols: usize,
+        rows: usize,
+        #[serde(default = "one")]
+        cell_width: f32,
+        #[serde(default = "one")]
+        cell_height: f32,
+        #[serde(default)]
+        wrap: bool,
+    },
+    Hex {
+        cols: usize,
+        rows: usize,
+        #[serde(default = "one")]
+        size: f32,
+        #[serde(default)]
+        shape: Shape,
+        #[serde(default)]
+        wrap: bool,
+    },
+}
+
+fn one() -> f32 {
+    1.0
+}
+
+// Fractal Perlin noise: land on the highest land_fraction of the map, or where the noise is above
+// sea_level, or on the share of the preset when neither is set, shaped by the preset, with biomes and rivers if biomes is set (see
+// empire::TerrainGenerator)
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TerrainSpec {
+    // Noise seed, drawn from the run rng if missing
+    pub seed: Option<u32>,
+    pub octaves: usize,
+    // Times the frequency_scale of the preset
+    pub frequency: f64,
+    pub persistence: f64,
+    pub lacunarity: f64,
+    pub sea_level: Option<f64>,
+    // 0-1, replaces sea_level
+    pub land_fraction: Option<f64>,
+    pub preset: TerrainPreset,
+    pub biomes: bool,
+}
+
+impl Default for TerrainSpec {
+    fn default() -> Self {
+        // noise::Fbm defaults
+        Self { seed: None, octaves: 6, frequency: 1.0, persistence: 0.5, lacunarity: std::f64::consts::PI * 2.0 / 3.0, sea_level: None,
+            land_fraction: None, preset: TerrainPreset::Open, biomes: false }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum ModelSpec {
+    // Two-state automaton, rect geometry only
+    Conway {
+        #[serde(default = "life_rule")]
+        rule: String,
+        #[serde(default = "certain")]
+        birth_p: f64,
+        #[serde(default = "certain")]
+        stay_p: f64,
+        // Fraction of random alive cells at start, besides the patterns
+        #[serde(default)]
+        density: f64,
+    },
+    // Two-state automaton, hex geometry only
+    HexConway {
+        #[serde(default = "hex_rule")]
+        rule: String,
+        #[serde(default = "certain")]
+        birth_p: f64,
+        #[serde(default = "certain")]
+        stay_p: f64,
+        #[serde(default)]
+        density: f64,
+    },
+    // Empires expanding on the terrain, hex geometry only
+    Empire {
+        // Which empire takes a cell claimed by several
+        #[serde(default)]
+        conflict: Conflict,
+        // Border combat between empires, none when missing
+        warfare: Option<Warfare>,
+        // How the control of the empires falls far from their capitals
+        #[serde(default)]
+        control: Control,
+        // Empires splitting and rebelling, never when missing
+        rebellion: Option<Rebellion>,
+        // Empires making war and peace, all at war when missing
+        diplomacy: Option<Diplomacy>,
+    },
+}
+
+fn life_rule() -> String {
+    "B3/S23".to_string()
+}
+
+fn hex_rule() -> String {
+    "B2/S35".to_string()
+}
+
+fn certain() -> f64 {
+    1.0
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmpireSpec {
+    pub name: Option<String>,
+    pub x: usize,
+    pub y: usize,
+    // Traits of the empire (see empire::Attributes), random when missing
+    pub expansion: Option<f64>,
+    pub aggression: Option<f64>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PatternSpec {
+    // Catalogue name or path of a pattern file
+    pub pattern: String,
+    pub x: usize,
+    pub y: usize,
+    // Clockwise degrees: 0, 90, 180 or 270
+    #[serde(default)]
+    pub rotation: u16,
+    #[serde(default)]
+    pub flip: bool,
+}
+
+impl PatternSpec {
+    pub fn transform(&self) -> Transform {
+        let rotation = match self.rotation {
+            90 => Rotation::R90,
+            180 => Rotation::R180,
+            270 => Rotation::R270,
+            _ => Rotation::R0,
+        };
+        Transform { rotation, flip: self.flip }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ViewerSpec {
+    pub title: String,
+    pub screen_width: i32,
+    pub screen_height: i32,
+    pub fps: u32,
+    pub camera: CameraSpec,
+}
+
+impl Default for ViewerSpec {
+    fn default() -> Self {
+        Self { title: "empiresim".to_string(), screen_width: 1600, screen_height: 800, fps: 60, camera: CameraSpec::default() }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CameraSpec {
+    // Natural log of the zoom, so that zooming speed is uniform
+    pub zoom_ln: f32,
+    pub zoom_ln_min: f32,
+    pub zoom_ln_max: f32,
+    pub zoom_ln_speed: f32,
+    // World units per second at zoom 1
+    pub pos_speed: f32,
+}
+
+impl Default for CameraSpec {
+    fn default() -> Self {
+        Self { zoom_ln: -2.4, zoom_ln_min: -3.0, zoom_ln_max: 1.0, zoom_ln_speed: 6.0, pos_speed: 300.0 }
+    }
+}
+
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(std::io::Error),
+    // Syntax error or wrong field type, the message has the position
+    Parse(String),
+    // Every problem found by validate(), as (field path, message)
+    Invalid(Vec<(String, String)>),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::Io(e) => write!(f, "{}", e),
+            ScenarioError::Parse(msg) => write!(f, "{}", msg),
+            ScenarioError::Invalid(problems) => {
+                write!(f, "invalid scenario:")?;
+                for (field, msg) in problems {
+                    write!(f, "\n  {}: {}", field, msg)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+impl Scenario {
+    pub fn from_toml(text: &str) -> Result<Scenario, ScenarioError> {
+        let scenario: Scenario = toml::from_str(text).map_err(|e| ScenarioError::Parse(e.to_string()))?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    pub fn from_json(text: &str) -> Result<Scenario, ScenarioError> {
+        let scenario: Scenario = serde_json::from_str(text).map_err(|e| ScenarioError::Parse(e.to_string()))?;
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    // The format is chosen from the extension, .json or .toml.
+    // Pattern paths in the file are relative to its directory.
+    pub fn load(path: impl AsRef<Path>) -> Result<Scenario, ScenarioError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(ScenarioError::Io)?;
+        let is_json = path.extension().is_some_and(|e| e.eq_ignore_ascii_case("json"));
+        let mut scenario: Scenario = if is_json {
+            serde_json::from_str(&text).map_err(|e| ScenarioError::Parse(e.to_string()))?
+        } else {
+            toml::from_str(&text).map_err(|e| ScenarioError::Parse(e.to_string()))?
+        };
+        scenario.base_dir = path.parent().map(Path::to_path_buf);
+        scenario.validate()?;
+        Ok(scenario)
+    }
+
+    // Catalogue name, or pattern file relative to base_dir
+    fn load_pattern(&self, p: &PatternSpec) -> Result<Pattern, Box<dyn std::error::Error>> {
+        match &self.base_dir {
+            Some(dir) if pattern::catalogue::get(&p.pattern).is_none() => pattern::load(&dir.join(&p.pattern).to_string_lossy()),
+            _ => pattern::load(&p.pattern),
+        }
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string_pretty(self).expect("Scenario is always representable in TOML")
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Scenario is always representable in JSON")
+    }
+
+    // Check what serde cannot: ranges, coherence between sections, positions inside the map.
+    // Reports every problem at once instead of the first one.
+    pub fn validate(&self) -> Result<(), ScenarioError> {
+        let mut problems = Vec::new();
+        let mut check = |ok: bool, field: &str, msg: String| {
+            if !ok {
+                problems.push((field.to_string(), msg));
+            }
+        };
+        let (cols, rows) = self.geometry.dims();
+        check(cols > 0 && rows > 0, "geometry", format!("the map must have at least one cell, found {}x{}", cols, rows));
+        match &self.geometry {
+            GeometrySpec::Rect { cell_width, cell_height, wrap, .. } => {
+                check(*cell_width > 0.0 && *cell_height > 0.0, "geometry", "cell_width and cell_height must be positive".to_string());
+                check(!wrap, "geometry.wrap", "wrap-around edges are not supported by the simulations yet".to_string());
+            }
+            GeometrySpec::Hex { size, wrap, .. } => {
+                check(*size > 0.0, "geometry.size", format!("must be positive, found {}", size));
+                check(!wrap, "geometry.wrap", "wrap-around edges are not supported by the simulations yet".to_string());
+            }
+        }
+
+        let t = &self.terrain;
+        check((1..=32).contains(&t.octaves), "terrain.octaves", format!("must be between 1 and 32, found {}", t.octaves));
+        check(t.frequency > 0.0, "terrain.frequency", format!("must be positive, found {}", t.frequency));
+        check(t.lacunarity > 0.0, "terrain.lacunarity", format!("must be positive, found {}", t.lacunarity));
+        if let Some(level) = t.sea_level {
+            check((-1.0..=1.0).contains(&level), "terrain.sea_level", format!("must be between -1 and 1, found {}", level));
+        }
+        if let Some(f) = t.land_fraction {
+            check((0.0..=1.0).contains(&f), "terrain.land_fraction", format!("must be between 0 and 1, found {}", f));
+        }
+
+        let is_hex = matches!(self.geometry, GeometrySpec::Hex { .. });
+        match &self.model {
+            ModelSpec::Conway { rule, birth_p, stay_p, density } | ModelSpec::HexConway { rule, birth_p, stay_p, density } => {
+                let conway = matches!(self.model, ModelSpec::Conway { .. });
+                if conway {
+                    check(!is_hex, "model.type", "'conway' needs a rect geometry, use 'hex-conway' on hex maps".to_string());
+                } else {
+                    check(is_hex, "model.type", "'hex-conway' needs a hex geometry, use 'conway' on rect maps".to_string());
+                }
+                check(pattern::parse_rule(rule).is_some(), "model.rule", format!("'{}' is not a rule like B3/S23", rule));
+                for (name, p) in [("model.birth_p", birth_p), ("model.stay_p", stay_p), ("model.density", density)] {
+                    check((0.0..=1.0).contains(p), name, format!("must be between 0 and 1, found {}", p));
+                }
+                check(self.empires.is_empty(), "empires", "only the 'empire' model has empires".to_string());
+            }
+            ModelSpec::Empire { warfare, control, rebellion, diplomacy, .. } => {
+                check(is_hex, "model.type", "'empire' needs a hex geometry".to_string());
+                if let Some(w) = warfare {
+                    check((0.0..=1.0).contains(&w.rate), "model.warfare.rate", format!("must be between 0 and 1, found {}", w.rate));
+                    check(w.sharpness >= 0.0, "model.warfare.sharpness", format!("must not be negative, found {}", w.sharpness));
+                }
+                check(control.range > 0.0, "model.control.range", format!("must be positive, found {}", control.range));
+                if let Some(r) = rebellion {
+                    check((0.0..=1.0).contains(&r.rate), "model.rebellion.rate", format!("must be between 0 and 1, found {}", r.rate));
+                    check((0.0..=1.0).contains(&r.threshold), "model.rebellion.threshold", format!("must be between 0 and 1, found {}", r.threshold));
+                }
+                if let Some(d) = diplomacy {
+                    check((0.0..=1.0).contains(&d.rate), "model.diplomacy.rate", format!("must be between 0 and 1, found {}", d.rate));
+                    check(d.war_ratio >= 1.0, "model.diplomacy.war_ratio", format!("must be at least 1, found {}", d.war_ratio));
+                    check(d.vassal_ratio >= d.war_ratio, "model.diplomacy.vassal_ratio", format!("must be at least war_ratio, found {}", d.vassal_ratio));
+                }
+                check(self.patterns.is_empty(), "patterns", "patterns are only supported by the 'conway' model".to_string());
+            }
+        }
+        if matches!(self.model, ModelSpec::HexConway { .. }) {
+            check(self.patterns.is_empty(), "patterns", "patterns are only supported by the 'conway' model".to_string());
+        }
+        if !matches!(self.geometry, GeometrySpec::Hex { shape: Shape::Rectangle, .. } | GeometrySpec::Rect { .. }) {
+            check(matches!(self.model, ModelSpec::Empire { .. }), "geometry.shape", "only the 'empire' model supports non rectangular maps".to_string());
+        }
+
+        for (i, e) in self.empires.iter().enumerate() {
+            check(self.geometry.contains(e.x, e.y), &format!("empires[{}]", i), format!("({}, {}) is outside of the map", e.x, e.y));
+            for (field, v) in [("expansion", e.expansion), ("aggression", e.aggression)] {
+                if let Some(v) = v {
+                    check((0.0..=1.0).contains(&v), &format!("empires[{}].{}", i, field), format!("must be between 0 and 1, found {}", v));
+                }
+            }
+        }
+        for (i, p) in self.patterns.iter().enumerate() {
+            let field = format!("patterns[{}]", i);
+            check(matches!(p.rotation, 0 | 90 | 180 | 270), &format!("{}.rotation", field), format!("must be 0, 90, 180 or 270, found {}", p.rotation));
+            check(p.x < cols && p.y < rows, &field, format!("({}, {}) is outside of the map", p.x, p.y));
+            if let Err(e) = self.load_pattern(p) {
+                check(false, &format!("{}.pattern", field), format!("cannot load '{}': {}", p.pattern, e));
+            }
+        }
+
+        let v = &self.viewer;
+        check(v.screen_width > 0 && v.screen_height > 0, "viewer", "screen_width and screen_height must be positive".to_string());
+        let c = &v.camera;
+        check(c.zoom_ln_min <= c.zoom_ln_max, "viewer.camera", "zoom_ln_min must not be greater than zoom_ln_max".to_string());
+        check((c.zoom_ln_min..=c.zoom_ln_max).contains(&c.zoom_ln), "viewer.camera.zoom_ln",
+              format!("must be between zoom_ln_min and zoom_ln_max, found {}", c.zoom_ln));
+
+        if problems.is_empty() { Ok(()) } else { Err(ScenarioError::Invalid(problems)) }
+    }
+
+    pub fn rng(&self) -> SimRng {
+        rng::seeded(self.seed.unwrap_or_else(rng::clock_seed))
+    }
+
+    pub fn rect_geometry(&self) -> Option<RectGeometry> {
+        match self.geometry {
+            GeometrySpec::Rect { cols, rows, cell_width, cell_height, .. } =>
+                Some(RectGeometry::new(Vector2::new(0.0, 0.0), cols, rows, Vector2::new(cell_width, cell_height))),
+            GeometrySpec::Hex { .. } => None,
+        }
+    }
+
+    pub fn hex_geometry(&self) -> Option<HexGeometry> {
+        match self.geometry {
+            GeometrySpec::Hex { cols, rows, size, .. } => Some(HexGeometry::new(Vector2::new(0.0, 0.0), cols, rows, size)),
+            GeometrySpec::Rect { .. } => None,
+        }
+    }
+
+    // Build the 'conway' model on the geometry from rect_geometry(): random soup, then the patterns
+    pub fn build_conway<'a>(&self, geo: &'a RectGeometry, rng: &mut SimRng) -> Option<ConwaySimulation<'a, RectGeometry>> {
+        let ModelSpec::Conway { rule, birth_p, stay_p, density } = &self.model else { return None };
+        let (birth, stay) = pattern::parse_rule(rule)?;
+        let mut sim = ConwaySimulation::new(geo, &birth, &stay)
+            .with_probabilities(*birth_p, *stay_p, rng::seeded(rng.random()));
+        for i in 0..geo.cells.x * geo.cells.y {
+            sim.get_mut(i).unwrap().val = rng.random_bool(*density) as u8;
+        }
+        for p in self.patterns.iter() {
+            let pat = self.load_pattern(p).ok()?;
+            pat.stamp(&mut sim, p.x, p.y, p.transform());
+        }
+        Some(sim)
+    }
+
+    // Build the 'hex-conway' model on the geometry from hex_geometry()
+    pub fn build_hex_conway<'a>(&self, geo: &'a HexGeometry, rng: &mut SimRng) -> Option<HexConwaySimulation<'a>> {
+        let ModelSpec::HexConway { rule, birth_p, stay_p, density } = &self.model else { return None };
+        let (birth, stay) = pattern::parse_rule(rule)?;
+        let mut sim = HexConwaySimulation::new(geo, &birth, &stay)
+            .with_probabilities(*birth_p, *stay_p, rng::seeded(rng.random()));
+        for s in sim.states.iter_mut().flatten() {
+            *s = rng.random_bool(*density) as u8;
+        }
+        Some(sim)
+    }
+
+    // Build the 'empire' model on the geometry from hex_geometry(): noise terrain, or land with
+    // biomes, inside the map shape, then the starting empires. Also gives the indices in
+    // `empires` of the ones not on free earth, which are skipped.
+    pub fn build_empire<'a>(&self, geo: &'a HexGeometry, rng: &mut SimRng) -> Option<(EmpireSimulation<'a>, Vec<usize>)> {
+        let ModelSpec::Empire { conflict, warfare, control, rebellion, diplomacy } = &self.model else { return None };
+        let mask = |x, y| self.geometry.contains(x, y);
+        let mut sim = if self.terrain.biomes {
+            let land = empire::biome_land(geo, &self.terrain, mask, rng);
+            EmpireSimulation::from_land(geo, land, rng::seeded(rng.random()))
+        } else {
+            EmpireSimulation::new(geo, empire::noise_terrain(geo, &self.terrain, mask, rng), rng::seeded(rng.random()))
+        };
+        sim = sim.with_conflict(*conflict).with_control(*control);
+        if let Some(w) = warfare {
+            sim = sim.with_warfare(*w);
+        }
+        if let Some(r) = rebellion {
+            sim = sim.with_rebellion(*r);
+        }
+        if let Some(d) = diplomacy {
+            sim = sim.with_diplomacy(*d);
+        }
+        let mut skipped = Vec::new();
+        for (i, e) in self.empires.iter().enumerate() {
+            let random = Attributes::random(&mut sim.rng);
+            let attributes = Attributes::founding(e.expansion.unwrap_or(random.expansion), e.aggression.unwrap_or(random.aggression));
+            if sim.found_empire(e.x, e.y, e.name.clone(), attributes).is_none() {
+                skipped.push(i);
+            }
+        }
+        Some((sim, skipped))
+    }
+}
+
+impl GeometrySpec {
+    pub fn dims(&self) -> (usize, usize) {
+        match *self {
+            GeometrySpec::Rect { cols, rows, .. } | GeometrySpec::Hex { cols, rows, .. } => (cols, rows),
+        }
+    }
+
+    // Whether the (offset) cell is part of the map shape
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        let (cols, rows) = self.dims();
+        if x >= cols || y >= rows {
+            return false;
+        }
+        match self {
+            GeometrySpec::Hex { shape: Shape::Hexagon, .. } => {
+                let a = OffsetCoord { x: x as isize, y: y as isize }.axial();
+                let c = OffsetCoord { x: (cols / 2) as isize, y: (rows / 2) as isize }.axial();
+                let (dq, dr) = (a.q - c.q, a.r - c.r);
+                let distance = (dq.abs() + dr.abs() + (dq + dr).abs()) / 2;
+                let radius = ((cols - 1) / 2).min((rows - 1) / 2) as isize;
+                distance <= radius
+            }
+            _ => true,
+        }
+    }
+}
+
+// Scenario from the first of the command line arguments (without the program name) ending in
+// .toml or .json. Ok(None) if there is none.
+pub fn scenario_from_args<I: IntoIterator<Item = String>>(args: I) -> Result<Option<Scenario>, String> {
+    let Some(path) = args.into_iter().find(|a| a.ends_with(".toml") || a.ends_with(".json")) else {
+        return Ok(None);
+    };
+    Scenario::load(&path).map(Some).map_err(|e| format!("Cannot load scenario '{}': {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cycle::{run_until_stable, Stability};
+    use crate::empire::Reach;
+
+    const GUN: &str = r#"
+        name = "Gun"
+        seed = 7
+        [geometry]
+        type = "rect"
+        cols = 60
+        rows = 40
+        [model]
+        type = "conway"
+        [[patterns]]
+        pattern = "gosper-glider-gun"
+        x = 2
+        y = 2
+        [[patterns]]
+        pattern = "blinker"
+        x = 50
+        y = 30
+        rotation = 90
+    "#;
+
+    #[test]
+    fn test_toml_and_json_agree() {
+        let s = Scenario::from_toml(GUN).unwrap();
+        assert_eq!(s.viewer, ViewerSpec::default());
+        assert_eq!(s.model, ModelSpec::Conway { rule: "B3/S23".to_string(), birth_p: 1.0, stay_p: 1.0, density: 0.0 });
+        assert_eq!(Scenario::from_json(&s.to_json()).unwrap(), s);
+        assert_eq!(Scenario::from_toml(&s.to_toml()).unwrap(), s);
+    }
+
+    #[test]
+    fn test_build_conway() {
+        let s = Scenario::from_toml(GUN).unwrap();
+        let geo = s.rect_geometry().unwrap();
+        let mut sim = s.build_conway(&geo, &mut s.rng()).unwrap();
+        // The vertical blinker
+        assert!((30..33).all(|y| sim.get(geo.cell2id(50, y).0).unwrap().val == 1));
+        // The gun keeps growing, it never stabilizes
+        assert_eq!(run_until_stable(&mut sim, 60), None);
+
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 20, 20, 1.0);
+        let hex = Scenario::from_toml("[geometry]\ntype = \"hex\"\ncols = 20\nrows = 20\n[model]\ntype = \"hex-conway\"\n").unwrap();
+        let mut sim = hex.build_hex_conway(&geo, &mut hex.rng()).unwrap();
+        assert_eq!(run_until_stable(&mut sim, 3), Some(Stability::Still { generation: 0 }));
+    }
+
+    #[test]
+    fn test_build_empire() {
+        let text = "[geometry]\ntype = \"hex\"\ncols = 20\nrows = 20\n[terrain]\nsea_level = -1.0\n\
+                    [model]\ntype = \"empire\"\nconflict = \"oldest\"\n[model.warfare]\nrate = 0.2\n[model.control]\nreach = \"path\"\n[model.rebellion]\nsplit_size = 3\n[model.diplomacy]\nwar_ratio = 2.0\n\
+                    [[empires]]\nname = \"Rome\"\nx = 5\ny = 5\nexpansion = 0.4\n";
+        let s = Scenario::from_toml(text).unwrap();
+        assert_eq!(Scenario::from_toml(&s.to_toml()).unwrap(), s);
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 20, 20, 1.0);
+        let (sim, skipped) = s.build_empire(&geo, &mut s.rng()).unwrap();
+        assert!(skipped.is_empty());
+        assert_eq!(sim.conflict(), Conflict::Oldest);
+        assert_eq!(sim.warfare(), Some(Warfare { rate: 0.2, ..Default::default() }));
+        assert_eq!(sim.control(), Control { reach: Reach::Path, range: 20.0 });
+        assert_eq!(sim.rebellion(), Some(Rebellion { split_size: 3, ..Default::default() }));
+        assert_eq!(sim.diplomacy(), Some(Diplomacy { war_ratio: 2.0, ..Default::default() }));
+        let rome = sim.empire(0).unwrap();
+        assert_eq!((rome.name.as_str(), rome.capital, rome.attributes.expansion), ("Rome", (5, 5), 0.4));
+        // The supply range of the first warfare files is still accepted
+        let old = Scenario::from_toml(&text.replace("rate = 0.2\n", "rate = 0.2\nsupply_range = 10.0\n")).unwrap();
+        assert_eq!(old.model, s.model);
+        // A second empire on the same cell is skipped
+        let s = Scenario::from_toml(&format!("{}[[empires]]\nx = 5\ny = 5\n[[empires]]\nx = 9\ny = 9\n", text)).unwrap();
+        let (sim, skipped) = s.build_empire(&geo, &mut s.rng()).unwrap();
+        assert_eq!(skipped, vec![1]);
+        assert_eq!(sim.empire(1).unwrap().capital, (9, 9));
+    }
+
+    #[test]
+    fn test_terrain() {
+        let text = "[geometry]\ntype = \"hex\"\ncols = 40\nrows = 30\n[terrain]\nseed = 7\npreset = \"islands\"\nland_fraction = 0.3\nbiomes = true\n\
+                    [model]\ntype = \"empire\"\n";
+        let s = Scenario::from_toml(text).unwrap();
+        assert_eq!((s.terrain.preset, s.terrain.land_fraction), (TerrainPreset::Islands, Some(0.3)));
+        assert_eq!(Scenario::from_toml(&s.to_toml()).unwrap(), s);
+        // The same map at every run
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 40, 30, 1.0);
+        let (a, b) = (s.build_empire(&geo, &mut s.rng()).unwrap().0, s.build_empire(&geo, &mut s.rng()).unwrap().0);
+        assert_eq!((&a.terrain, &a.rivers), (&b.terrain, &b.rivers));
+        let land = a.terrain.iter().flatten().filter(|t| t.is_land()).count();
+        assert_eq!(land, 360);
+        // Without a land fraction the sea level replaces the one of the preset
+        let s = Scenario::from_toml(&text.replace("land_fraction = 0.3", "sea_level = 1.0")).unwrap();
+        let sea = s.build_empire(&geo, &mut s.rng()).unwrap().0;
+        assert!(sea.terrain.iter().flatten().all(|t| !t.is_land()));
+
+        let Err(ScenarioError::Invalid(problems)) = Scenario::from_toml(&text.replace("0.3", "1.3")) else { panic!("expected validation errors") };
+        assert_eq!(problems[0].0, "terrain.land_fraction");
+    }
+
+    #[test]
+    fn test_errors_are_helpful() {
+        let err = Scenario::from_toml("[geometry]\ntype = \"hex\"\ncols = 20\nrows = 20\nsise = 2.0\n[model]\ntype = \"empire\"\n").unwrap_err();
+        assert!(matches!(err, ScenarioError::Parse(_)));
+        assert!(err.to_string().contains("sise"), "{}", err);
+
+        let text = r#"{
+            "geometry": {"type": "rect", "cols": 10, "rows": 0},
+            "model": {"type": "conway", "rule": "B3/S2x", "density": 1.5},
+            "patterns": [{"pattern": "no-such-pattern", "x": 1, "y": 1, "rotation": 45}],
+            "empires": [{"x": 1, "y": 1, "aggression": 2.0}]
+        }"#;
+        let Err(ScenarioError::Invalid(problems)) = Scenario::from_json(text) else { panic!("expected validation errors") };
+        let fields: Vec<&str> = problems.iter().map(|(f, _)| f.as_str()).collect();
+        assert_eq!(fields, vec!["geometry", "model.rule", "model.density", "empires", "empires[0]",
+                                "empires[0].aggression", "patterns[0].rotation", "patterns[0]", "patterns[0].pattern"]);
+    }
+
+    #[test]
+    fn test_load_from_file() {
+        let dir = std::env::temp_dir().join(format!("empiresim-scenario-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("patterns")).unwrap();
+        std::fs::write(dir.join("patterns/pair.rle"), "x = 2, y = 1\n2o!\n").unwrap();
+        let text = GUN.replace("gosper-glider-gun", "patterns/pair.rle");
+        std::fs::write(dir.join("gun.toml"), &text).unwrap();
+        // The pattern is found next to the scenario, whatever the working directory
+        let s = Scenario::load(dir.join("gun.toml")).unwrap();
+        assert_eq!(s.base_dir.as_deref(), Some(dir.as_path()));
+        let geo = s.rect_geometry().unwrap();
+        let sim = s.build_conway(&geo, &mut s.rng()).unwrap();
+        assert!([(2, 2), (3, 2)].iter().all(|&(x, y)| sim.get(geo.cell2id(x, y).0).unwrap().val == 1));
+        assert!(Scenario::from_toml(&text).is_err());
+
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert!(scenario_from_args(args(&["42"])).unwrap().is_none());
+        let path = dir.join("gun.toml").to_string_lossy().to_string();
+        assert_eq!(scenario_from_args(args(&["42", &path])).unwrap(), Some(s));
+        assert!(scenario_from_args(args(&["missing.toml"])).unwrap_err().starts_with("Cannot load scenario 'missing.toml'"));
+        // Wrap-around edges are in the schema but not supported yet
+        let Err(ScenarioError::Invalid(problems)) = Scenario::from_toml("[geometry]\ntype = \"rect\"\ncols = 5\nrows = 5\nwrap = true\n[model]\ntype = \"conway\"\n") else { panic!("expected validation errors") };
+        assert_eq!(problems, vec![("geometry.wrap".to_string(), "wrap-around edges are not supported by the simulations yet".to_string())]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_hexagon_shape() {
+        let g = GeometrySpec::Hex { cols: 11, rows: 11, size: 1.0, shape: Shape::Hexagon, wrap: false };
+        assert!(g.contains(5, 5) && g.contains(5, 0) && g.contains(0, 5) && g.contains(10, 5));
+        assert!(!g.contains(0, 0) && !g.contains(10, 10) && !g.contains(11, 5));
+    }
+}
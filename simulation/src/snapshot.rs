@@ -0,0 +1,1019 @@
+use std::fmt;
+use std::path::Path;
+use rand::SeedableRng;
+use crate::math::Vector2;
+use crate::conway_sim::{ConwaySimulation, State};
+use crate::empire::{Attributes, Conflict, Control, Diplomacy, Empire, EmpireSimulation, Reach, Rebellion, Relation, Terrain, Warfare};
+use crate::hex_conway_sim::HexConwaySimulation;
+use crate::hex_geom::HexGeometry;
+use crate::rect_geom::RectGeometry;
+use crate::rng::SimRng;
+
+// Binary snapshot of a whole simulation, little endian:
+//   "ESIM" magic
+//   u16 version of the writer, u16 oldest reader version able to load it
+//   string model name ("conway", "hex-conway", ...)
+//   sections: [u8; 4] tag, u32 length, payload. The "END " section closes the file.
+// Readers skip the sections they do not know, so a newer writer can add data without breaking
+// older readers. When a change cannot be ignored safely, the snapshots using it require the
+// version of the change (Snapshot::require), the others stay readable by the older readers.
+// Strings and byte arrays are prefixed by their length as a varint (LEB128).
+// Versions, readers load all the older ones:
+//   1: 'hex-empire' EMPIRES without the foundation generation and no RULE. The first files had
+//      no OWNERS either: CELLS held 0 for sea, 1 for free earth, 2 + id for the cells of an
+//      empire, and EMPIRES the ids as u8 2 + id.
+//   2: foundation generation in EMPIRES, RULE required. The Warfare in RULE had a third f64,
+//      the supply range, read as the range of a Control by hex distance.
+//   3: Warfare without the supply range, CONTROL
+//   4: REBELLION, older readers would have run the empires without it
+//   5: DIPLOMACY and RELATIONS, older readers would have put all the empires at war
+//   6: the biomes in the CELLS of 'hex-empire', RIVERS
+// 'hex-empire' files require version 2, then the version of the newest of these they use.
+pub const MAGIC: &[u8; 4] = b"ESIM";
+pub const VERSION: u16 = 6;
+
+// Sections shared by all the models
+pub const GEOMETRY: [u8; 4] = *b"GEOM";
+pub const RULE: [u8; 4] = *b"RULE";
+pub const GENERATION: [u8; 4] = *b"GENR";
+pub const RNG: [u8; 4] = *b"RNG ";
+pub const CELLS: [u8; 4] = *b"CELL";
+// 'hex-empire' model: owner of every cell as wide runs, 0 for free cells and id + 1 otherwise, then
+// the empire table: count, then varint id, name and foundation generation of each. CELLS is the
+// terrain in the order of Terrain::ALL, RIVERS has runs of 1 on the river cells and 0 elsewhere, no
+// rivers without it. RULE is the Conflict policy as a u8 then the Warfare: u8 0 without, 1 then
+// rate and sharpness as f64. CONTROL is the Control: u8 reach (0 hex, 1 path) and f64 range, the
+// default one without it. REBELLION is the Rebellion, if any: varint split size, f64 rate and
+// threshold. DIPLOMACY is the Diplomacy, if any: f64 rate, war and vassal ratios. RELATIONS has the
+// relations set: count, then varint a, varint b and u8 relation of a towards b (the order of
+// Relation). ATTRIBUTES has the Attributes of the empires in the order of EMPIRES as four f64:
+// population, strength, expansion and aggression; without it the empires get the default ones.
+// CAPITALS has their capitals as varint x and y; without it the first cell of the empire is the
+// capital.
+pub const OWNERS: [u8; 4] = *b"OWNR";
+pub const RIVERS: [u8; 4] = *b"RIVR";
+pub const EMPIRES: [u8; 4] = *b"EMPR";
+pub const ATTRIBUTES: [u8; 4] = *b"ATTR";
+pub const CAPITALS: [u8; 4] = *b"CAPT";
+pub const CONTROL: [u8; 4] = *b"CTRL";
+pub const REBELLION: [u8; 4] = *b"RBLN";
+pub const DIPLOMACY: [u8; 4] = *b"DIPL";
+pub const RELATIONS: [u8; 4] = *b"RELS";
+const END: [u8; 4] = *b"END ";
+
+// Largest grid a snapshot can hold, cols * rows
+const MAX_CELLS: usize = 1 << 30;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(std::io::Error),
+    BadMagic,
+    // Written by a newer version that older readers cannot load
+    TooNew { version: u16, min_reader_version: u16 },
+    WrongModel { expected: String, found: String },
+    MissingSection([u8; 4]),
+    Truncated,
+    Invalid(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(e) => write!(f, "{}", e),
+            SnapshotError::BadMagic => write!(f, "not a simulation snapshot"),
+            SnapshotError::TooNew { version, min_reader_version } =>
+                write!(f, "snapshot version {} needs a reader of version {}, this is version {}", version, min_reader_version, VERSION),
+            SnapshotError::WrongModel { expected, found } =>
+                write!(f, "snapshot of a '{}' simulation, expected '{}'", found, expected),
+            SnapshotError::MissingSection(tag) => write!(f, "missing section '{}'", String::from_utf8_lossy(tag)),
+            SnapshotError::Truncated => write!(f, "snapshot is truncated"),
+            SnapshotError::Invalid(msg) => write!(f, "invalid snapshot: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+impl From<std::io::Error> for SnapshotError {
+    fn from(e: std::io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+#[derive(Default)]
+pub struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn u8(&mut self, v: u8) -> &mut Self {
+        self.buf.push(v);
+        self
+    }
+
+    pub fn u16(&mut self, v: u16) -> &mut Self {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    pub fn u32(&mut self, v: u32) -> &mut Self {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    pub fn u64(&mut self, v: u64) -> &mut Self {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    pub fn u128(&mut self, v: u128) -> &mut Self {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    pub fn f32(&mut self, v: f32) -> &mut Self {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    pub fn f64(&mut self, v: f64) -> &mut Self {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    pub fn varint(&mut self, mut v: u64) -> &mut Self {
+        while v >= 0x80 {
+            self.buf.push((v as u8) | 0x80);
+            v >>= 7;
+        }
+        self.buf.push(v as u8);
+        self
+    }
+
+    pub fn bytes(&mut self, v: &[u8]) -> &mut Self {
+        self.varint(v.len() as u64);
+        self.buf.extend_from_slice(v);
+        self
+    }
+
+    pub fn str(&mut self, v: &str) -> &mut Self {
+        self.bytes(v.as_bytes())
+    }
+
+    // Byte cells as (run length, value) pairs: maps are mostly long runs of sea or earth
+    pub fn runs(&mut self, cells: &[u8]) -> &mut Self {
+        self.varint(cells.len() as u64);
+        let mut i = 0;
+        while i < cells.len() {
+            let run = cells[i..].iter().take_while(|&&c| c == cells[i]).count();
+            self.varint(run as u64).u8(cells[i]);
+            i += run;
+        }
+        self
+    }
+
+    // Same for wider cells, values as varints
+    pub fn wide_runs(&mut self, cells: &[u32]) -> &mut Self {
+        self.varint(cells.len() as u64);
+        let mut i = 0;
+        while i < cells.len() {
+            let run = cells[i..].iter().take_while(|&&c| c == cells[i]).count();
+            self.varint(run as u64).varint(cells[i] as u64);
+            i += run;
+        }
+        self
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+pub struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], SnapshotError> {
+        let bytes = self.slice(N)?;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn slice(&mut self, n: usize) -> Result<&'a [u8], SnapshotError> {
+        let end = self.pos.checked_add(n).filter(|&e| e <= self.data.len()).ok_or(SnapshotError::Truncated)?;
+        let s = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(s)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos == self.data.len()
+    }
+
+    pub fn u8(&mut self) -> Result<u8, SnapshotError> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, SnapshotError> {
+        Ok(u16::from_le_bytes(self.take()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, SnapshotError> {
+        Ok(u32::from_le_bytes(self.take()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, SnapshotError> {
+        Ok(u64::from_le_bytes(self.take()?))
+    }
+
+    pub fn u128(&mut self) -> Result<u128, SnapshotError> {
+        Ok(u128::from_le_bytes(self.take()?))
+    }
+
+    pub fn f32(&mut self) -> Result<f32, SnapshotError> {
+        Ok(f32::from_le_bytes(self.take()?))
+    }
+
+    pub fn f64(&mut self) -> Result<f64, SnapshotError> {
+        Ok(f64::from_le_bytes(self.take()?))
+    }
+
+    pub fn varint(&mut self) -> Result<u64, SnapshotError> {
+        let mut v = 0_u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.u8()?;
+            v |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+        Err(SnapshotError::Invalid("varint too long".to_string()))
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], SnapshotError> {
+        let len = self.varint()? as usize;
+        self.slice(len)
+    }
+
+    pub fn str(&mut self) -> Result<String, SnapshotError> {
+        String::from_utf8(self.bytes()?.to_vec()).map_err(|_| SnapshotError::Invalid("string is not utf-8".to_string()))
+    }
+
+    // Run-length encoded cells, which must be as many as expected: the length is checked before
+    // anything is allocated, so a corrupted file cannot ask for an arbitrary amount of memory
+    pub fn runs(&mut self, expected: usize) -> Result<Vec<u8>, SnapshotError> {
+        let len = self.run_len(expected)?;
+        let mut cells = Vec::with_capacity(len);
+        while cells.len() < len {
+            let run = self.varint()? as usize;
+            let value = self.u8()?;
+            if run == 0 || run > len - cells.len() {
+                return Err(SnapshotError::Invalid("cell run out of bounds".to_string()));
+            }
+            cells.resize(cells.len() + run, value);
+        }
+        Ok(cells)
+    }
+
+    pub fn wide_runs(&mut self, expected: usize) -> Result<Vec<u32>, SnapshotError> {
+        let len = self.run_len(expected)?;
+        let mut cells = Vec::with_capacity(len);
+        while cells.len() < len {
+            let run = self.varint()? as usize;
+            let value = u32::try_from(self.varint()?).map_err(|_| SnapshotError::Invalid("cell value out of range".to_string()))?;
+            if run == 0 || run > len - cells.len() {
+                return Err(SnapshotError::Invalid("cell run out of bounds".to_string()));
+            }
+            cells.resize(cells.len() + run, value);
+        }
+        Ok(cells)
+    }
+
+    fn run_len(&mut self, expected: usize) -> Result<usize, SnapshotError> {
+        let len = self.varint()?;
+        if len != expected as u64 {
+            return Err(SnapshotError::Invalid(format!("{} cells for a geometry of {}", len, expected)));
+        }
+        Ok(expected)
+    }
+}
+
+// A decoded snapshot: the model name and its sections
+pub struct Snapshot {
+    pub model: String,
+    pub version: u16,
+    pub min_reader_version: u16,
+    sections: Vec<([u8; 4], Vec<u8>)>,
+}
+
+impl Snapshot {
+    pub fn new(model: &str) -> Self {
+        Self { model: model.to_string(), version: VERSION, min_reader_version: 1, sections: Vec::new() }
+    }
+
+    // Only readers of at least this version can load the snapshot
+    pub fn require(&mut self, version: u16) {
+        self.min_reader_version = self.min_reader_version.max(version);
+    }
+
+    pub fn add(&mut self, tag: [u8; 4], section: Writer) {
+        self.sections.retain(|(t, _)| *t != tag);
+        self.sections.push((tag, section.into_bytes()));
+    }
+
+    pub fn has(&self, tag: [u8; 4]) -> bool {
+        self.sections.iter().any(|(t, _)| *t == tag)
+    }
+
+    pub fn section(&self, tag: [u8; 4]) -> Result<Reader<'_>, SnapshotError> {
+        self.sections.iter().find(|(t, _)| *t == tag)
+            .map(|(_, data)| Reader::new(data))
+            .ok_or(SnapshotError::MissingSection(tag))
+    }
+
+    pub fn expect_model(&self, model: &str) -> Result<(), SnapshotError> {
+        if self.model != model {
+            return Err(SnapshotError::WrongModel { expected: model.to_string(), found: self.model.clone() });
+        }
+        Ok(())
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Writer::new();
+        w.buf.extend_from_slice(MAGIC);
+        w.u16(VERSION).u16(self.min_reader_version).str(&self.model);
+        for (tag, data) in self.sections.iter().chain(std::iter::once(&(END, Vec::new()))) {
+            w.buf.extend_from_slice(tag);
+            w.u32(data.len() as u32);
+            w.buf.extend_from_slice(data);
+        }
+        w.into_bytes()
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Snapshot, SnapshotError> {
+        let mut r = Reader::new(data);
+        if r.take::<4>().ok().as_ref() != Some(MAGIC) {
+            return Err(SnapshotError::BadMagic);
+        }
+        let version = r.u16()?;
+        let min_reader_version = r.u16()?;
+        if min_reader_version > VERSION {
+            return Err(SnapshotError::TooNew { version, min_reader_version });
+        }
+        let model = r.str()?;
+        let mut sections = Vec::new();
+        loop {
+            let tag = r.take::<4>()?;
+            let len = r.u32()? as usize;
+            let data = r.slice(len)?;
+            if tag == END {
+                break;
+            }
+            sections.push((tag, data.to_vec()));
+        }
+        Ok(Snapshot { model, version, min_reader_version, sections })
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<(), SnapshotError> {
+        Ok(std::fs::write(path, self.encode())?)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> Result<Snapshot, SnapshotError> {
+        Snapshot::decode(&std::fs::read(path)?)
+    }
+}
+
+// A simulation that can be saved to a snapshot and rebuilt from it.
+// The simulations borrow their geometry, so loading happens in two steps:
+//   let geo = Sim::geometry(&snap)?;
+//   let sim = Sim::from_snapshot(&geo, &snap)?;
+pub trait Snapshottable<'a>: Sized {
+    type Geometry: 'a;
+    const MODEL: &'static str;
+    fn snapshot(&self) -> Snapshot;
+    fn geometry(snap: &Snapshot) -> Result<Self::Geometry, SnapshotError>;
+    fn from_snapshot(geo: &'a Self::Geometry, snap: &Snapshot) -> Result<Self, SnapshotError>;
+}
+
+pub fn write_rng(rng: &SimRng) -> Writer {
+    let mut w = Writer::new();
+    w.buf.extend_from_slice(&rng.get_seed());
+    w.u64(rng.get_stream()).u128(rng.get_word_pos());
+    w
+}
+
+pub fn read_rng(r: &mut Reader) -> Result<SimRng, SnapshotError> {
+    let mut rng = SimRng::from_seed(r.take::<32>()?);
+    rng.set_stream(r.u64()?);
+    rng.set_word_pos(r.u128()?);
+    Ok(rng)
+}
+
+pub fn write_hex_geometry(geo: &HexGeometry) -> Writer {
+    let mut w = Writer::new();
+    w.f32(geo.origin.x).f32(geo.origin.y).u32(geo.cols as u32).u32(geo.rows as u32).f32(geo.size);
+    w
+}
+
+pub fn read_hex_geometry(r: &mut Reader) -> Result<HexGeometry, SnapshotError> {
+    let origin = Vector2::new(r.f32()?, r.f32()?);
+    let (cols, rows) = read_grid(r)?;
+    let size = r.f32()?;
+    check_cell_size(size)?;
+    Ok(HexGeometry::new(origin, cols, rows, size))
+}
+
+// Columns and rows, at least one of each and at most MAX_CELLS in all
+fn read_grid(r: &mut Reader) -> Result<(usize, usize), SnapshotError> {
+    let (cols, rows) = (r.u32()? as usize, r.u32()? as usize);
+    if cols == 0 || rows == 0 || cols.saturating_mul(rows) > MAX_CELLS {
+        return Err(SnapshotError::Invalid(format!("grid of {}x{} cells", cols, rows)));
+    }
+    Ok((cols, rows))
+}
+
+fn check_cell_size(size: f32) -> Result<(), SnapshotError> {
+    if !size.is_finite() || size <= 0.0 {
+        return Err(SnapshotError::Invalid(format!("cell size {}", size)));
+    }
+    Ok(())
+}
+
+pub fn write_generation(generation: u64) -> Writer {
+    let mut w = Writer::new();
+    w.u64(generation);
+    w
+}
+
+// Birth/survival rule with its probabilities
+fn write_rule(birth: &[u8], stay: &[u8], birth_p: f64, stay_p: f64) -> Writer {
+    let mut w = Writer::new();
+    w.bytes(birth).bytes(stay).f64(birth_p).f64(stay_p);
+    w
+}
+
+fn read_rule(r: &mut Reader) -> Result<(Vec<u8>, Vec<u8>, f64, f64), SnapshotError> {
+    let (birth, stay) = (r.bytes()?.to_vec(), r.bytes()?.to_vec());
+    let (birth_p, stay_p) = (r.f64()?, r.f64()?);
+    if !(0.0..=1.0).contains(&birth_p) || !(0.0..=1.0).contains(&stay_p) {
+        return Err(SnapshotError::Invalid("rule probabilities out of [0, 1]".to_string()));
+    }
+    Ok((birth, stay, birth_p, stay_p))
+}
+
+// Range of a Control, positive like in the scenarios: 0 gives no control at all
+fn read_range(r: &mut Reader) -> Result<f64, SnapshotError> {
+    let range = r.f64()?;
+    if range > 0.0 { Ok(range) } else { Err(SnapshotError::Invalid(format!("control range {}", range))) }
+}
+
+fn read_cells(snap: &Snapshot, expected: usize) -> Result<Vec<u8>, SnapshotError> {
+    snap.section(CELLS)?.runs(expected)
+}
+
+impl<'a> Snapshottable<'a> for ConwaySimulation<'a, RectGeometry> {
+    type Geometry = RectGeometry;
+    const MODEL: &'static str = "conway";
+
+    fn snapshot(&self) -> Snapshot {
+        let geo = self.geo();
+        let mut snap = Snapshot::new(Self::MODEL);
+        let mut w = Writer::new();
+        w.f32(geo.geocenter.x).f32(geo.geocenter.y)
+            .u32(geo.cells.x as u32).u32(geo.cells.y as u32)
+            .f32(geo.celsize.x).f32(geo.celsize.y);
+        snap.add(GEOMETRY, w);
+        snap.add(RULE, write_rule(&self.birth, &self.stay, self.birth_p, self.stay_p));
+        snap.add(GENERATION, write_generation(self.generation));
+        snap.add(RNG, write_rng(&self.rng));
+        let mut w = Writer::new();
+        w.runs(&self.states.iter().map(|s| s.val).collect::<Vec<_>>());
+        snap.add(CELLS, w);
+        snap
+    }
+
+    fn geometry(snap: &Snapshot) -> Result<RectGeometry, SnapshotError> {
+        snap.expect_model(Self::MODEL)?;
+        let mut r = snap.section(GEOMETRY)?;
+        let center = Vector2::new(r.f32()?, r.f32()?);
+        let (cols, rows) = read_grid(&mut r)?;
+        let size = Vector2::new(r.f32()?, r.f32()?);
+        check_cell_size(size.x)?;
+        check_cell_size(size.y)?;
+        Ok(RectGeometry::new(center, cols, rows, size))
+    }
+
+    fn from_snapshot(geo: &'a RectGeometry, snap: &Snapshot) -> Result<Self, SnapshotError> {
+        snap.expect_model(Self::MODEL)?;
+        let (birth, stay, birth_p, stay_p) = read_rule(&mut snap.section(RULE)?)?;
+        let rng = read_rng(&mut snap.section(RNG)?)?;
+        let mut sim = ConwaySimulation::new(geo, &birth, &stay).with_probabilities(birth_p, stay_p, rng);
+        sim.generation = snap.section(GENERATION)?.u64()?;
+        sim.states = read_cells(snap, geo.cells.x * geo.cells.y)?.into_iter().map(|val| State { val }).collect();
+        Ok(sim)
+    }
+}
+
+impl<'a> Snapshottable<'a> for HexConwaySimulation<'a> {
+    type Geometry = HexGeometry;
+    const MODEL: &'static str = "hex-conway";
+
+    fn snapshot(&self) -> Snapshot {
+        let mut snap = Snapshot::new(Self::MODEL);
+        snap.add(GEOMETRY, write_hex_geometry(self.geo));
+        snap.add(RULE, write_rule(&self.birth, &self.stay, self.birth_p, self.stay_p));
+        snap.add(GENERATION, write_generation(self.generation));
+        snap.add(RNG, write_rng(&self.rng));
+        let mut w = Writer::new();
+        w.runs(&self.states.concat());
+        snap.add(CELLS, w);
+        snap
+    }
+
+    fn geometry(snap: &Snapshot) -> Result<HexGeometry, SnapshotError> {
+        snap.expect_model(Self::MODEL)?;
+        read_hex_geometry(&mut snap.section(GEOMETRY)?)
+    }
+
+    fn from_snapshot(geo: &'a HexGeometry, snap: &Snapshot) -> Result<Self, SnapshotError> {
+        snap.expect_model(Self::MODEL)?;
+        let (birth, stay, birth_p, stay_p) = read_rule(&mut snap.section(RULE)?)?;
+        let rng = read_rng(&mut snap.section(RNG)?)?;
+        let mut sim = HexConwaySimulation::new(geo, &birth, &stay).with_probabilities(birth_p, stay_p, rng);
+        sim.generation = snap.section(GENERATION)?.u64()?;
+        let cells = read_cells(snap, geo.cols * geo.rows)?;
+        for (row, saved) in sim.states.iter_mut().zip(cells.chunks(geo.cols)) {
+            row.copy_from_slice(saved);
+        }
+        Ok(sim)
+    }
+}
+
+impl<'a> Snapshottable<'a> for EmpireSimulation<'a> {
+    type Geometry = HexGeometry;
+    const MODEL: &'static str = "hex-empire";
+
+    fn snapshot(&self) -> Snapshot {
+        let mut snap = Snapshot::new(Self::MODEL);
+        snap.require(2);
+        if self.warfare.is_some() || self.control != Control::default() {
+            snap.require(3);
+        }
+        if self.rebellion.is_some() {
+            snap.require(4);
+        }
+        if self.diplomacy.is_some() || !self.relations.is_empty() {
+            snap.require(5);
+        }
+        let rivers = self.rivers.iter().flatten().any(|&r| r);
+        if rivers || self.terrain.iter().flatten().any(|t| !matches!(t, Terrain::Sea | Terrain::Earth)) {
+            snap.require(6);
+        }
+        snap.add(GEOMETRY, write_hex_geometry(self.geo));
+        let mut w = Writer::new();
+        w.u8(self.conflict as u8);
+        match self.warfare {
+            Some(war) => w.u8(1).f64(war.rate).f64(war.sharpness),
+            None => w.u8(0),
+        };
+        snap.add(RULE, w);
+        let mut w = Writer::new();
+        w.u8(self.control.reach as u8).f64(self.control.range);
+        snap.add(CONTROL, w);
+        if let Some(rebellion) = self.rebellion {
+            let mut w = Writer::new();
+            w.varint(rebellion.split_size as u64).f64(rebellion.rate).f64(rebellion.threshold);
+            snap.add(REBELLION, w);
+        }
+        if let Some(diplomacy) = self.diplomacy {
+            let mut w = Writer::new();
+            w.f64(diplomacy.rate).f64(diplomacy.war_ratio).f64(diplomacy.vassal_ratio);
+            snap.add(DIPLOMACY, w);
+        }
+        snap.add(GENERATION, write_generation(self.generation));
+        snap.add(RNG, write_rng(&self.rng));
+        let mut w = Writer::new();
+        w.runs(&self.terrain.iter().flatten().map(|t| *t as u8).collect::<Vec<_>>());
+        snap.add(CELLS, w);
+        if rivers {
+            let mut w = Writer::new();
+            w.runs(&self.rivers.iter().flatten().map(|&r| r as u8).collect::<Vec<_>>());
+            snap.add(RIVERS, w);
+        }
+        let mut w = Writer::new();
+        w.wide_runs(&self.owners.iter().flatten().map(|o| o.map_or(0, |id| id + 1)).collect::<Vec<_>>());
+        snap.add(OWNERS, w);
+        let mut w = Writer::new();
+        w.varint(self.empires().count() as u64);
+        for e in self.empires() {
+            w.varint(e.id as u64).str(&e.name).varint(e.founded);
+        }
+        snap.add(EMPIRES, w);
+        let mut w = Writer::new();
+        for a in self.empires().map(|e| e.attributes) {
+            w.f64(a.population).f64(a.strength).f64(a.expansion).f64(a.aggression);
+        }
+        snap.add(ATTRIBUTES, w);
+        let mut w = Writer::new();
+        for (x, y) in self.empires().map(|e| e.capital) {
+            w.varint(x as u64).varint(y as u64);
+        }
+        snap.add(CAPITALS, w);
+        let mut w = Writer::new();
+        w.varint(self.relations.iter().count() as u64);
+        for (a, b, relation) in self.relations.iter() {
+            w.varint(a as u64).varint(b as u64).u8(relation as u8);
+        }
+        snap.add(RELATIONS, w);
+        snap
+    }
+
+    fn geometry(snap: &Snapshot) -> Result<HexGeometry, SnapshotError> {
+        snap.expect_model(Self::MODEL)?;
+        read_hex_geometry(&mut snap.section(GEOMETRY)?)
+    }
+
+    fn from_snapshot(geo: &'a HexGeometry, snap: &Snapshot) -> Result<Self, SnapshotError> {
+        snap.expect_model(Self::MODEL)?;
+        let mut cells = read_cells(snap, geo.cols * geo.rows)?;
+        // Version 1 files without OWNERS have the owners in the cells
+        let first_layout = snap.version < 2 && !snap.has(OWNERS);
+        let owners = if first_layout {
+            let owners = cells.iter().map(|&c| if c >= 2 { c as u32 - 1 } else { 0 }).collect();
+            cells.iter_mut().for_each(|c| *c = (*c).min(1));
+            owners
+        } else {
+            snap.section(OWNERS)?.wide_runs(cells.len())?
+        };
+        let terrain = cells.iter().map(|&t| Terrain::ALL.get(t as usize).copied()
+            .ok_or_else(|| SnapshotError::Invalid(format!("terrain {}", t)))).collect::<Result<Vec<_>, _>>()?;
+        let rivers = if snap.has(RIVERS) { snap.section(RIVERS)?.runs(cells.len())? } else { vec![0; cells.len()] };
+        let rivers = rivers.chunks(geo.cols).map(|row| row.iter().map(|&r| r != 0).collect()).collect();
+        let rng = read_rng(&mut snap.section(RNG)?)?;
+        let mut r = if snap.version < 2 && !snap.has(RULE) { Reader::new(&[0]) } else { snap.section(RULE)? };
+        let conflict = match r.u8()? {
+            0 => Conflict::Weighted,
+            1 => Conflict::Strongest,
+            2 => Conflict::Oldest,
+            3 => Conflict::KeepOnTie,
+            c => return Err(SnapshotError::Invalid(format!("conflict policy {}", c))),
+        };
+        let mut sim = EmpireSimulation::new(geo, terrain.chunks(geo.cols).map(|row| row.to_vec()).collect(), rng)
+            .with_rivers(rivers).with_conflict(conflict);
+        if !r.is_empty() && r.u8()? == 1 {
+            sim = sim.with_warfare(Warfare { rate: r.f64()?, sharpness: r.f64()? });
+            if snap.version < 3 {
+                sim = sim.with_control(Control { reach: Reach::Hex, range: read_range(&mut r)? });
+            }
+        }
+        if snap.has(CONTROL) {
+            let mut r = snap.section(CONTROL)?;
+            let reach = match r.u8()? {
+                0 => Reach::Hex,
+                1 => Reach::Path,
+                c => return Err(SnapshotError::Invalid(format!("control reach {}", c))),
+            };
+            sim = sim.with_control(Control { reach, range: read_range(&mut r)? });
+        }
+        if snap.has(REBELLION) {
+            let mut r = snap.section(REBELLION)?;
+            sim = sim.with_rebellion(Rebellion { split_size: r.varint()? as usize, rate: r.f64()?, threshold: r.f64()? });
+        }
+        if snap.has(DIPLOMACY) {
+            let mut r = snap.section(DIPLOMACY)?;
+            sim = sim.with_diplomacy(Diplomacy { rate: r.f64()?, war_ratio: r.f64()?, vassal_ratio: r.f64()? });
+        }
+        sim.generation = snap.section(GENERATION)?.u64()?;
+        let mut r = snap.section(EMPIRES)?;
+        let mut attributes = if snap.has(ATTRIBUTES) { Some(snap.section(ATTRIBUTES)?) } else { None };
+        let mut capitals = if snap.has(CAPITALS) { Some(snap.section(CAPITALS)?) } else { None };
+        for _ in 0..r.varint()? {
+            let id = if first_layout {
+                r.u8()?.checked_sub(2).ok_or_else(|| SnapshotError::Invalid("empire id out of range".to_string()))? as u32
+            } else {
+                u32::try_from(r.varint()?).map_err(|_| SnapshotError::Invalid("empire id out of range".to_string()))?
+            };
+            let name = r.str()?;
+            let founded = if snap.version < 2 { 0 } else { r.varint()? };
+            let attributes = match attributes.as_mut() {
+                Some(a) => Attributes { population: a.f64()?, strength: a.f64()?, expansion: a.f64()?, aggression: a.f64()? },
+                None => Attributes::default(),
+            };
+            let capital = match capitals.as_mut() {
+                Some(c) => (c.varint()? as usize, c.varint()? as usize),
+                None => owners.iter().position(|&o| o == id + 1).map_or((0, 0), |i| (i % geo.cols, i / geo.cols)),
+            };
+            if capital.0 >= geo.cols || capital.1 >= geo.rows {
+                return Err(SnapshotError::Invalid(format!("capital {},{} outside of the map", capital.0, capital.1)));
+            }
+            // Never more empires than cells
+            if id as usize >= geo.cols * geo.rows {
+                return Err(SnapshotError::Invalid(format!("empire id {}", id)));
+            }
+            sim.empires.restore(Empire { id, name, founded, capital, attributes });
+        }
+        for (row, saved) in sim.owners.iter_mut().zip(owners.chunks(geo.cols)) {
+            for (owner, &o) in row.iter_mut().zip(saved) {
+                *owner = o.checked_sub(1);
+                if owner.is_some_and(|id| sim.empires.get(id).is_none()) {
+                    return Err(SnapshotError::Invalid(format!("cell owned by the unknown empire {}", o - 1)));
+                }
+            }
+        }
+        if snap.has(RELATIONS) {
+            let mut r = snap.section(RELATIONS)?;
+            for _ in 0..r.varint()? {
+                let (a, b) = (r.varint()?, r.varint()?);
+                let relation = match r.u8()? {
+                    0 => Relation::War,
+                    1 => Relation::Peace,
+                    2 => Relation::Alliance,
+                    3 => Relation::Vassal,
+                    4 => Relation::Overlord,
+                    v => return Err(SnapshotError::Invalid(format!("relation {}", v))),
+                };
+                let known = |id: u64| u32::try_from(id).ok().filter(|&id| sim.empires.get(id).is_some());
+                let (Some(a), Some(b)) = (known(a), known(b)) else {
+                    return Err(SnapshotError::Invalid(format!("relation between the unknown empires {} and {}", a, b)));
+                };
+                sim.relations.set(a, b, relation);
+            }
+        }
+        Ok(sim)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng;
+    use crate::geometry::Geometry;
+    use crate::rng;
+
+    fn soup(geo: &RectGeometry) -> ConwaySimulation<'_, RectGeometry> {
+        let mut sim = ConwaySimulation::new(geo, &[3, 6], &[2,3])
+            .with_probabilities(0.8, 0.9, rng::seeded(3));
+        let mut rng = rng::seeded(4);
+        for i in 0..geo.size() {
+            sim.get_mut(i).unwrap().val = rng.random_bool(0.3) as u8;
+        }
+        sim
+    }
+
+    fn vals(sim: &ConwaySimulation<RectGeometry>) -> Vec<u8> {
+        sim.states.iter().map(|s| s.val).collect()
+    }
+
+    #[test]
+    fn test_conway_round_trip_continues_identically() {
+        let geo = RectGeometry::new(Vector2::new(1.0, -2.0), 24, 16, Vector2::new(3.0, 4.0));
+        let mut sim = soup(&geo);
+        for _ in 0..5 {
+            sim.step();
+        }
+        let bytes = sim.snapshot().encode();
+        let snap = Snapshot::decode(&bytes).unwrap();
+        let geo2 = ConwaySimulation::geometry(&snap).unwrap();
+        assert_eq!((geo2.geocenter, geo2.cells.x, geo2.cells.y, geo2.celsize), (geo.geocenter, 24, 16, geo.celsize));
+        let mut loaded = ConwaySimulation::from_snapshot(&geo2, &snap).unwrap();
+        assert_eq!(loaded.generation(), 5);
+        assert_eq!(vals(&loaded), vals(&sim));
+        // Same rule, probabilities and rng position: the future is the same
+        for _ in 0..5 {
+            sim.step();
+            loaded.step();
+        }
+        assert_eq!(vals(&loaded), vals(&sim));
+    }
+
+    #[test]
+    fn test_hex_round_trip_is_compact() {
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 100, 80, 2.0);
+        let mut sim = HexConwaySimulation::new(&geo, &[2], &[3,4]);
+        sim.states[40][50] = 1;
+        sim.states[40][51] = 1;
+        let bytes = sim.snapshot().encode();
+        assert!(bytes.len() < 200, "{} bytes", bytes.len());
+        let snap = Snapshot::decode(&bytes).unwrap();
+        let geo2 = HexConwaySimulation::geometry(&snap).unwrap();
+        assert_eq!(geo2, geo);
+        let loaded = HexConwaySimulation::from_snapshot(&geo2, &snap).unwrap();
+        assert_eq!(loaded.states, sim.states);
+        // A hex snapshot is not a rect one
+        assert!(matches!(ConwaySimulation::geometry(&snap), Err(SnapshotError::WrongModel { .. })));
+    }
+
+    #[test]
+    fn test_empire_round_trip() {
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 20, 10, 1.0);
+        let war = Warfare { rate: 0.3, ..Default::default() };
+        let control = Control { reach: Reach::Path, range: 7.0 };
+        let mut terrain = vec![vec![Terrain::Earth; 20]; 10];
+        terrain[0][..7].copy_from_slice(&Terrain::ALL);
+        let mut rivers = vec![vec![false; 20]; 10];
+        rivers[0][5] = true;
+        let mut sim = EmpireSimulation::new(&geo, terrain, rng::seeded(5)).with_rivers(rivers)
+            .with_conflict(Conflict::Oldest).with_warfare(war).with_control(control).with_rebellion(Rebellion::default());
+        sim.found_empire(3, 3, Some("Rome".to_string()), Attributes::founding(0.5, 0.3));
+        sim.interact_new_random_empire();
+        sim.step();
+        sim.set_relation(1, 0, Relation::Vassal);
+        let snap = Snapshot::decode(&sim.snapshot().encode()).unwrap();
+        let mut loaded = EmpireSimulation::from_snapshot(&geo, &snap).unwrap();
+        assert_eq!((loaded.generation(), loaded.conflict(), loaded.warfare(), loaded.control()), (1, Conflict::Oldest, Some(war), control));
+        assert_eq!((loaded.rebellion(), loaded.diplomacy()), (Some(Rebellion::default()), None));
+        assert_eq!(loaded.relations(), sim.relations());
+        assert_eq!(loaded.relation(0, 1), Relation::Overlord);
+        assert_eq!(loaded.empires().collect::<Vec<_>>(), sim.empires().collect::<Vec<_>>());
+        assert_eq!((&loaded.terrain, &loaded.rivers), (&sim.terrain, &sim.rivers));
+        // The biomes need a version 6 reader, the rivers are optional
+        let bytes = sim.snapshot().encode();
+        assert_eq!(&bytes[6..8], &[6, 0]);
+        let mut no_rivers = Snapshot::decode(&bytes).unwrap();
+        no_rivers.sections.retain(|(t, _)| *t != RIVERS);
+        let no_rivers = EmpireSimulation::from_snapshot(&geo, &no_rivers).unwrap();
+        assert_eq!(&no_rivers.terrain, &sim.terrain);
+        assert!(no_rivers.rivers.iter().flatten().all(|r| !r));
+        // Same rng position: the next random empire lands on the same cell
+        sim.interact_new_random_empire();
+        loaded.interact_new_random_empire();
+        assert_eq!(loaded.owners, sim.owners);
+    }
+
+    // Version 1 file written by hand with the given extra sections
+    fn version_1(geo: &HexGeometry, cells: &[u8], sections: Vec<([u8; 4], Writer)>) -> Snapshot {
+        let mut snap = Snapshot::new(EmpireSimulation::MODEL);
+        snap.add(GEOMETRY, write_hex_geometry(geo));
+        snap.add(GENERATION, write_generation(4));
+        snap.add(RNG, write_rng(&rng::seeded(9)));
+        let mut w = Writer::new();
+        w.runs(cells);
+        snap.add(CELLS, w);
+        for (tag, w) in sections {
+            snap.add(tag, w);
+        }
+        let mut bytes = snap.encode();
+        bytes[4..8].copy_from_slice(&[1, 0, 1, 0]);
+        Snapshot::decode(&bytes).unwrap()
+    }
+
+    #[test]
+    fn test_reads_version_1() {
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 4, 2, 1.0);
+        // First layout: empire 0 owns a cell, empire 1 two, the ids are the cell states
+        let mut empires = Writer::new();
+        empires.varint(2).u8(2).str("Rome").u8(3).str("Carthage");
+        let snap = version_1(&geo, &[0, 1, 2, 3, 3, 1, 1, 0], vec![(EMPIRES, empires)]);
+        assert_eq!(snap.version, 1);
+        let sim = EmpireSimulation::from_snapshot(&geo, &snap).unwrap();
+        assert_eq!(sim.generation(), 4);
+        assert_eq!(sim.terrain, vec![vec![Terrain::Sea, Terrain::Earth, Terrain::Earth, Terrain::Earth], vec![Terrain::Earth, Terrain::Earth, Terrain::Earth, Terrain::Sea]]);
+        assert_eq!(sim.owners, vec![vec![None, None, Some(0), Some(1)], vec![Some(1), None, None, None]]);
+        assert_eq!(sim.empire(1).map(|e| (e.name.as_str(), e.founded, e.capital)), Some(("Carthage", 0, (3, 0))));
+        assert_eq!(sim.conflict(), Conflict::default());
+
+        // Then OWNERS and varint ids, still without the foundation generation
+        let mut owners = Writer::new();
+        owners.wide_runs(&[0, 0, 6, 0, 0, 0, 0, 0]);
+        let mut empires = Writer::new();
+        empires.varint(1).varint(5).str("Rome");
+        let snap = version_1(&geo, &[0, 1, 1, 1, 1, 1, 1, 0], vec![(OWNERS, owners), (EMPIRES, empires)]);
+        let sim = EmpireSimulation::from_snapshot(&geo, &snap).unwrap();
+        assert_eq!(sim.owners[0][2], Some(5));
+        assert_eq!(sim.empire(5).map(|e| (e.name.as_str(), e.founded)), Some(("Rome", 0)));
+
+        // Saved again, readable by version 2 as it uses nothing newer
+        assert_eq!(&sim.snapshot().encode()[4..8], &[VERSION as u8, 0, 2, 0]);
+
+        // An owned cell of the first layout with no such empire
+        let mut empires = Writer::new();
+        empires.varint(0);
+        let snap = version_1(&geo, &[2; 8], vec![(EMPIRES, empires)]);
+        assert!(matches!(EmpireSimulation::from_snapshot(&geo, &snap), Err(SnapshotError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_reads_version_2_warfare() {
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 4, 2, 1.0);
+        let mut sim = EmpireSimulation::new(&geo, vec![vec![Terrain::Earth; 4]; 2], rng::seeded(1));
+        sim.found_empire(1, 1, None, Attributes::default());
+        let mut snap = sim.snapshot();
+        snap.sections.retain(|(t, _)| *t != CONTROL);
+        // Conflict, then warfare with rate, sharpness and supply range
+        let mut rule = Writer::new();
+        rule.u8(0).u8(1).f64(0.3).f64(1.5).f64(12.0);
+        snap.add(RULE, rule);
+        let mut bytes = snap.encode();
+        bytes[4..8].copy_from_slice(&[2, 0, 2, 0]);
+        let loaded = EmpireSimulation::from_snapshot(&geo, &Snapshot::decode(&bytes).unwrap()).unwrap();
+        assert_eq!(loaded.warfare(), Some(Warfare { rate: 0.3, sharpness: 1.5 }));
+        assert_eq!(loaded.control(), Control { reach: Reach::Hex, range: 12.0 });
+        assert_eq!(loaded.empires().collect::<Vec<_>>(), sim.empires().collect::<Vec<_>>());
+
+        // No control at all is not a valid range
+        let mut snap = sim.snapshot();
+        let mut control = Writer::new();
+        control.u8(0).f64(0.0);
+        snap.add(CONTROL, control);
+        assert!(matches!(EmpireSimulation::from_snapshot(&geo, &snap), Err(SnapshotError::Invalid(_))));
+    }
+
+    #[test]
+    fn test_version_checks() {
+        let geo = RectGeometry::new(Vector2::new(0.0, 0.0), 8, 8, Vector2::new(1.0, 1.0));
+        let sim = soup(&geo);
+        let mut snap = sim.snapshot();
+        // The Conway layouts never changed, the empires need the newest things they use
+        assert_eq!(&snap.encode()[4..8], &[VERSION as u8, 0, 1, 0]);
+        let hex = HexGeometry::new(Vector2::new(0.0, 0.0), 6, 4, 1.0);
+        assert_eq!(HexConwaySimulation::new(&hex, &[2], &[3, 4]).snapshot().min_reader_version, 1);
+        let empires = |sim: EmpireSimulation| Snapshot::decode(&sim.snapshot().encode()).unwrap().min_reader_version;
+        let plain = || EmpireSimulation::new(&hex, vec![vec![Terrain::Earth; 6]; 4], rng::seeded(1));
+        assert_eq!(empires(plain()), 2);
+        assert_eq!(empires(plain().with_warfare(Warfare::default())), 3);
+        assert_eq!(empires(plain().with_rebellion(Rebellion::default())), 4);
+        assert_eq!(empires(plain().with_rebellion(Rebellion::default()).with_diplomacy(Diplomacy::default())), 5);
+        assert_eq!(empires(EmpireSimulation::new(&hex, vec![vec![Terrain::Forest; 6]; 4], rng::seeded(1))), 6);
+        // A newer writer adding a section older readers do not know about is still readable
+        let mut extra = Writer::new();
+        extra.str("from the future");
+        snap.add(*b"XTRA", extra);
+        let mut bytes = snap.encode();
+        bytes[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        let decoded = Snapshot::decode(&bytes).unwrap();
+        assert_eq!(decoded.version, VERSION + 1);
+        assert!(ConwaySimulation::from_snapshot(&geo, &decoded).is_ok());
+        // But not one declaring that it needs a newer reader
+        bytes[6..8].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert!(matches!(Snapshot::decode(&bytes), Err(SnapshotError::TooNew { .. })));
+    }
+
+    #[test]
+    fn test_corrupted_data() {
+        let geo = RectGeometry::new(Vector2::new(0.0, 0.0), 8, 8, Vector2::new(1.0, 1.0));
+        let bytes = soup(&geo).snapshot().encode();
+        assert!(matches!(Snapshot::decode(b"PNG\0...."), Err(SnapshotError::BadMagic)));
+        for len in [3, 10, bytes.len() / 2, bytes.len() - 1] {
+            assert!(matches!(Snapshot::decode(&bytes[..len]), Err(SnapshotError::Truncated | SnapshotError::BadMagic)), "{}", len);
+        }
+        let mut snap = Snapshot::decode(&bytes).unwrap();
+        let mut w = Writer::new();
+        w.runs(&[0; 10]);
+        snap.add(CELLS, w);
+        assert!(matches!(ConwaySimulation::from_snapshot(&geo, &snap), Err(SnapshotError::Invalid(_))));
+        // A length far beyond the geometry is rejected before it is allocated
+        let mut w = Writer::new();
+        w.varint(u64::MAX).varint(u64::MAX).u8(1);
+        snap.add(CELLS, w);
+        assert!(matches!(ConwaySimulation::from_snapshot(&geo, &snap), Err(SnapshotError::Invalid(_))));
+        let mut w = Writer::new();
+        w.varint(1 << 40).varint(1 << 40).u8(1);
+        assert!(matches!(Reader::new(&w.into_bytes()).wide_runs(64), Err(SnapshotError::Invalid(_))));
+        snap.sections.retain(|(t, _)| *t != RNG);
+        assert!(matches!(ConwaySimulation::from_snapshot(&geo, &snap), Err(SnapshotError::MissingSection(RNG))));
+    }
+
+    #[test]
+    fn test_corrupted_empire_never_panics() {
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 12, 8, 1.0);
+        let mut sim = EmpireSimulation::new(&geo, vec![vec![Terrain::Plains; 12]; 8], rng::seeded(6))
+            .with_warfare(Warfare::default()).with_rebellion(Rebellion::default()).with_diplomacy(Diplomacy::default());
+        sim.found_empire(2, 2, None, Attributes::founding(0.5, 0.5));
+        sim.found_empire(9, 5, None, Attributes::founding(0.3, 0.9));
+        sim.step();
+        let bytes = sim.snapshot().encode();
+        // Every byte set to a few values: decoding fails or gives a simulation that steps
+        for i in 0..bytes.len() {
+            for v in [0, 1, 0x7f, 0x80, 0xff] {
+                let mut bad = bytes.clone();
+                bad[i] = v;
+                let Ok(snap) = Snapshot::decode(&bad) else { continue };
+                let Ok(geo) = EmpireSimulation::geometry(&snap) else { continue };
+                if let Ok(mut loaded) = EmpireSimulation::from_snapshot(&geo, &snap) {
+                    loaded.step();
+                    loaded.step();
+                }
+            }
+        }
+        let mut snap = Snapshot::decode(&bytes).unwrap();
+        let mut w = Writer::new();
+        w.runs(&[255; 96]);
+        snap.add(CELLS, w);
+        assert!(matches!(EmpireSimulation::from_snapshot(&geo, &snap), Err(SnapshotError::Invalid(_))));
+        // Geometries without cells, or too large, or with cells of no size
+        for (cols, rows, size) in [(0, 8, 1.0), (12, 0, 1.0), (1 << 16, 1 << 16, 1.0), (12, 8, 0.0), (12, 8, f32::NAN)] {
+            let mut snap = Snapshot::decode(&bytes).unwrap();
+            snap.add(GEOMETRY, write_hex_geometry(&HexGeometry::new(Vector2::new(0.0, 0.0), cols, rows, size)));
+            assert!(matches!(EmpireSimulation::geometry(&snap), Err(SnapshotError::Invalid(_))), "{}x{} {}", cols, rows, size);
+        }
+    }
+}
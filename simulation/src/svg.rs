@@ -0,0 +1,327 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+use std::ops::RangeInclusive;
+use crate::hex_geom::{HexGeometry, OffsetCoord};
+use crate::raster::{Palette, Rgba};
+use crate::rect_geom::RectGeometry;
+use crate::render::{CellView, Layout};
+
+// Vector export of the maps, in the world coordinates of the geometry.
+// Every cell is a polygon, grouped by state so each state is a layer in a vector editor,
+// and the region of every outlined state (an empire) is a single closed path.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SvgStyle {
+    pub palette: Palette,
+    // Outline of every cell
+    pub grid: Option<Rgba>,
+    pub grid_width: f32,
+    // Merged border around the regions of the states in `outlined`
+    pub border: Option<Rgba>,
+    pub border_width: f32,
+    pub outlined: RangeInclusive<u32>,
+    pub font_size: f32,
+    pub label_color: Rgba,
+}
+
+impl Default for SvgStyle {
+    fn default() -> Self {
+        Self {
+            palette: Palette::life(),
+            grid: None,
+            grid_width: 0.02,
+            border: Some(Rgba::BLACK),
+            border_width: 0.1,
+            outlined: 1..=u32::MAX,
+            font_size: 1.0,
+            label_color: Rgba::BLACK,
+        }
+    }
+}
+
+// The corners of the cells are on an integer lattice, so the shared corners of two cells are equal
+// and the border edges can be chained. Sides go clockwise on screen, with the neighbour across them.
+trait Tiling {
+    fn dims(&self) -> (usize, usize);
+    fn corners(&self, x: usize, y: usize) -> Vec<(i64, i64)>;
+    // Neighbour across the side from corner i to corner i+1
+    fn across(&self, x: usize, y: usize, side: usize) -> Option<(usize, usize)>;
+    fn world(&self, corner: (i64, i64)) -> (f32, f32);
+    fn bounds(&self) -> (f32, f32, f32, f32);
+}
+
+// x in half hex widths, y in half sizes
+impl Tiling for HexGeometry {
+    fn dims(&self) -> (usize, usize) {
+        (self.cols, self.rows)
+    }
+
+    fn corners(&self, x: usize, y: usize) -> Vec<(i64, i64)> {
+        let (cx, cy) = (2 * x as i64 + 1 + (y & 1) as i64, 2 + 3 * y as i64);
+        [(1, 1), (0, 2), (-1, 1), (-1, -1), (0, -2), (1, -1)].iter().map(|(dx, dy)| (cx + dx, cy + dy)).collect()
+    }
+
+    fn across(&self, x: usize, y: usize, side: usize) -> Option<(usize, usize)> {
+        const SIDES: [(isize, isize); 6] = [(0, 1), (-1, 1), (-1, 0), (0, -1), (1, -1), (1, 0)];
+        let a = OffsetCoord{x: x as isize, y: y as isize}.axial();
+        let (dq, dr) = SIDES[side];
+        let o = crate::hex_geom::AxialCoord{q: a.q + dq, r: a.r + dr}.offset();
+        (0 <= o.x && o.x < self.cols as isize && 0 <= o.y && o.y < self.rows as isize).then_some((o.x as usize, o.y as usize))
+    }
+
+    fn world(&self, (x, y): (i64, i64)) -> (f32, f32) {
+        (self.origin.x + x as f32 * 0.5 * self.hex_width(), self.origin.y + y as f32 * 0.5 * self.size)
+    }
+
+    fn bounds(&self) -> (f32, f32, f32, f32) {
+        let r = self.rect();
+        (r.x, r.y, r.width, r.height)
+    }
+}
+
+impl Tiling for RectGeometry {
+    fn dims(&self) -> (usize, usize) {
+        (self.cells.x, self.cells.y)
+    }
+
+    fn corners(&self, x: usize, y: usize) -> Vec<(i64, i64)> {
+        let (x, y) = (x as i64, y as i64);
+        vec![(x, y), (x + 1, y), (x + 1, y + 1), (x, y + 1)]
+    }
+
+    fn across(&self, x: usize, y: usize, side: usize) -> Option<(usize, usize)> {
+        const SIDES: [(isize, isize); 4] = [(0, -1), (1, 0), (0, 1), (-1, 0)];
+        let (nx, ny) = (x as isize + SIDES[side].0, y as isize + SIDES[side].1);
+        (0 <= nx && nx < self.cells.x as isize && 0 <= ny && ny < self.cells.y as isize).then_some((nx as usize, ny as usize))
+    }
+
+    fn world(&self, (x, y): (i64, i64)) -> (f32, f32) {
+        let start = self.start();
+        (start.x + x as f32 * self.celsize.x, start.y + y as f32 * self.celsize.y)
+    }
+
+    fn bounds(&self) -> (f32, f32, f32, f32) {
+        let start = self.start();
+        (start.x, start.y, self.cells.x as f32 * self.celsize.x, self.cells.y as f32 * self.celsize.y)
+    }
+}
+
+// Labels are (state, text), placed on the cell of the state closest to the middle of its region
+pub fn hex_svg(geo: &HexGeometry, cell: impl Fn(usize, usize) -> u32, labels: &[(u32, String)], style: &SvgStyle) -> String {
+    svg(geo, cell, labels, style)
+}
+
+pub fn rect_svg(geo: &RectGeometry, cell: impl Fn(usize, usize) -> u32, labels: &[(u32, String)], style: &SvgStyle) -> String {
+    svg(geo, cell, labels, style)
+}
+
+pub fn view_svg(view: &(impl CellView + ?Sized), labels: &[(u32, String)], style: &SvgStyle) -> String {
+    match view.layout() {
+        Layout::Rect(geo) => rect_svg(geo, |x, y| view.cell(x, y), labels, style),
+        Layout::Hex(geo) => hex_svg(geo, |x, y| view.cell(x, y), labels, style),
+    }
+}
+
+fn svg(tiling: &impl Tiling, cell: impl Fn(usize, usize) -> u32, labels: &[(u32, String)], style: &SvgStyle) -> String {
+    let (cols, rows) = tiling.dims();
+    let (bx, by, bw, bh) = tiling.bounds();
+    let mut out = String::new();
+    writeln!(out, r#"<?xml version="1.0" encoding="UTF-8"?>"#).unwrap();
+    writeln!(out, r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} {} {} {}" width="{}" height="{}">"#,
+        num(bx), num(by), num(bw), num(bh), num(bw), num(bh)).unwrap();
+
+    // Cells, one group per state
+    let mut states: BTreeMap<u32, Vec<(usize, usize)>> = BTreeMap::new();
+    for y in 0..rows {
+        for x in 0..cols {
+            states.entry(cell(x, y)).or_default().push((x, y));
+        }
+    }
+    for (&state, cells) in states.iter() {
+        let color = style.palette.get(state);
+        if color.0[3] == 0 {
+            continue;
+        }
+        write!(out, r#"<g id="state-{}" {}"#, state, paint("fill", color)).unwrap();
+        if let Some(g) = style.grid {
+            write!(out, r#" {} stroke-width="{}""#, paint("stroke", g), num(style.grid_width)).unwrap();
+        }
+        writeln!(out, ">").unwrap();
+        for &(x, y) in cells {
+            let points: Vec<String> = tiling.corners(x, y).into_iter().map(|c| {
+                let (wx, wy) = tiling.world(c);
+                format!("{},{}", num(wx), num(wy))
+            }).collect();
+            writeln!(out, r#"<polygon points="{}"/>"#, points.join(" ")).unwrap();
+        }
+        writeln!(out, "</g>").unwrap();
+    }
+
+    // Borders
+    if let Some(b) = style.border {
+        writeln!(out, r#"<g fill="none" {} stroke-width="{}" stroke-linejoin="round">"#, paint("stroke", b), num(style.border_width)).unwrap();
+        for (&state, cells) in states.range(style.outlined.clone()) {
+            let mut d = String::new();
+            for ring in outline(tiling, &cell, state, cells) {
+                for (i, c) in ring.into_iter().enumerate() {
+                    let (wx, wy) = tiling.world(c);
+                    write!(d, "{}{} {} ", if i == 0 { "M" } else { "L" }, num(wx), num(wy)).unwrap();
+                }
+                d.push('Z');
+            }
+            writeln!(out, r#"<path id="border-{}" d="{}"/>"#, state, d).unwrap();
+        }
+        writeln!(out, "</g>").unwrap();
+    }
+
+    // Labels
+    if !labels.is_empty() {
+        writeln!(out, r#"<g font-family="sans-serif" font-size="{}" text-anchor="middle" dominant-baseline="central" {}>"#,
+            num(style.font_size), paint("fill", style.label_color)).unwrap();
+        for (state, text) in labels {
+            let Some(cells) = states.get(state) else { continue };
+            let centre = |&(x, y): &(usize, usize)| {
+                let corners = tiling.corners(x, y);
+                let n = corners.len() as f32;
+                corners.into_iter().map(|c| tiling.world(c)).fold((0.0, 0.0), |(sx, sy), (cx, cy)| (sx + cx / n, sy + cy / n))
+            };
+            let n = cells.len() as f32;
+            let (mx, my) = cells.iter().map(centre).fold((0.0, 0.0), |(sx, sy), (cx, cy)| (sx + cx / n, sy + cy / n));
+            // The middle of a curved region may be outside of it
+            let (lx, ly) = cells.iter().map(centre)
+                .min_by(|a, b| ((a.0 - mx).powi(2) + (a.1 - my).powi(2)).total_cmp(&((b.0 - mx).powi(2) + (b.1 - my).powi(2))))
+                .unwrap();
+            writeln!(out, r#"<text x="{}" y="{}">{}</text>"#, num(lx), num(ly), escape(text)).unwrap();
+        }
+        writeln!(out, "</g>").unwrap();
+    }
+    writeln!(out, "</svg>").unwrap();
+    out
+}
+
+// Closed rings of corners around the cells of a state, holes included
+fn outline(tiling: &impl Tiling, cell: &impl Fn(usize, usize) -> u32, state: u32, cells: &[(usize, usize)]) -> Vec<Vec<(i64, i64)>> {
+    let mut edges: HashMap<(i64, i64), Vec<(i64, i64)>> = HashMap::new();
+    for &(x, y) in cells {
+        let corners = tiling.corners(x, y);
+        for side in 0..corners.len() {
+            let inside = tiling.across(x, y, side).is_some_and(|(nx, ny)| cell(nx, ny) == state);
+            if !inside {
+                edges.entry(corners[side]).or_default().push(corners[(side + 1) % corners.len()]);
+            }
+        }
+    }
+    let mut starts: Vec<(i64, i64)> = edges.keys().copied().collect();
+    starts.sort();
+    let mut rings = Vec::new();
+    for start in starts {
+        while edges.get(&start).is_some_and(|e| !e.is_empty()) {
+            let mut ring = vec![start];
+            let mut at = start;
+            loop {
+                let next = edges.get_mut(&at).and_then(|e| e.pop()).expect("Border edges always form rings");
+                if next == start {
+                    break;
+                }
+                ring.push(next);
+                at = next;
+            }
+            rings.push(simplify(ring));
+        }
+    }
+    rings
+}
+
+// Drop the corners in the middle of a straight line
+fn simplify(ring: Vec<(i64, i64)>) -> Vec<(i64, i64)> {
+    let n = ring.len();
+    (0..n).filter(|&i| {
+        let (a, b, c) = (ring[(i + n - 1) % n], ring[i], ring[(i + 1) % n]);
+        (b.0 - a.0) * (c.1 - b.1) != (b.1 - a.1) * (c.0 - b.0)
+    }).map(|i| ring[i]).collect()
+}
+
+fn paint(attr: &str, c: Rgba) -> String {
+    let [r, g, b, a] = c.0;
+    if a == 255 {
+        format!(r##"{}="#{:02x}{:02x}{:02x}""##, attr, r, g, b)
+    } else {
+        format!(r##"{}="#{:02x}{:02x}{:02x}" {}-opacity="{}""##, attr, r, g, b, attr, num(a as f32 / 255.0))
+    }
+}
+
+// Short numbers, the maps have thousands of points
+fn num(v: f32) -> String {
+    let s = format!("{:.3}", v);
+    let s = s.trim_end_matches('0').trim_end_matches('.');
+    if s == "-0" { "0".to_string() } else { s.to_string() }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+pub trait ToSvg {
+    fn to_svg(&self, style: &SvgStyle) -> String;
+}
+
+impl<T: CellView + ?Sized> ToSvg for T {
+    fn to_svg(&self, style: &SvgStyle) -> String {
+        view_svg(self, &[], style)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::empire::owner_state;
+    use crate::conway_sim::ConwaySimulation;
+    use crate::pattern::{catalogue, rle, Transform};
+    use crate::math::Vector2;
+
+    fn paths(svg: &str) -> Vec<&str> {
+        svg.lines().filter(|l| l.starts_with("<path")).collect()
+    }
+
+    #[test]
+    fn test_rect_block() {
+        let geo = RectGeometry::new(Vector2::new(0.0, 0.0), 6, 4, Vector2::new(2.0, 2.0));
+        let mut sim = ConwaySimulation::new(&geo, &[3], &[2,3]);
+        rle::parse(catalogue::BLOCK).unwrap().stamp(&mut sim, 1, 1, Transform::default());
+        let svg = sim.to_svg(&SvgStyle::default());
+        assert!(svg.contains(r#"viewBox="-6 -4 12 8""#));
+        assert_eq!(svg.matches("<polygon").count(), 24);
+        assert!(svg.contains(r##"<g id="state-1" fill="#000000">"##));
+        // The 4 cells of the block merged into a square
+        assert_eq!(paths(&svg), [r#"<path id="border-1" d="M-4 -2 L0 -2 L0 2 L-4 2 Z"/>"#]);
+    }
+
+    #[test]
+    fn test_hex_empires() {
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 6, 5, 1.0);
+        let mut states = vec![vec![1_u32; 6]; 5];
+        // Empire 0 is two neighbour hexes, empire 1 a ring with earth in the middle
+        let (first, second) = (owner_state(0), owner_state(1));
+        states[0][0] = first;
+        states[0][1] = first;
+        let centre = OffsetCoord{x: 3, y: 2}.axial();
+        for n in geo.neighbours(centre) {
+            let o = n.offset();
+            states[o.y as usize][o.x as usize] = second;
+        }
+        let labels = vec![(first, "Rome & Co".to_string()), (owner_state(2), "Nobody".to_string())];
+        let style = SvgStyle { palette: Palette::empire(), outlined: first..=255, ..Default::default() };
+        let svg = hex_svg(&geo, |x, y| states[y][x], &labels, &style);
+        assert_eq!(svg.matches("<polygon").count(), 30);
+        let paths = paths(&svg);
+        assert_eq!(paths.len(), 2);
+        assert!(paths[0].starts_with(&format!(r#"<path id="border-{}""#, first)));
+        assert_eq!(paths[0].matches('L').count(), 9);
+        assert_eq!(paths[0].matches('Z').count(), 1);
+        // Outer ring and hole
+        assert_eq!(paths[1].matches('M').count(), 2);
+        assert_eq!(paths[1].matches('L').count(), 17 + 5);
+        assert!(svg.contains(">Rome &amp; Co</text>"));
+        assert!(!svg.contains("Nobody"));
+    }
+}
@@ -0,0 +1,300 @@
+use std::fmt::Write;
+use crate::raster::{Palette, Rgba};
+use crate::render::{CellView, Layout};
+
+// Text rendering of the simulations for a terminal, to watch a run over SSH without raylib.
+// Rect grids use half blocks, two cells per character, hex grids two characters per cell
+// with the odd rows shifted by one. Every frame starts by moving the cursor home.
+
+pub const CLEAR: &str = "\x1B[2J";
+pub const HOME: &str = "\x1B[H";
+pub const RESET: &str = "\x1B[0m";
+pub const HIDE_CURSOR: &str = "\x1B[?25l";
+pub const SHOW_CURSOR: &str = "\x1B[?25h";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorMode {
+    // Blank for state 0, filled otherwise
+    Mono,
+    // 24-bit colours from the palette
+    TrueColor,
+}
+
+impl ColorMode {
+    // TrueColor if the terminal says it supports it
+    pub fn detect() -> Self {
+        match std::env::var("COLORTERM").as_deref() {
+            Ok("truecolor") | Ok("24bit") => ColorMode::TrueColor,
+            _ => ColorMode::Mono,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TermStyle {
+    pub palette: Palette,
+    pub color: ColorMode,
+}
+
+impl Default for TermStyle {
+    fn default() -> Self {
+        Self { palette: Palette::life(), color: ColorMode::detect() }
+    }
+}
+
+// The cells shown, in cells of the map
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Viewport {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Viewport {
+    pub fn new(x: usize, y: usize, width: usize, height: usize) -> Self {
+        Self { x, y, width, height }
+    }
+
+    // Largest viewport fitting a terminal of the given size, for a rect and for a hex grid
+    pub fn rect_for_terminal(columns: usize, lines: usize) -> Self {
+        Self::new(0, 0, columns, 2 * lines)
+    }
+
+    pub fn hex_for_terminal(columns: usize, lines: usize) -> Self {
+        Self::new(0, 0, columns.saturating_sub(1) / 2, lines)
+    }
+
+    // Move as little as possible to show the region (x0, y0, x1, y1 inclusive), centre on it if
+    // it is too big. The viewport never leaves a map of cols x rows cells.
+    pub fn follow(&mut self, region: (usize, usize, usize, usize), cols: usize, rows: usize) {
+        let (x0, y0, x1, y1) = region;
+        let axis = |start: usize, size: usize, lo: usize, hi: usize, len: usize| {
+            let start = if hi - lo + 1 > size {
+                (lo + hi + 1).saturating_sub(size) / 2
+            } else if lo < start {
+                lo
+            } else if hi >= start + size {
+                hi + 1 - size
+            } else {
+                start
+            };
+            start.min(len.saturating_sub(size))
+        };
+        self.x = axis(self.x, self.width, x0, x1, cols);
+        self.y = axis(self.y, self.height, y0, y1, rows);
+    }
+}
+
+// Cells selected by `pred`, as (x0, y0, x1, y1) inclusive
+pub fn bounding_box(cols: usize, rows: usize, cell: impl Fn(usize, usize) -> u32, pred: impl Fn(u32) -> bool) -> Option<(usize, usize, usize, usize)> {
+    let mut bb: Option<(usize, usize, usize, usize)> = None;
+    for y in 0..rows {
+        for x in 0..cols {
+            if pred(cell(x, y)) {
+                bb = Some(match bb {
+                    None => (x, y, x, y),
+                    Some((x0, y0, x1, y1)) => (x0.min(x), y0.min(y), x1.max(x), y1.max(y)),
+                });
+            }
+        }
+    }
+    bb
+}
+
+// Only emits the escape codes when the colours change
+struct Painter {
+    out: String,
+    fg: Option<Rgba>,
+    bg: Option<Rgba>,
+}
+
+impl Painter {
+    fn new() -> Self {
+        Self { out: String::from(HOME), fg: None, bg: None }
+    }
+
+    fn put(&mut self, fg: Rgba, bg: Rgba, text: &str) {
+        if self.fg != Some(fg) {
+            let [r, g, b, _] = fg.0;
+            write!(self.out, "\x1B[38;2;{};{};{}m", r, g, b).unwrap();
+            self.fg = Some(fg);
+        }
+        if self.bg != Some(bg) {
+            let [r, g, b, _] = bg.0;
+            write!(self.out, "\x1B[48;2;{};{};{}m", r, g, b).unwrap();
+            self.bg = Some(bg);
+        }
+        self.out.push_str(text);
+    }
+
+    // Back to the terminal colours, keeping the line open
+    fn end_line_colors(&mut self) {
+        if self.bg.is_some() {
+            self.out.push_str(RESET);
+            self.fg = None;
+            self.bg = None;
+        }
+    }
+
+    fn put_fg(&mut self, fg: Rgba, text: &str) {
+        if self.fg != Some(fg) {
+            let [r, g, b, _] = fg.0;
+            write!(self.out, "\x1B[38;2;{};{};{}m", r, g, b).unwrap();
+            self.fg = Some(fg);
+        }
+        self.out.push_str(text);
+    }
+
+    fn end_line(&mut self) {
+        if self.fg.is_some() || self.bg.is_some() {
+            self.out.push_str(RESET);
+            self.fg = None;
+            self.bg = None;
+        }
+        // Wipe what is left of a longer previous frame
+        self.out.push_str("\x1B[K\n");
+    }
+}
+
+pub fn rect_frame(cols: usize, rows: usize, cell: impl Fn(usize, usize) -> u32, view: &Viewport, style: &TermStyle) -> String {
+    let mut p = Painter::new();
+    let x_end = (view.x + view.width).min(cols);
+    let y_end = (view.y + view.height).min(rows);
+    for y in (view.y..y_end).step_by(2) {
+        for x in view.x..x_end {
+            let top = cell(x, y);
+            let bottom = if y + 1 < y_end { Some(cell(x, y + 1)) } else { None };
+            match style.color {
+                ColorMode::Mono => p.out.push(match (top != 0, bottom.is_some_and(|b| b != 0)) {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                }),
+                ColorMode::TrueColor => {
+                    let fg = style.palette.get(top);
+                    match bottom {
+                        Some(b) => p.put(fg, style.palette.get(b), "▀"),
+                        // Last line of an odd height, leave the terminal background below
+                        None => {
+                            p.end_line_colors();
+                            p.put_fg(fg, "▀");
+                        }
+                    }
+                }
+            }
+        }
+        p.end_line();
+    }
+    p.out
+}
+
+pub fn hex_frame(cols: usize, rows: usize, cell: impl Fn(usize, usize) -> u32, view: &Viewport, style: &TermStyle) -> String {
+    let mut p = Painter::new();
+    let x_end = (view.x + view.width).min(cols);
+    let y_end = (view.y + view.height).min(rows);
+    for y in view.y..y_end {
+        if y & 1 == 1 {
+            p.out.push(' ');
+        }
+        for x in view.x..x_end {
+            let state = cell(x, y);
+            match style.color {
+                ColorMode::Mono => p.out.push_str(if state != 0 { "● " } else { "· " }),
+                ColorMode::TrueColor => {
+                    let c = style.palette.get(state);
+                    p.put(c, c, "  ");
+                }
+            }
+        }
+        p.end_line();
+    }
+    p.out
+}
+
+pub trait TermRender {
+    // Size of the map in cells
+    fn cells(&self) -> (usize, usize);
+    fn frame(&self, view: &Viewport, style: &TermStyle) -> String;
+}
+
+impl<T: CellView + ?Sized> TermRender for T {
+    fn cells(&self) -> (usize, usize) {
+        self.layout().dims()
+    }
+
+    fn frame(&self, view: &Viewport, style: &TermStyle) -> String {
+        let (cols, rows) = self.cells();
+        match self.layout() {
+            Layout::Rect(_) => rect_frame(cols, rows, |x, y| self.cell(x, y), view, style),
+            Layout::Hex(_) => hex_frame(cols, rows, |x, y| self.cell(x, y), view, style),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conway_sim::ConwaySimulation;
+    use crate::hex_conway_sim::HexConwaySimulation;
+    use crate::rect_geom::RectGeometry;
+    use crate::hex_geom::HexGeometry;
+    use crate::pattern::{catalogue, rle, Transform};
+    use crate::math::Vector2;
+
+    fn mono() -> TermStyle {
+        TermStyle { palette: Palette::life(), color: ColorMode::Mono }
+    }
+
+    #[test]
+    fn test_rect_half_blocks() {
+        let geo = RectGeometry::new(Vector2::new(0.0, 0.0), 5, 5, Vector2::new(1.0, 1.0));
+        let mut sim = ConwaySimulation::new(&geo, &[3], &[2,3]);
+        rle::parse(catalogue::GLIDER).unwrap().stamp(&mut sim, 1, 1, Transform::default());
+        let frame = sim.frame(&Viewport::new(0, 0, 5, 5), &mono());
+        assert_eq!(frame, format!("{}  ▄  \x1B[K\n ▄▄█ \x1B[K\n     \x1B[K\n", HOME));
+        // Two cells per character, only the viewport
+        let frame = sim.frame(&Viewport::new(2, 2, 2, 2), &mono());
+        assert_eq!(frame, format!("{}▄█\x1B[K\n", HOME));
+    }
+
+    #[test]
+    fn test_true_color() {
+        let geo = RectGeometry::new(Vector2::new(0.0, 0.0), 2, 2, Vector2::new(1.0, 1.0));
+        let mut sim = ConwaySimulation::new(&geo, &[3], &[2,3]);
+        sim.get_mut(0).unwrap().val = 1;
+        let style = TermStyle { palette: Palette::life().with(1, Rgba::rgb(255, 0, 0)), color: ColorMode::TrueColor };
+        let frame = sim.frame(&Viewport::new(0, 0, 2, 2), &style);
+        assert_eq!(frame, format!("{}\x1B[38;2;255;0;0m\x1B[48;2;255;255;255m▀\x1B[38;2;255;255;255m▀{}\x1B[K\n", HOME, RESET));
+    }
+
+    #[test]
+    fn test_hex_rows() {
+        let geo = HexGeometry::new(Vector2::new(0.0, 0.0), 3, 3, 1.0);
+        let mut sim = HexConwaySimulation::new(&geo, &[2], &[3,5]);
+        sim.states[1][0] = 1;
+        sim.states[2][2] = 1;
+        let frame = sim.frame(&Viewport::hex_for_terminal(80, 24), &mono());
+        assert_eq!(frame, format!("{}· · · \x1B[K\n ● · · \x1B[K\n· · ● \x1B[K\n", HOME));
+    }
+
+    #[test]
+    fn test_follow() {
+        let mut view = Viewport::new(0, 0, 10, 6);
+        // Already visible
+        view.follow((2, 2, 5, 4), 100, 50);
+        assert_eq!((view.x, view.y), (0, 0));
+        // Just enough to show it
+        view.follow((12, 3, 14, 8), 100, 50);
+        assert_eq!((view.x, view.y), (5, 3));
+        // Centred when too big, kept inside the map
+        view.follow((80, 0, 99, 49), 100, 50);
+        assert_eq!((view.x, view.y), (85, 22));
+        view.follow((98, 48, 99, 49), 100, 50);
+        assert_eq!((view.x, view.y), (90, 44));
+        let cells = [[0, 0, 0], [0, 1, 0], [0, 0, 1]];
+        assert_eq!(bounding_box(3, 3, |x, y| cells[y][x], |s| s != 0), Some((1, 1, 2, 2)));
+        assert_eq!(bounding_box(3, 3, |_, _| 0, |s| s != 0), None);
+    }
+}